@@ -1,7 +1,9 @@
+use once_cell::sync::OnceCell;
 use std::str::FromStr;
 
 enum HashVersion {
     V1,
+    V2,
 }
 
 impl HashVersion {
@@ -9,11 +11,28 @@ impl HashVersion {
         let version: String = hash.chars().take_while(|c| c != &'$').collect();
         match version.as_ref() {
             "1" => Some(HashVersion::V1),
+            "2" => Some(HashVersion::V2),
             _ => None,
         }
     }
 }
 
+/// The result of checking a password against a hash.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PasswordCheck {
+    Incorrect,
+    Correct,
+    /// The password is correct, but the hash was generated with an older hashing algorithm or
+    /// with other parameters than are currently used. The password should be rehashed.
+    CorrectOutdated,
+}
+
+impl PasswordCheck {
+    pub fn is_correct(self) -> bool {
+        self != PasswordCheck::Incorrect
+    }
+}
+
 /// Hashing algorithm V1, using PBKDF2 with 15_000 iterations and a 128-bit salt.
 struct V1Hash {
     pub salt: [u8; 16],
@@ -23,7 +42,8 @@ struct V1Hash {
 impl V1Hash {
     const PBKDF2_ITERATIONS: u32 = 15_000;
 
-    /// Hash a user password.
+    /// Hash a user password. New hashes use V2, so this is only used in tests.
+    #[cfg(test)]
     pub fn hash_password(password: &str) -> Self {
         use rand::RngCore;
 
@@ -86,6 +106,159 @@ impl ToString for V1Hash {
     }
 }
 
+/// The parameters of hashing algorithm V2.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct V2Parameters {
+    /// The memory size in KiB.
+    pub memory_kib: u32,
+    /// The number of passes over the memory.
+    pub iterations: u32,
+    /// The degree of parallelism.
+    pub parallelism: u32,
+}
+
+impl Default for V2Parameters {
+    /// The parameters recommended by OWASP: 19 MiB of memory, 2 iterations, and a parallelism of
+    /// 1.
+    fn default() -> Self {
+        Self {
+            memory_kib: 19 * 1024,
+            iterations: 2,
+            parallelism: 1,
+        }
+    }
+}
+
+impl V2Parameters {
+    fn argon2_config(&self) -> argon2::Config<'static> {
+        argon2::Config {
+            variant: argon2::Variant::Argon2id,
+            version: argon2::Version::Version13,
+            mem_cost: self.memory_kib,
+            time_cost: self.iterations,
+            lanes: self.parallelism,
+            thread_mode: argon2::ThreadMode::Sequential,
+            secret: &[],
+            ad: &[],
+            hash_length: 32,
+        }
+    }
+}
+
+static V2_PARAMETERS: OnceCell<V2Parameters> = OnceCell::new();
+
+/// Set the parameters new hashes are generated with. Hashes generated with other parameters are
+/// reported as outdated when checked. If not set, the default parameters are used.
+///
+/// # Panics
+/// This function is only callable once, before any password is hashed or checked, and panics
+/// if the parameters are invalid.
+pub fn init_v2_parameters(parameters: V2Parameters) {
+    // Hashing checks the parameters.
+    if let Err(err) = argon2id("", &[0u8; 16], parameters) {
+        panic!("Invalid password hashing parameters: {}", err);
+    }
+
+    if V2_PARAMETERS.set(parameters).is_err() {
+        panic!("Password hashing parameters initialization called more than once.")
+    }
+}
+
+fn v2_parameters() -> V2Parameters {
+    *V2_PARAMETERS.get_or_init(V2Parameters::default)
+}
+
+/// Hashing algorithm V2, using Argon2id with tunable parameters and a 128-bit salt.
+struct V2Hash {
+    pub parameters: V2Parameters,
+    pub salt: [u8; 16],
+    pub hash: [u8; 32],
+}
+
+impl V2Hash {
+    /// Hash a user password.
+    pub fn hash_password(password: &str, parameters: V2Parameters) -> Self {
+        use rand::RngCore;
+
+        let mut salt = [0u8; 16];
+        let mut rng = rand::thread_rng();
+        rng.fill_bytes(&mut salt);
+
+        let hash = argon2id(password, &salt, parameters)
+            .expect("password hashing parameters were checked on initialization");
+
+        V2Hash {
+            parameters,
+            salt,
+            hash,
+        }
+    }
+
+    pub fn check(&self, password: &str) -> bool {
+        match argon2id(password, &self.salt, self.parameters) {
+            Ok(hash) => crypto::util::fixed_time_eq(&hash, &self.hash),
+            Err(_) => false,
+        }
+    }
+}
+
+impl FromStr for V2Hash {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split('$').collect();
+        if parts.len() != 6 {
+            return Err(());
+        }
+
+        if parts[0] != "2" {
+            return Err(());
+        }
+
+        let parameters = V2Parameters {
+            memory_kib: parts[1].parse().map_err(|_| ())?,
+            iterations: parts[2].parse().map_err(|_| ())?,
+            parallelism: parts[3].parse().map_err(|_| ())?,
+        };
+
+        let vec_salt = base64::decode(parts[4]).map_err(|_| ())?;
+        let vec_hash = base64::decode(parts[5]).map_err(|_| ())?;
+
+        if vec_salt.len() != 16 {
+            return Err(());
+        }
+
+        if vec_hash.len() != 32 {
+            return Err(());
+        }
+
+        let mut salt = [0u8; 16];
+        salt.copy_from_slice(&vec_salt);
+
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&vec_hash);
+
+        Ok(Self {
+            parameters,
+            salt,
+            hash,
+        })
+    }
+}
+
+impl ToString for V2Hash {
+    fn to_string(&self) -> String {
+        format!(
+            "2${}${}${}${}${}",
+            self.parameters.memory_kib,
+            self.parameters.iterations,
+            self.parameters.parallelism,
+            base64::encode(&self.salt),
+            base64::encode(&self.hash)
+        )
+    }
+}
+
 fn kit_hash_format(iterations: u32, salt: &str, hash: &[u8]) -> String {
     format!(
         "PBKDF2$sha256${}${}${}",
@@ -119,26 +292,76 @@ fn pbkdf2(password: &str, salt: &[u8], iterations: u32) -> [u8; 32] {
     dk
 }
 
-/// Check a password against a hash previously generated by this crate.
-pub fn check_user_password(password: &str, hash: &str) -> bool {
+/// Perform Argon2id.
+fn argon2id(
+    password: &str,
+    salt: &[u8],
+    parameters: V2Parameters,
+) -> Result<[u8; 32], argon2::Error> {
+    let vec_hash = argon2::hash_raw(password.as_bytes(), salt, &parameters.argon2_config())?;
+
+    let mut hash = [0u8; 32];
+    hash.copy_from_slice(&vec_hash);
+
+    Ok(hash)
+}
+
+/// Check a password against a hash previously generated by this crate. Reports whether the hash
+/// is outdated, such that the password can be rehashed.
+pub fn check_user_password(password: &str, hash: &str) -> PasswordCheck {
+    check_user_password_with(password, hash, v2_parameters())
+}
+
+fn check_user_password_with(password: &str, hash: &str, parameters: V2Parameters) -> PasswordCheck {
     match HashVersion::from_hash(hash) {
         Some(HashVersion::V1) => {
             let v1_hash: V1Hash = match hash.parse() {
                 Ok(v1_hash) => v1_hash,
-                Err(_) => return false,
+                Err(_) => return PasswordCheck::Incorrect,
             };
 
-            v1_hash.check(password)
+            if v1_hash.check(password) {
+                PasswordCheck::CorrectOutdated
+            } else {
+                PasswordCheck::Incorrect
+            }
         }
-        None => false,
+        Some(HashVersion::V2) => {
+            let v2_hash: V2Hash = match hash.parse() {
+                Ok(v2_hash) => v2_hash,
+                Err(_) => return PasswordCheck::Incorrect,
+            };
+
+            if !v2_hash.check(password) {
+                PasswordCheck::Incorrect
+            } else if v2_hash.parameters != parameters {
+                PasswordCheck::CorrectOutdated
+            } else {
+                PasswordCheck::Correct
+            }
+        }
+        None => PasswordCheck::Incorrect,
     }
 }
 
 /// Hash a user password.
 pub fn hash_user_password(password: &str) -> String {
-    let v1_hash = V1Hash::hash_password(password);
+    let v2_hash = V2Hash::hash_password(password, v2_parameters());
 
-    v1_hash.to_string()
+    v2_hash.to_string()
+}
+
+/// Hash a randomly generated token, such as a password reset token, for storage. Returns the
+/// lowercase hexadecimal SHA-256 of the token.
+///
+/// Unlike passwords, generated tokens have sufficient entropy that a single, unsalted round of
+/// hashing suffices, and the hash can be used to look up the token.
+pub fn hash_token(token: &str) -> String {
+    use crypto::{digest::Digest, sha2::Sha256};
+
+    let mut hasher = Sha256::new();
+    hasher.input_str(token);
+    hasher.result_str()
 }
 
 #[cfg(test)]
@@ -177,10 +400,110 @@ mod test {
         assert!(v1_hash.check("It all adds up to normality."),)
     }
 
+    #[test]
+    pub fn known_token_hash() {
+        assert_eq!(
+            &super::hash_token("abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
     #[test]
     pub fn hash_round_trip() {
         let password = "It all adds up to normality.";
         let v1_hash = super::V1Hash::hash_password(password);
         assert!(v1_hash.check(password))
     }
+
+    /// Cheap parameters, to keep the tests fast.
+    const TEST_PARAMETERS: super::V2Parameters = super::V2Parameters {
+        memory_kib: 64,
+        iterations: 1,
+        parallelism: 1,
+    };
+
+    #[test]
+    pub fn v2_hash_round_trip() {
+        let password = "It all adds up to normality.";
+        let v2_hash = super::V2Hash::hash_password(password, TEST_PARAMETERS);
+        assert!(v2_hash.check(password));
+        assert!(!v2_hash.check("It all adds up to abnormality."));
+
+        let parsed: super::V2Hash = v2_hash.to_string().parse().unwrap();
+        assert_eq!(parsed.parameters, TEST_PARAMETERS);
+        assert!(parsed.check(password));
+    }
+
+    #[test]
+    pub fn v2_hash_format() {
+        let v2_hash = super::V2Hash {
+            parameters: TEST_PARAMETERS,
+            salt: [1, 2, 3, 4, 5, 6, 7, 8, 9, 16, 17, 18, 19, 20, 21, 22],
+            hash: [0; 32],
+        };
+        assert_eq!(
+            v2_hash.to_string(),
+            "2$64$1$1$AQIDBAUGBwgJEBESExQVFg==$AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA="
+        );
+    }
+
+    #[test]
+    pub fn check_v2_hash() {
+        let v2_hash: super::V2Hash =
+            "2$64$1$1$AQIDBAUGBwgJEBESExQVFg==$M+kBFkjfwBa3Wy1nb+Ob/++BRqTRnLysFI6dYD9lrno="
+                .parse()
+                .unwrap();
+
+        assert!(v2_hash.check("It all adds up to normality."));
+    }
+
+    #[test]
+    pub fn v1_hashes_are_outdated() {
+        use super::PasswordCheck;
+
+        let v1_hash = "1$AQIDBAUGBwgJEBESExQVFg==$CxZam+iAsfxNt9doaNmMtSjBy6NqyoOMxSppNpJFmx8=";
+        assert_eq!(
+            super::check_user_password_with(
+                "It all adds up to normality.",
+                v1_hash,
+                TEST_PARAMETERS
+            ),
+            PasswordCheck::CorrectOutdated
+        );
+        assert_eq!(
+            super::check_user_password_with(
+                "It all adds up to abnormality.",
+                v1_hash,
+                TEST_PARAMETERS
+            ),
+            PasswordCheck::Incorrect
+        );
+    }
+
+    #[test]
+    pub fn v2_hashes_with_other_parameters_are_outdated() {
+        use super::PasswordCheck;
+
+        let password = "It all adds up to normality.";
+        let v2_hash = super::V2Hash::hash_password(password, TEST_PARAMETERS).to_string();
+        assert_eq!(
+            super::check_user_password_with(password, &v2_hash, TEST_PARAMETERS),
+            PasswordCheck::Correct
+        );
+        assert_eq!(
+            super::check_user_password_with(
+                password,
+                &v2_hash,
+                super::V2Parameters {
+                    iterations: 2,
+                    ..TEST_PARAMETERS
+                }
+            ),
+            PasswordCheck::CorrectOutdated
+        );
+        assert_eq!(
+            super::check_user_password_with("", &v2_hash, TEST_PARAMETERS),
+            PasswordCheck::Incorrect
+        );
+    }
 }
@@ -5,4 +5,6 @@
 //! Secondly, kit password hashes are generated as to be compatible with mosquitto-auth-plug.
 
 pub mod hash;
+pub mod signature;
 pub mod token;
+pub mod totp;
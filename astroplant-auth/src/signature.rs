@@ -0,0 +1,28 @@
+//! Signs messages with HMAC-SHA256, such that receivers sharing the secret can verify the
+//! messages' origin and integrity.
+
+/// Sign a message with a secret. Returns the lowercase hexadecimal HMAC-SHA256 of the message.
+pub fn sign(secret: &[u8], message: &[u8]) -> String {
+    use crypto::{hmac::Hmac, mac::Mac, sha2::Sha256};
+
+    let mut mac = Hmac::new(Sha256::new(), secret);
+    mac.input(message);
+
+    mac.result()
+        .code()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    #[test]
+    pub fn known_signature() {
+        // RFC 4231, test case 2.
+        assert_eq!(
+            super::sign(b"Jefe", b"what do ya want for nothing?"),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+}
@@ -1,4 +1,15 @@
-//! TODO: add ability to revoke refresh tokens.
+//! Refresh tokens carry the id of the session they were issued for. Sessions are stored by the
+//! caller, and refresh tokens should only be exchanged for access tokens while their session
+//! exists, such that refresh tokens can be revoked. Access tokens cannot be revoked, but are
+//! short-lived.
+//!
+//! Users with two-factor authentication enabled are first issued a challenge token after
+//! providing their password, which is exchanged for a refresh token and access token after
+//! providing the second factor.
+//!
+//! Personal access tokens are long-lived tokens users create for scripts and integrations. They
+//! carry the id of the personal access token as stored by the caller, which should be checked to
+//! still exist and to permit the request, such that they can be revoked and scoped.
 
 use serde::{Deserialize, Serialize};
 
@@ -6,6 +17,8 @@ use serde::{Deserialize, Serialize};
 enum TokenType {
     Refresh,
     Access,
+    Challenge,
+    Personal,
 }
 
 #[derive(Debug)]
@@ -14,14 +27,56 @@ pub enum Error {
     Other,
 }
 
+/// The time in seconds refresh tokens are valid for.
+pub const REFRESH_TOKEN_VALIDITY_TIME: usize = 60 * 60 * 24 * 365;
+
+/// The time in seconds access tokens are valid for.
+pub const ACCESS_TOKEN_VALIDITY_TIME: usize = 60 * 15;
+
+/// The time in seconds two-factor authentication challenge tokens are valid for.
+pub const CHALLENGE_TOKEN_VALIDITY_TIME: usize = 60 * 5;
+
+/// The time in seconds personal access tokens are valid for.
+pub const PERSONAL_TOKEN_VALIDITY_TIME: usize = 60 * 60 * 24 * 365;
+
 #[derive(Debug, PartialEq, Serialize, Deserialize, Clone)]
 pub struct AuthenticationState {
     pub user_id: i32,
+    /// The session the token was issued for. Tokens issued before sessions were introduced do not
+    /// have one.
+    #[serde(default)]
+    pub session_id: Option<String>,
+    /// The personal access token, if the token is a personal access token.
+    #[serde(default)]
+    pub personal_access_token_id: Option<i32>,
 }
 
 impl AuthenticationState {
-    pub fn new(user_id: i32) -> Self {
-        Self { user_id }
+    pub fn new(user_id: i32, session_id: String) -> Self {
+        Self {
+            user_id,
+            session_id: Some(session_id),
+            personal_access_token_id: None,
+        }
+    }
+
+    /// The state of a two-factor authentication challenge, which is issued before a session is
+    /// created.
+    pub fn challenge(user_id: i32) -> Self {
+        Self {
+            user_id,
+            session_id: None,
+            personal_access_token_id: None,
+        }
+    }
+
+    /// The state of a personal access token.
+    pub fn personal(user_id: i32, personal_access_token_id: i32) -> Self {
+        Self {
+            user_id,
+            session_id: None,
+            personal_access_token_id: Some(personal_access_token_id),
+        }
     }
 }
 
@@ -71,27 +126,62 @@ impl TokenSigner {
     }
 
     pub fn create_refresh_token(&self, state: AuthenticationState) -> String {
-        const VALIDITY_TIME: usize = 60 * 60 * 24 * 365;
+        self.create_token(REFRESH_TOKEN_VALIDITY_TIME, TokenType::Refresh, state)
+    }
+
+    pub fn create_access_token(&self, state: AuthenticationState) -> String {
+        self.create_token(ACCESS_TOKEN_VALIDITY_TIME, TokenType::Access, state)
+    }
 
-        self.create_token(VALIDITY_TIME, TokenType::Refresh, state)
+    /// Decode a refresh token. Whether the token's session still exists should be checked before
+    /// exchanging it for an access token.
+    pub fn decode_refresh_token(&self, token: &str) -> Result<AuthenticationState, Error> {
+        let claims = self.decode_token(token)?;
+        match claims.token_type {
+            TokenType::Refresh => Ok(claims.state),
+            _ => Err(Error::Other),
+        }
     }
 
     pub fn access_token_from_refresh_token(&self, token: &str) -> Result<String, Error> {
-        const VALIDITY_TIME: usize = 60 * 15;
+        let state = self.decode_refresh_token(token)?;
+        Ok(self.create_access_token(state))
+    }
 
+    pub fn decode_access_token(&self, token: &str) -> Result<AuthenticationState, Error> {
         let claims = self.decode_token(token)?;
         match claims.token_type {
-            TokenType::Refresh => {
-                Ok(self.create_token(VALIDITY_TIME, TokenType::Access, claims.state))
-            }
+            TokenType::Access => Ok(claims.state),
             _ => Err(Error::Other),
         }
     }
 
-    pub fn decode_access_token(&self, token: &str) -> Result<AuthenticationState, Error> {
+    pub fn create_personal_token(&self, state: AuthenticationState) -> String {
+        self.create_token(PERSONAL_TOKEN_VALIDITY_TIME, TokenType::Personal, state)
+    }
+
+    /// Decode a token authenticating a request: either an access token or a personal access
+    /// token. Whether a personal access token still exists and permits the request should be
+    /// checked by the caller.
+    pub fn decode_authorization_token(&self, token: &str) -> Result<AuthenticationState, Error> {
         let claims = self.decode_token(token)?;
         match claims.token_type {
             TokenType::Access => Ok(claims.state),
+            TokenType::Personal if claims.state.personal_access_token_id.is_some() => {
+                Ok(claims.state)
+            }
+            _ => Err(Error::Other),
+        }
+    }
+
+    pub fn create_challenge_token(&self, state: AuthenticationState) -> String {
+        self.create_token(CHALLENGE_TOKEN_VALIDITY_TIME, TokenType::Challenge, state)
+    }
+
+    pub fn decode_challenge_token(&self, token: &str) -> Result<AuthenticationState, Error> {
+        let claims = self.decode_token(token)?;
+        match claims.token_type {
+            TokenType::Challenge => Ok(claims.state),
             _ => Err(Error::Other),
         }
     }
@@ -106,7 +196,7 @@ mod test {
         for _ in 0..100 {
             let id = rand::random::<i32>();
 
-            let state = super::AuthenticationState { user_id: id };
+            let state = super::AuthenticationState::new(id, id.to_string());
 
             let refresh_token = token_signer.create_refresh_token(state.clone());
             let access_token = token_signer
@@ -121,4 +211,80 @@ mod test {
             );
         }
     }
+
+    #[test]
+    pub fn tokens_without_session_decode() {
+        use super::TokenType;
+
+        let token_signer = super::TokenSigner::new(b"my server secret".to_vec());
+
+        #[derive(serde::Serialize)]
+        struct LegacyState {
+            user_id: i32,
+        }
+        #[derive(serde::Serialize)]
+        struct LegacyClaims {
+            exp: usize,
+            token_type: TokenType,
+            state: LegacyState,
+        }
+
+        let exp = chrono::Utc::now().timestamp() as usize + 60;
+        let legacy_token = jsonwebtoken::encode(
+            &jsonwebtoken::Header::default(),
+            &LegacyClaims {
+                exp,
+                token_type: TokenType::Access,
+                state: LegacyState { user_id: 1 },
+            },
+            b"my server secret",
+        )
+        .unwrap();
+
+        let state = token_signer.decode_access_token(&legacy_token).unwrap();
+        assert_eq!(state.user_id, 1);
+        assert_eq!(state.session_id, None);
+    }
+
+    #[test]
+    pub fn challenge_tokens_are_not_access_tokens() {
+        let token_signer = super::TokenSigner::new(b"my server secret".to_vec());
+        let state = super::AuthenticationState::challenge(1);
+
+        let challenge_token = token_signer.create_challenge_token(state.clone());
+        assert_eq!(
+            token_signer
+                .decode_challenge_token(&challenge_token)
+                .unwrap(),
+            state
+        );
+        assert!(token_signer.decode_access_token(&challenge_token).is_err());
+        assert!(token_signer
+            .decode_refresh_token(&challenge_token)
+            .is_err());
+
+        let access_token = token_signer.create_access_token(super::AuthenticationState::new(
+            1,
+            "session".to_owned(),
+        ));
+        assert!(token_signer.decode_challenge_token(&access_token).is_err());
+    }
+
+    #[test]
+    pub fn personal_tokens_authorize_but_do_not_refresh() {
+        let token_signer = super::TokenSigner::new(b"my server secret".to_vec());
+        let state = super::AuthenticationState::personal(1, 2);
+
+        let personal_token = token_signer.create_personal_token(state.clone());
+        assert_eq!(
+            token_signer
+                .decode_authorization_token(&personal_token)
+                .unwrap(),
+            state
+        );
+        assert!(token_signer.decode_access_token(&personal_token).is_err());
+        assert!(token_signer
+            .access_token_from_refresh_token(&personal_token)
+            .is_err());
+    }
 }
@@ -0,0 +1,165 @@
+//! Time-based one-time passwords (TOTP) as specified in RFC 6238, for two-factor authentication
+//! of users. Uses the parameters authenticator apps assume by default: HMAC-SHA1, six digits and
+//! a period of 30 seconds.
+
+use crypto::hmac::Hmac;
+use crypto::mac::Mac;
+use crypto::sha1::Sha1;
+
+/// The number of bytes of generated secrets. 160 bits, as recommended by RFC 4226.
+const SECRET_LENGTH: usize = 20;
+
+/// The number of digits of codes.
+const DIGITS: u32 = 6;
+
+/// The time in seconds a code is valid for.
+pub const PERIOD: u64 = 30;
+
+/// The number of periods before and after the current period that codes are still accepted from,
+/// to allow for clock drift and for the time it takes to enter a code.
+const ALLOWED_DRIFT: u64 = 1;
+
+/// Generate a new secret, encoded as base32 as expected by authenticator apps.
+pub fn generate_secret() -> String {
+    use rand::RngCore;
+
+    let mut secret = [0u8; SECRET_LENGTH];
+    let mut rng = rand::thread_rng();
+    rng.fill_bytes(&mut secret);
+
+    base32::encode(base32::Alphabet::RFC4648 { padding: false }, &secret)
+}
+
+fn decode_secret(secret: &str) -> Option<Vec<u8>> {
+    base32::decode(base32::Alphabet::RFC4648 { padding: false }, secret)
+}
+
+/// Percent-encode a component of an otpauth URI.
+fn uri_encode(component: &str) -> String {
+    let mut encoded = String::with_capacity(component.len());
+    for byte in component.bytes() {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                encoded.push(byte as char)
+            }
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+/// Create the otpauth URI of a secret, which can be entered into authenticator apps (generally
+/// by scanning it as a QR code).
+pub fn otpauth_uri(issuer: &str, account_name: &str, secret: &str) -> String {
+    format!(
+        "otpauth://totp/{issuer}:{account_name}?secret={secret}&issuer={issuer}\
+         &algorithm=SHA1&digits={digits}&period={period}",
+        issuer = uri_encode(issuer),
+        account_name = uri_encode(account_name),
+        secret = secret,
+        digits = DIGITS,
+        period = PERIOD,
+    )
+}
+
+/// The time step a unix timestamp falls in.
+fn time_step(timestamp: u64) -> u64 {
+    timestamp / PERIOD
+}
+
+/// Calculate the code of a time step, as specified in RFC 4226.
+fn code(secret: &[u8], step: u64) -> u32 {
+    let mut hmac = Hmac::new(Sha1::new(), secret);
+    hmac.input(&step.to_be_bytes());
+    let result = hmac.result();
+    let hash = result.code();
+
+    let offset = (hash[hash.len() - 1] & 0xf) as usize;
+    let truncated = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+
+    truncated % 10u32.pow(DIGITS)
+}
+
+/// Check a code against a secret at the given unix timestamp. Returns the time step the code
+/// belongs to if it is correct. The caller should reject codes of time steps that were used
+/// before, such that codes cannot be replayed.
+pub fn check(secret: &str, code_input: &str, timestamp: u64) -> Option<u64> {
+    let secret = decode_secret(secret)?;
+
+    let code_input = code_input.trim();
+    if code_input.len() != DIGITS as usize || !code_input.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let current = time_step(timestamp);
+    (current.saturating_sub(ALLOWED_DRIFT)..=current + ALLOWED_DRIFT).find(|&step| {
+        let expected = format!("{:0width$}", code(&secret, step), width = DIGITS as usize);
+        crypto::util::fixed_time_eq(expected.as_bytes(), code_input.as_bytes())
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check, code, generate_secret, otpauth_uri, time_step, PERIOD};
+
+    /// The SHA1 secret of the test vectors in RFC 6238.
+    const RFC_SECRET: &[u8] = b"12345678901234567890";
+
+    #[test]
+    fn rfc_6238_test_vectors() {
+        // The RFC lists eight-digit codes; six-digit codes are their last six digits.
+        let vectors = [
+            (59, 287_082),
+            (1_111_111_109, 81_804),
+            (1_111_111_111, 50_471),
+            (1_234_567_890, 5_924),
+            (2_000_000_000, 279_037),
+            (20_000_000_000, 353_130),
+        ];
+
+        for &(timestamp, expected) in vectors.iter() {
+            assert_eq!(code(RFC_SECRET, time_step(timestamp)), expected);
+        }
+    }
+
+    #[test]
+    fn check_allows_drift() {
+        let secret = base32::encode(base32::Alphabet::RFC4648 { padding: false }, RFC_SECRET);
+        let timestamp = 1_111_111_109;
+        let step = time_step(timestamp);
+
+        assert_eq!(check(&secret, "081804", timestamp), Some(step));
+        assert_eq!(check(&secret, "081804", timestamp + PERIOD), Some(step));
+        assert_eq!(check(&secret, "081804", timestamp - PERIOD), Some(step));
+        assert_eq!(check(&secret, "081804", timestamp + 2 * PERIOD), None);
+        assert_eq!(check(&secret, "81804", timestamp), None);
+        assert_eq!(check(&secret, "000000", timestamp), None);
+    }
+
+    #[test]
+    fn generated_secret_round_trip() {
+        let secret = generate_secret();
+        let timestamp = 1_600_000_000;
+        let expected = format!(
+            "{:06}",
+            code(
+                &super::decode_secret(&secret).unwrap(),
+                time_step(timestamp)
+            )
+        );
+
+        assert_eq!(
+            check(&secret, &expected, timestamp),
+            Some(time_step(timestamp))
+        );
+
+        let uri = otpauth_uri("AstroPlant", "user name", &secret);
+        assert!(uri.starts_with("otpauth://totp/AstroPlant:user%20name?secret="));
+        assert!(uri.contains(&secret));
+    }
+}
@@ -13,13 +13,15 @@ pub use server_rpc::{ServerRpcRequest, ServerRpcResponder};
 mod kit_rpc;
 pub use kit_rpc::{KitRpc, KitRpcResponseError, KitsRpc};
 
+mod presence;
+
 const MQTT_API_MESSAGE_BUFFER: usize = 128;
 
 pub mod astroplant_capnp {
     include!(concat!(env!("OUT_DIR"), "/proto/astroplant_capnp.rs"));
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct RawMeasurement {
     pub id: uuid::Uuid,
     pub kit_serial: String,
@@ -52,8 +54,17 @@ pub struct Media {
     pub metadata: serde_json::Value,
 }
 
+/// A kit sent a message.
+#[derive(Clone, Debug)]
+pub struct KitSeen {
+    pub kit_serial: String,
+    /// The time the message was received, in milliseconds since the Unix epoch.
+    pub datetime: u64,
+}
+
 #[derive(Debug)]
 pub enum MqttApiMessage {
+    KitSeen(KitSeen),
     RawMeasurement(RawMeasurement),
     AggregateMeasurement(AggregateMeasurement),
     Media(Media),
@@ -180,12 +191,14 @@ fn proxy<'a>(
 
 struct Handler {
     server_rpc_handler: server_rpc::ServerRpcHandler,
+    presence_tracker: presence::PresenceTracker,
 }
 
 impl Handler {
     pub fn new() -> Self {
         Self {
             server_rpc_handler: server_rpc::ServerRpcHandler::new(),
+            presence_tracker: presence::PresenceTracker::new(),
         }
     }
 
@@ -200,6 +213,8 @@ impl Handler {
             Some(serial) => serial.to_owned(),
             None => return Err(Error::InvalidTopic),
         };
+        self.presence_tracker
+            .seen(&kit_serial, std::time::Instant::now());
 
         match topic_parts.next() {
             Some("measurement") => match topic_parts.next() {
@@ -255,7 +270,30 @@ impl Handler {
                     establish_subscriptions(&mut mqtt_client);
                 }
                 Notification::Publish(publish) => {
-                    match self.handle_mqtt_publish(publish) {
+                    let result = self.handle_mqtt_publish(publish);
+
+                    let datetime = std::time::SystemTime::now()
+                        .duration_since(std::time::UNIX_EPOCH)
+                        .map(|since_epoch| since_epoch.as_millis() as u64)
+                        .unwrap_or(0);
+                    let kits_seen = self.presence_tracker.take_due();
+                    if kits_seen
+                        .into_iter()
+                        .map(|kit_serial| KitSeen {
+                            kit_serial,
+                            datetime,
+                        })
+                        .any(|kit_seen| {
+                            mqtt_api_sender
+                                .send(MqttApiMessage::KitSeen(kit_seen))
+                                .is_err()
+                        })
+                    {
+                        // Receiver not keeping up. Disconnect.
+                        break;
+                    }
+
+                    match result {
                         Ok(MqttMessage::Api(msg, responder)) => {
                             if let Some(responder) = responder {
                                 thread_pool
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// Kits are reported as seen at most once per this interval.
+const REPORT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Tracks which kits have sent messages, throttling how often each kit is reported as seen.
+///
+/// A kit last reported longer than `REPORT_INTERVAL` ago is reported on its next message, just
+/// like a kit that was never seen. Such kits are pruned periodically, such that only kits seen
+/// recently are tracked.
+pub struct PresenceTracker {
+    last_reported: HashMap<String, Instant>,
+    last_pruned: Option<Instant>,
+    due: Vec<String>,
+}
+
+impl PresenceTracker {
+    pub fn new() -> Self {
+        Self {
+            last_reported: HashMap::new(),
+            last_pruned: None,
+            due: vec![],
+        }
+    }
+
+    /// Record a message of a kit.
+    pub fn seen(&mut self, kit_serial: &str, now: Instant) {
+        match self.last_reported.get_mut(kit_serial) {
+            Some(last_reported) if now.duration_since(*last_reported) < REPORT_INTERVAL => {}
+            Some(last_reported) => {
+                *last_reported = now;
+                self.due.push(kit_serial.to_owned());
+            }
+            None => {
+                self.last_reported.insert(kit_serial.to_owned(), now);
+                self.due.push(kit_serial.to_owned());
+            }
+        }
+
+        match self.last_pruned {
+            Some(last_pruned) if now.saturating_duration_since(last_pruned) < REPORT_INTERVAL => {}
+            _ => {
+                self.prune(now);
+                self.last_pruned = Some(now);
+            }
+        }
+    }
+
+    /// Stop tracking kits that were last reported longer than `REPORT_INTERVAL` ago.
+    fn prune(&mut self, now: Instant) {
+        self.last_reported.retain(|_, last_reported| {
+            now.saturating_duration_since(*last_reported) < REPORT_INTERVAL
+        });
+    }
+
+    /// Take the serials of the kits that are due to be reported as seen.
+    pub fn take_due(&mut self) -> Vec<String> {
+        std::mem::replace(&mut self.due, vec![])
+    }
+}
+
+impl Default for PresenceTracker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{PresenceTracker, REPORT_INTERVAL};
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn reports_are_throttled_per_kit() {
+        let start = Instant::now();
+        let mut tracker = PresenceTracker::new();
+
+        tracker.seen("k-a", start);
+        tracker.seen("k-a", start + Duration::from_secs(1));
+        tracker.seen("k-b", start + Duration::from_secs(1));
+        assert_eq!(tracker.take_due(), vec!["k-a", "k-b"]);
+        assert!(tracker.take_due().is_empty());
+
+        tracker.seen("k-a", start + REPORT_INTERVAL - Duration::from_secs(1));
+        assert!(tracker.take_due().is_empty());
+
+        tracker.seen("k-a", start + REPORT_INTERVAL);
+        assert_eq!(tracker.take_due(), vec!["k-a"]);
+    }
+
+    #[test]
+    fn kits_not_seen_recently_are_pruned() {
+        let start = Instant::now();
+        let mut tracker = PresenceTracker::new();
+
+        tracker.seen("k-a", start);
+        tracker.seen("k-b", start + Duration::from_secs(1));
+        assert_eq!(tracker.last_reported.len(), 2);
+
+        tracker.seen("k-c", start + REPORT_INTERVAL);
+        assert_eq!(tracker.last_reported.len(), 2);
+        assert!(!tracker.last_reported.contains_key("k-a"));
+
+        tracker.seen("k-c", start + REPORT_INTERVAL * 3);
+        assert_eq!(tracker.last_reported.len(), 1);
+        assert_eq!(tracker.take_due(), vec!["k-a", "k-b", "k-c", "k-c"]);
+    }
+}
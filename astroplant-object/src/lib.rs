@@ -11,12 +11,26 @@ use tokio::io::AsyncReadExt;
 pub enum Error {
     S3Put(rusoto_core::RusotoError<rusoto_s3::PutObjectError>),
     S3Get(rusoto_core::RusotoError<rusoto_s3::GetObjectError>),
+    S3Delete(rusoto_core::RusotoError<rusoto_s3::DeleteObjectError>),
     S3NoFile,
     LocalIo(tokio::io::Error),
     LocalOther,
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
+impl Error {
+    /// Whether the error is caused by the requested object not existing.
+    pub fn is_not_found(&self) -> bool {
+        match self {
+            Error::S3Get(rusoto_core::RusotoError::Service(
+                rusoto_s3::GetObjectError::NoSuchKey(_),
+            )) => true,
+            Error::LocalIo(err) => err.kind() == std::io::ErrorKind::NotFound,
+            _ => false,
+        }
+    }
+}
+
 #[derive(Clone)]
 enum Stores {
     S3 {
@@ -140,4 +154,26 @@ impl ObjectStore {
             }
         }
     }
+
+    /// Delete an object. Deleting an object that does not exist is not an error.
+    pub async fn delete(&self, kit_serial: &str, object_name: &str) -> Result<()> {
+        let key = format!("{}/{}", kit_serial, object_name);
+        match &self.store {
+            Stores::S3 { s3, bucket_name } => {
+                let mut request = rusoto_s3::DeleteObjectRequest::default();
+                request.bucket = bucket_name.clone();
+                request.key = key;
+                s3.delete_object(request).await.map_err(Error::S3Delete)?;
+                Ok(())
+            }
+            Stores::Local { root } => {
+                let path = root.join(Path::new(&key));
+                match tokio::fs::remove_file(path).await {
+                    Ok(()) => Ok(()),
+                    Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(()),
+                    Err(err) => Err(Error::LocalIo(err)),
+                }
+            }
+        }
+    }
 }
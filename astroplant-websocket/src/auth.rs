@@ -0,0 +1,90 @@
+use futures::future::BoxFuture;
+use jsonrpc_core::{Error, ErrorCode};
+
+/// An authenticated user, and the personal access token they authenticated with, if any. The
+/// subscriptions of users authenticated with a personal access token are limited to the token's
+/// scope.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub user_id: i32,
+    pub personal_access_token_id: Option<i32>,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub enum AuthenticationError {
+    Expired,
+    Invalid,
+}
+
+/// A subscription requested by a WebSocket session.
+#[derive(Clone, Debug)]
+pub enum Subscription {
+    RawMeasurements { kit_serial: String },
+    AggregateMeasurements { kit_serial: String },
+    Media { kit_serial: String },
+    KitStatus { kit_serial: String },
+    Alerts { kit_serial: String },
+}
+
+/// The JSON-RPC error code for a failed authentication.
+const UNAUTHENTICATED: i64 = -32001;
+
+/// The JSON-RPC error code for a subscription the session's user is not permitted to make.
+const FORBIDDEN: i64 = -32003;
+
+pub(crate) fn authentication_error(error: AuthenticationError) -> Error {
+    Error {
+        code: ErrorCode::ServerError(UNAUTHENTICATED),
+        message: match error {
+            AuthenticationError::Expired => "The access token has expired.".to_owned(),
+            AuthenticationError::Invalid => "The access token is invalid.".to_owned(),
+        },
+        data: None,
+    }
+}
+
+pub(crate) fn forbidden_error() -> Error {
+    Error {
+        code: ErrorCode::ServerError(FORBIDDEN),
+        message: "You are not permitted to make this subscription.".to_owned(),
+        data: None,
+    }
+}
+
+/// The callbacks used to authenticate WebSocket sessions and to authorize their subscriptions.
+pub(crate) struct Auth {
+    authenticate: Box<dyn Fn(&str) -> Result<AuthenticatedUser, AuthenticationError> + Send + Sync>,
+    authorize: Box<
+        dyn Fn(Option<AuthenticatedUser>, Subscription) -> BoxFuture<'static, bool> + Send + Sync,
+    >,
+}
+
+impl Auth {
+    pub fn new<A, Z>(authenticate: A, authorize: Z) -> Self
+    where
+        A: Fn(&str) -> Result<AuthenticatedUser, AuthenticationError> + Send + Sync + 'static,
+        Z: Fn(Option<AuthenticatedUser>, Subscription) -> BoxFuture<'static, bool>
+            + Send
+            + Sync
+            + 'static,
+    {
+        Self {
+            authenticate: Box::new(authenticate),
+            authorize: Box::new(authorize),
+        }
+    }
+
+    /// Authenticate a user by their access token or personal access token.
+    pub fn authenticate(&self, token: &str) -> Result<AuthenticatedUser, AuthenticationError> {
+        (self.authenticate)(token)
+    }
+
+    /// Authorize a subscription of a (possibly anonymous) user.
+    pub fn authorize(
+        &self,
+        user: Option<AuthenticatedUser>,
+        subscription: Subscription,
+    ) -> BoxFuture<'static, bool> {
+        (self.authorize)(user, subscription)
+    }
+}
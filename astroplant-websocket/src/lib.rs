@@ -1,33 +1,45 @@
 #![recursion_limit = "1024"]
 
+mod auth;
 mod subscribers;
 mod types;
 mod web_socket_session;
 
+use auth::Auth;
+pub use auth::{AuthenticatedUser, AuthenticationError, Subscription};
 use subscribers::Subscribers;
 pub use types::RawMeasurement;
+use web_socket_session::SessionMetadata;
 
+use futures::future::{BoxFuture, FutureExt, TryFutureExt};
 use jsonrpc_core::MetaIoHandler;
 use jsonrpc_core::{futures as futuresOne, Params, Value};
 use jsonrpc_pubsub::typed::{Sink, Subscriber};
-use jsonrpc_pubsub::{PubSubHandler, Session, SubscriptionId};
+use jsonrpc_pubsub::{PubSubHandler, SubscriptionId};
 use jsonrpc_server_utils::tokio;
 use log::{debug, trace};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex, RwLock};
-use warp::{filters::BoxedFilter, Filter};
+use warp::{filters::BoxedFilter, Filter, Reply};
 
 use futuresOne::future::Future as FutureOne;
 
 type PeripheralQuantityType = (i32, i32);
 
+/// Subscribers to the updates of kits, by kit serial.
+type KitSubscriptions = Arc<RwLock<HashMap<String, Subscribers<Sink<Value>>>>>;
+
 #[derive(Clone)]
 struct WebSocketHandler {
     executor: tokio::runtime::TaskExecutor,
-    raw_measurement_subscriptions: Arc<RwLock<HashMap<String, Subscribers<Sink<Value>>>>>,
+    raw_measurement_subscriptions: KitSubscriptions,
     raw_measurement_buffer:
         Arc<RwLock<HashMap<String, HashMap<PeripheralQuantityType, RawMeasurement>>>>,
+    aggregate_measurement_subscriptions: KitSubscriptions,
+    media_subscriptions: KitSubscriptions,
+    kit_status_subscriptions: KitSubscriptions,
+    alert_subscriptions: KitSubscriptions,
 }
 
 impl WebSocketHandler {
@@ -36,9 +48,46 @@ impl WebSocketHandler {
             executor,
             raw_measurement_subscriptions: Arc::new(RwLock::new(HashMap::default())),
             raw_measurement_buffer: Arc::new(RwLock::new(HashMap::default())),
+            aggregate_measurement_subscriptions: Arc::new(RwLock::new(HashMap::default())),
+            media_subscriptions: Arc::new(RwLock::new(HashMap::default())),
+            kit_status_subscriptions: Arc::new(RwLock::new(HashMap::default())),
+            alert_subscriptions: Arc::new(RwLock::new(HashMap::default())),
+        }
+    }
+
+    /// Notify all subscribers to a kit.
+    fn notify(&self, subscriptions: &KitSubscriptions, kit_serial: &str, value: Value) {
+        let subscriptions = subscriptions.read().unwrap();
+
+        let subscribers: Option<&Subscribers<Sink<Value>>> = subscriptions.get(kit_serial);
+        if let Some(subscribers) = subscribers {
+            for (id, subscriber) in subscribers.iter() {
+                let id = id.clone();
+                self.executor.spawn(
+                    subscriber
+                        .notify(Ok(value.clone()))
+                        .map(|_| ())
+                        .map_err(move |_| {
+                            debug!(
+                                "subscriber {:?}: failed sending notification. Transport has gone away.",
+                                id
+                            )
+                        }),
+                );
+            }
         }
     }
 
+    fn remove_subscriber(subscriptions: &KitSubscriptions, id: &SubscriptionId) {
+        let mut subscriptions = subscriptions.write().unwrap();
+
+        // O(n) with n the number of distinct kits subscribed to.
+        subscriptions.retain(|_, s| {
+            s.remove(id);
+            !s.is_empty()
+        });
+    }
+
     fn buffer_raw_measurement(&self, kit_serial: String, raw_measurement: RawMeasurement) {
         let mut buffer = self.raw_measurement_buffer.write().unwrap();
         let index = (raw_measurement.peripheral, raw_measurement.quantity_type);
@@ -50,27 +99,8 @@ impl WebSocketHandler {
     }
 
     fn publish_raw_measurement(&self, kit_serial: String, raw_measurement: RawMeasurement) {
-        let subscriptions = self.raw_measurement_subscriptions.read().unwrap();
-
-        let subscribers: Option<&Subscribers<Sink<Value>>> = subscriptions.get(&kit_serial);
-        if let Some(subscribers) = subscribers {
-            let value = serde_json::to_value(raw_measurement.clone()).unwrap();
-            for (id, subscriber) in subscribers.iter() {
-                let id = id.clone();
-                self.executor
-                    .spawn(
-                        subscriber
-                            .notify(Ok(value.clone()))
-                            .map(|_| ())
-                            .map_err(move |_| {
-                                debug!(
-                                    "subscriber {:?}: failed sending raw measurement. Transport has gone away.",
-                                    id
-                                )
-                            }),
-                    );
-            }
-        }
+        let value = serde_json::to_value(raw_measurement.clone()).unwrap();
+        self.notify(&self.raw_measurement_subscriptions, &kit_serial, value);
 
         self.buffer_raw_measurement(kit_serial, raw_measurement);
     }
@@ -101,15 +131,53 @@ impl WebSocketHandler {
     }
 
     fn remove_raw_measurement_subscriber(&self, id: SubscriptionId) {
-        let mut subscriptions = self.raw_measurement_subscriptions.write().unwrap();
-
-        // O(n) with n the number of distinct kits subscribed to.
-        subscriptions.retain(|_, s| {
-            s.remove(&id);
-            !s.is_empty()
-        });
+        Self::remove_subscriber(&self.raw_measurement_subscriptions, &id);
         trace!("Raw measurement subscriber removed: {:?}", id);
     }
+
+    fn add_aggregate_measurement_subscriber(
+        &self,
+        kit_serial: String,
+        subscriber: Subscriber<Value>,
+    ) {
+        let mut subscriptions = self.aggregate_measurement_subscriptions.write().unwrap();
+        subscriptions.entry(kit_serial).or_default().add(subscriber);
+    }
+
+    fn remove_aggregate_measurement_subscriber(&self, id: SubscriptionId) {
+        Self::remove_subscriber(&self.aggregate_measurement_subscriptions, &id);
+        trace!("Aggregate measurement subscriber removed: {:?}", id);
+    }
+
+    fn add_media_subscriber(&self, kit_serial: String, subscriber: Subscriber<Value>) {
+        let mut subscriptions = self.media_subscriptions.write().unwrap();
+        subscriptions.entry(kit_serial).or_default().add(subscriber);
+    }
+
+    fn remove_media_subscriber(&self, id: SubscriptionId) {
+        Self::remove_subscriber(&self.media_subscriptions, &id);
+        trace!("Media subscriber removed: {:?}", id);
+    }
+
+    fn add_kit_status_subscriber(&self, kit_serial: String, subscriber: Subscriber<Value>) {
+        let mut subscriptions = self.kit_status_subscriptions.write().unwrap();
+        subscriptions.entry(kit_serial).or_default().add(subscriber);
+    }
+
+    fn remove_kit_status_subscriber(&self, id: SubscriptionId) {
+        Self::remove_subscriber(&self.kit_status_subscriptions, &id);
+        trace!("Kit status subscriber removed: {:?}", id);
+    }
+
+    fn add_alert_subscriber(&self, kit_serial: String, subscriber: Subscriber<Value>) {
+        let mut subscriptions = self.alert_subscriptions.write().unwrap();
+        subscriptions.entry(kit_serial).or_default().add(subscriber);
+    }
+
+    fn remove_alert_subscriber(&self, id: SubscriptionId) {
+        Self::remove_subscriber(&self.alert_subscriptions, &id);
+        trace!("Alert subscriber removed: {:?}", id);
+    }
 }
 
 pub struct WebSocketPublisher {
@@ -122,25 +190,88 @@ impl WebSocketPublisher {
         self.web_socket_handler
             .publish_raw_measurement(kit_serial, raw_measurement);
     }
-}
 
-/// Runs a JSON-RPC server on top of a Warp WebSocket filter.
-/// An executor for handling messages in run in another thread.
-///
-/// Returns a Warp filter and a handle to publish to subscriptions.
-pub fn run() -> (BoxedFilter<(impl warp::Reply,)>, WebSocketPublisher) {
-    let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+    /// Publish a newly stored aggregate measurement of a kit.
+    pub fn publish_aggregate_measurement<T: Serialize>(
+        &mut self,
+        kit_serial: String,
+        aggregate_measurement: &T,
+    ) {
+        let value = serde_json::to_value(aggregate_measurement).unwrap();
+        self.web_socket_handler.notify(
+            &self.web_socket_handler.aggregate_measurement_subscriptions,
+            &kit_serial,
+            value,
+        );
+    }
 
-    let web_socket_handler = WebSocketHandler::new(runtime.executor());
+    /// Publish a kit going online or offline.
+    pub fn publish_kit_status<T: Serialize>(&mut self, kit_serial: String, kit_status: &T) {
+        let value = serde_json::to_value(kit_status).unwrap();
+        self.web_socket_handler.notify(
+            &self.web_socket_handler.kit_status_subscriptions,
+            &kit_serial,
+            value,
+        );
+    }
 
-    std::thread::spawn(move || runtime.block_on(futuresOne::future::empty::<(), ()>()));
+    /// Publish an alert of a kit that fired or was resolved.
+    pub fn publish_alert<T: Serialize>(&mut self, kit_serial: String, alert: &T) {
+        let value = serde_json::to_value(alert).unwrap();
+        self.web_socket_handler.notify(
+            &self.web_socket_handler.alert_subscriptions,
+            &kit_serial,
+            value,
+        );
+    }
 
-    let mut io = PubSubHandler::new(MetaIoHandler::default());
+    /// Publish newly stored media of a kit.
+    pub fn publish_media<T: Serialize>(&mut self, kit_serial: String, media: &T) {
+        let value = serde_json::to_value(media).unwrap();
+        self.web_socket_handler.notify(
+            &self.web_socket_handler.media_subscriptions,
+            &kit_serial,
+            value,
+        );
+    }
+}
+
+/// Authorize a subscription, and call `subscribe` with the subscriber if it is authorized.
+/// Otherwise, the subscriber is rejected.
+fn authorize_subscription<F>(
+    executor: &tokio::runtime::TaskExecutor,
+    authorized: BoxFuture<'static, bool>,
+    subscriber: jsonrpc_pubsub::Subscriber,
+    subscribe: F,
+) where
+    F: FnOnce(jsonrpc_pubsub::Subscriber) + Send + 'static,
+{
+    executor.spawn(authorized.map(Ok::<_, ()>).compat().map(move |authorized| {
+        if authorized {
+            subscribe(subscriber);
+        } else {
+            let _ = subscriber.reject(auth::forbidden_error());
+        }
+    }));
+}
+
+/// Add a subscription to updates of a kit, as the `subscribe_{name}` and `unsubscribe_{name}`
+/// methods. Subscriptions take the kit's serial as `kitSerial` parameter.
+fn add_kit_subscription(
+    io: &mut PubSubHandler<SessionMetadata>,
+    web_socket_handler: &WebSocketHandler,
+    auth: &Arc<Auth>,
+    name: &str,
+    subscription: fn(String) -> Subscription,
+    subscribe: fn(&WebSocketHandler, String, Subscriber<Value>),
+    unsubscribe: fn(&WebSocketHandler, SubscriptionId),
+) {
     io.add_subscription(
-        "rawMeasurements",
-        ("subscribe_rawMeasurements", {
+        name,
+        (format!("subscribe_{}", name).as_str(), {
             let web_socket_handler = web_socket_handler.clone();
-            move |params: Params, _: Arc<Session>, subscriber: jsonrpc_pubsub::Subscriber| {
+            let auth = auth.clone();
+            move |params: Params, meta: SessionMetadata, subscriber: jsonrpc_pubsub::Subscriber| {
                 #[derive(Deserialize)]
                 #[serde(rename_all = "camelCase")]
                 struct SubParams {
@@ -149,39 +280,180 @@ pub fn run() -> (BoxedFilter<(impl warp::Reply,)>, WebSocketPublisher) {
 
                 match params.parse::<SubParams>() {
                     Ok(sub_params) => {
-                        let subscriber = Subscriber::new(subscriber);
-                        web_socket_handler
-                            .add_raw_measurement_subscriber(sub_params.kit_serial, subscriber);
+                        let authorized = auth
+                            .authorize(meta.user(), subscription(sub_params.kit_serial.clone()));
+                        let executor = web_socket_handler.executor.clone();
+                        let web_socket_handler = web_socket_handler.clone();
+                        authorize_subscription(
+                            &executor,
+                            authorized,
+                            subscriber,
+                            move |subscriber| {
+                                subscribe(
+                                    &web_socket_handler,
+                                    sub_params.kit_serial,
+                                    Subscriber::new(subscriber),
+                                );
+                            },
+                        );
+                    }
+                    Err(err) => {
+                        let _ = subscriber.reject(err);
                     }
-                    Err(_) => {}
                 }
             }
         }),
-        ("unsubscribe_rawMeasurements", {
+        (format!("unsubscribe_{}", name).as_str(), {
             let web_socket_handler = web_socket_handler.clone();
             move |id: SubscriptionId, _| {
-                web_socket_handler.remove_raw_measurement_subscriber(id);
+                unsubscribe(&web_socket_handler, id);
                 futuresOne::future::ok(Value::Bool(true))
             }
         }),
     );
-    let io_handler: MetaIoHandler<Arc<Session>> = io.into();
+}
+
+/// Runs a JSON-RPC server on top of a Warp WebSocket filter.
+/// An executor for handling messages in run in another thread.
+///
+/// Sessions are authenticated by an access token or personal access token, given either in the
+/// `token` query parameter or the Authorization header when upgrading the connection, or through
+/// the `authenticate` method. Access tokens are checked by `authenticate`, and subscriptions are
+/// checked by `authorize`. The future returned by `authorize` is not polled within a Tokio 0.2
+/// runtime.
+///
+/// Returns a Warp filter and a handle to publish to subscriptions.
+pub fn run<A, Z>(
+    authenticate: A,
+    authorize: Z,
+) -> (BoxedFilter<(impl warp::Reply,)>, WebSocketPublisher)
+where
+    A: Fn(&str) -> Result<AuthenticatedUser, AuthenticationError> + Send + Sync + 'static,
+    Z: Fn(Option<AuthenticatedUser>, Subscription) -> BoxFuture<'static, bool>
+        + Send
+        + Sync
+        + 'static,
+{
+    let mut runtime = tokio::runtime::Builder::new().build().unwrap();
+
+    let web_socket_handler = WebSocketHandler::new(runtime.executor());
+    let auth = Arc::new(Auth::new(authenticate, authorize));
+
+    std::thread::spawn(move || runtime.block_on(futuresOne::future::empty::<(), ()>()));
+
+    let mut io = PubSubHandler::new(MetaIoHandler::default());
+    io.add_method_with_meta("authenticate", {
+        let auth = auth.clone();
+        move |params: Params, meta: SessionMetadata| {
+            #[derive(Deserialize)]
+            struct AuthenticateParams {
+                token: String,
+            }
+
+            let result = params.parse::<AuthenticateParams>().and_then(|params| {
+                auth.authenticate(&params.token)
+                    .map_err(auth::authentication_error)
+            });
+            futuresOne::future::result(result.map(|user| {
+                meta.set_user(user);
+                Value::Bool(true)
+            }))
+        }
+    });
+    add_kit_subscription(
+        &mut io,
+        &web_socket_handler,
+        &auth,
+        "rawMeasurements",
+        |kit_serial| Subscription::RawMeasurements { kit_serial },
+        WebSocketHandler::add_raw_measurement_subscriber,
+        WebSocketHandler::remove_raw_measurement_subscriber,
+    );
+    add_kit_subscription(
+        &mut io,
+        &web_socket_handler,
+        &auth,
+        "aggregateMeasurements",
+        |kit_serial| Subscription::AggregateMeasurements { kit_serial },
+        WebSocketHandler::add_aggregate_measurement_subscriber,
+        WebSocketHandler::remove_aggregate_measurement_subscriber,
+    );
+    add_kit_subscription(
+        &mut io,
+        &web_socket_handler,
+        &auth,
+        "media",
+        |kit_serial| Subscription::Media { kit_serial },
+        WebSocketHandler::add_media_subscriber,
+        WebSocketHandler::remove_media_subscriber,
+    );
+    add_kit_subscription(
+        &mut io,
+        &web_socket_handler,
+        &auth,
+        "kitStatus",
+        |kit_serial| Subscription::KitStatus { kit_serial },
+        WebSocketHandler::add_kit_status_subscriber,
+        WebSocketHandler::remove_kit_status_subscriber,
+    );
+    add_kit_subscription(
+        &mut io,
+        &web_socket_handler,
+        &auth,
+        "alerts",
+        |kit_serial| Subscription::Alerts { kit_serial },
+        WebSocketHandler::add_alert_subscriber,
+        WebSocketHandler::remove_alert_subscriber,
+    );
+    let io_handler: MetaIoHandler<SessionMetadata> = io.into();
+
+    #[derive(Deserialize)]
+    struct UpgradeQuery {
+        token: Option<String>,
+    }
 
     let num_sockets = Arc::new(Mutex::new(0usize));
     let filter = warp::ws()
-        .map(move |ws: warp::ws::Ws| {
-            let mut num_sockets = num_sockets.lock().unwrap();
-            let socket_id: usize = *num_sockets;
-            *num_sockets += 1;
-            let io_handler = io_handler.clone();
-
-            trace!("Websocket {} connecting", socket_id);
-            ws.on_upgrade(move |web_socket| async move {
-                debug!("Websocket {} upgraded", socket_id);
-                web_socket_session::handle_session(socket_id, web_socket, io_handler).await;
-                debug!("WebSocket {} stopped", socket_id);
-            })
-        })
+        .and(warp::query())
+        .and(warp::header::optional("Authorization"))
+        .map(
+            move |ws: warp::ws::Ws, query: UpgradeQuery, authorization: Option<String>| {
+                let token = query.token.or_else(|| {
+                    authorization.and_then(|authorization| {
+                        let parts: Vec<_> = authorization.split(' ').collect();
+                        if parts.len() == 2 && parts[0] == "Bearer" {
+                            Some(parts[1].to_owned())
+                        } else {
+                            None
+                        }
+                    })
+                });
+                let user = match token.map(|token| auth.authenticate(&token)) {
+                    Some(Ok(user)) => Some(user),
+                    Some(Err(err)) => {
+                        debug!("WebSocket upgrade with invalid access token: {:?}", err);
+                        return Box::new(warp::reply::with_status(
+                            auth::authentication_error(err).message,
+                            warp::http::StatusCode::UNAUTHORIZED,
+                        )) as Box<dyn Reply>;
+                    }
+                    None => None,
+                };
+
+                let mut num_sockets = num_sockets.lock().unwrap();
+                let socket_id: usize = *num_sockets;
+                *num_sockets += 1;
+                let io_handler = io_handler.clone();
+
+                trace!("Websocket {} connecting", socket_id);
+                Box::new(ws.on_upgrade(move |web_socket| async move {
+                    debug!("Websocket {} upgraded", socket_id);
+                    web_socket_session::handle_session(socket_id, web_socket, io_handler, user)
+                        .await;
+                    debug!("WebSocket {} stopped", socket_id);
+                })) as Box<dyn Reply>
+            },
+        )
         .boxed();
 
     let publisher = WebSocketPublisher {
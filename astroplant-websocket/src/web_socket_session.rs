@@ -3,9 +3,38 @@ use futures::{select, Sink, SinkExt, StreamExt};
 use jsonrpc_core::futures as futuresOne;
 use jsonrpc_core::MetaIoHandler;
 use log::{debug, trace};
-use std::sync::Arc;
+use std::sync::{Arc, RwLock};
 use warp::ws::{Message, WebSocket};
 
+use crate::auth::AuthenticatedUser;
+
+/// The metadata of a WebSocket session, available to JSON-RPC handlers.
+#[derive(Clone)]
+pub struct SessionMetadata {
+    session: Arc<jsonrpc_pubsub::Session>,
+    user: Arc<RwLock<Option<AuthenticatedUser>>>,
+}
+
+impl SessionMetadata {
+    /// The user the session is authenticated as, if any.
+    pub fn user(&self) -> Option<AuthenticatedUser> {
+        *self.user.read().unwrap()
+    }
+
+    /// Authenticate the session as the given user. Existing subscriptions are unaffected.
+    pub fn set_user(&self, user: AuthenticatedUser) {
+        *self.user.write().unwrap() = Some(user);
+    }
+}
+
+impl jsonrpc_core::Metadata for SessionMetadata {}
+
+impl jsonrpc_pubsub::PubSubMetadata for SessionMetadata {
+    fn session(&self) -> Option<Arc<jsonrpc_pubsub::Session>> {
+        Some(self.session.clone())
+    }
+}
+
 async fn handle_rpc_msg<S>(socket_sink: &mut S, msg: &str) -> Result<(), ()>
 where
     S: Sink<Message> + std::marker::Unpin,
@@ -20,8 +49,8 @@ where
 
 async fn handle_web_socket_msg<S>(
     socket_sink: &mut S,
-    io_handler: &MetaIoHandler<Arc<jsonrpc_pubsub::Session>>,
-    context: Arc<jsonrpc_pubsub::Session>,
+    io_handler: &MetaIoHandler<SessionMetadata>,
+    context: SessionMetadata,
     msg: &str,
 ) -> Result<(), ()>
 where
@@ -37,14 +66,18 @@ where
 pub async fn handle_session(
     socket_id: usize,
     web_socket: WebSocket,
-    io_handler: MetaIoHandler<Arc<jsonrpc_pubsub::Session>>,
+    io_handler: MetaIoHandler<SessionMetadata>,
+    user: Option<AuthenticatedUser>,
 ) {
     let (mut socket_sink, socket_stream) = web_socket.split();
     let (rpc_to_socket_sender, rpc_receiver) = futuresOne::sync::mpsc::channel::<String>(64);
 
     let mut rpc_receiver = rpc_receiver.compat().fuse();
     let mut socket_stream = socket_stream.fuse();
-    let context = Arc::new(jsonrpc_pubsub::Session::new(rpc_to_socket_sender));
+    let context = SessionMetadata {
+        session: Arc::new(jsonrpc_pubsub::Session::new(rpc_to_socket_sender)),
+        user: Arc::new(RwLock::new(user)),
+    };
 
     loop {
         select! {
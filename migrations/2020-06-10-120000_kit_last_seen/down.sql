@@ -0,0 +1,2 @@
+DROP INDEX kits_last_seen_idx;
+ALTER TABLE kits DROP COLUMN last_seen;
@@ -0,0 +1,2 @@
+ALTER TABLE kits ADD COLUMN last_seen TIMESTAMP WITH TIME ZONE;
+CREATE INDEX kits_last_seen_idx ON kits (last_seen);
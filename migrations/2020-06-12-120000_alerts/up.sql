@@ -0,0 +1,25 @@
+CREATE TABLE alert_rules (
+    id SERIAL PRIMARY KEY,
+    kit_id INTEGER NOT NULL REFERENCES kits (id) ON DELETE CASCADE,
+    peripheral_id INTEGER NOT NULL REFERENCES peripherals (id) ON DELETE CASCADE,
+    quantity_type_id INTEGER NOT NULL REFERENCES quantity_types (id) ON DELETE CASCADE,
+    condition VARCHAR NOT NULL CHECK (condition IN ('above', 'below')),
+    threshold DOUBLE PRECISION NOT NULL,
+    duration_seconds INTEGER NOT NULL CHECK (duration_seconds >= 0),
+    description VARCHAR,
+    webhook_url VARCHAR,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE
+);
+CREATE INDEX alert_rules_kit_id_idx ON alert_rules (kit_id);
+
+CREATE TABLE alerts (
+    id SERIAL PRIMARY KEY,
+    alert_rule_id INTEGER NOT NULL REFERENCES alert_rules (id) ON DELETE CASCADE,
+    kit_id INTEGER NOT NULL REFERENCES kits (id) ON DELETE CASCADE,
+    datetime_start TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_fired TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_resolved TIMESTAMP WITH TIME ZONE,
+    value DOUBLE PRECISION NOT NULL
+);
+CREATE INDEX alerts_kit_id_datetime_fired_idx ON alerts (kit_id, datetime_fired, id);
+CREATE INDEX alerts_unresolved_idx ON alerts (alert_rule_id) WHERE datetime_resolved IS NULL;
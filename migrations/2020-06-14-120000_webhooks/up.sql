@@ -0,0 +1,28 @@
+CREATE TABLE webhooks (
+    id SERIAL PRIMARY KEY,
+    kit_id INTEGER NOT NULL REFERENCES kits (id) ON DELETE CASCADE,
+    url VARCHAR NOT NULL,
+    secret VARCHAR NOT NULL,
+    events VARCHAR[] NOT NULL,
+    enabled BOOLEAN NOT NULL DEFAULT TRUE,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT now()
+);
+CREATE INDEX webhooks_kit_id_idx ON webhooks (kit_id);
+
+CREATE TABLE webhook_deliveries (
+    id SERIAL PRIMARY KEY,
+    webhook_id INTEGER NOT NULL REFERENCES webhooks (id) ON DELETE CASCADE,
+    event VARCHAR NOT NULL,
+    payload JSONB NOT NULL,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    -- Null once the delivery succeeded or was given up on.
+    datetime_next_attempt TIMESTAMP WITH TIME ZONE,
+    datetime_delivered TIMESTAMP WITH TIME ZONE,
+    last_status_code INTEGER,
+    last_error VARCHAR
+);
+CREATE INDEX webhook_deliveries_webhook_id_datetime_created_idx
+    ON webhook_deliveries (webhook_id, datetime_created, id);
+CREATE INDEX webhook_deliveries_pending_idx
+    ON webhook_deliveries (datetime_next_attempt) WHERE datetime_next_attempt IS NOT NULL;
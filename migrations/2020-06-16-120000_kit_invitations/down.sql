@@ -0,0 +1 @@
+DROP TABLE kit_invitations;
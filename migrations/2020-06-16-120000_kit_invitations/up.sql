@@ -0,0 +1,13 @@
+CREATE TABLE kit_invitations (
+    id SERIAL PRIMARY KEY,
+    kit_id INTEGER NOT NULL REFERENCES kits (id) ON DELETE CASCADE,
+    code VARCHAR NOT NULL UNIQUE,
+    created_by INTEGER REFERENCES users (id) ON DELETE SET NULL,
+    access_super BOOLEAN NOT NULL,
+    access_configure BOOLEAN NOT NULL,
+    max_uses INTEGER NOT NULL CHECK (max_uses > 0),
+    uses INTEGER NOT NULL DEFAULT 0 CHECK (uses >= 0),
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL DEFAULT NOW(),
+    datetime_expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX kit_invitations_kit_id_idx ON kit_invitations (kit_id);
@@ -0,0 +1,9 @@
+CREATE TABLE sessions (
+    id UUID PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    user_agent VARCHAR,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_last_used TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX sessions_user_id_idx ON sessions (user_id);
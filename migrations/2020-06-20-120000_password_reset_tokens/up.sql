@@ -0,0 +1,8 @@
+CREATE TABLE password_reset_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
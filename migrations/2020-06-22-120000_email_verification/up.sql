@@ -0,0 +1,11 @@
+ALTER TABLE users ADD COLUMN email_verified BOOLEAN NOT NULL DEFAULT FALSE;
+
+CREATE TABLE email_verification_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    email_address VARCHAR NOT NULL,
+    token_hash VARCHAR(64) NOT NULL UNIQUE,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX email_verification_tokens_user_id_idx ON email_verification_tokens (user_id);
@@ -0,0 +1,7 @@
+CREATE TABLE login_failures (
+    username VARCHAR PRIMARY KEY,
+    failures INTEGER NOT NULL,
+    datetime_last_attempt TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_blocked_until TIMESTAMP WITH TIME ZONE
+);
+CREATE INDEX login_failures_datetime_last_attempt_idx ON login_failures (datetime_last_attempt);
@@ -0,0 +1,11 @@
+CREATE TABLE personal_access_tokens (
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    name VARCHAR NOT NULL,
+    kit_ids INTEGER[] NOT NULL,
+    actions VARCHAR[] NOT NULL,
+    datetime_created TIMESTAMP WITH TIME ZONE NOT NULL,
+    datetime_last_used TIMESTAMP WITH TIME ZONE,
+    datetime_expires TIMESTAMP WITH TIME ZONE NOT NULL
+);
+CREATE INDEX personal_access_tokens_user_id_idx ON personal_access_tokens (user_id);
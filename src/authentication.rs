@@ -1,15 +1,37 @@
-use crate::problem::{AccessTokenProblemCategory::*, Problem};
+use crate::problem::{AccessTokenProblemCategory::*, Problem, FORBIDDEN};
 
-use crate::models::UserId;
+use crate::models::{PersonalAccessTokenId, UserId};
 
 use astroplant_auth::token;
 use warp::{Filter, Rejection};
 
-/// A filter to authenticate a user through a normal token in the Authorization header.
-/// If there is no Authorization header, returns None.
+/// A user authenticated through a token in the Authorization header.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct AuthenticatedUser {
+    pub user_id: UserId,
+    /// The personal access token the user authenticated with, if any. Personal access tokens
+    /// only permit the kit actions they are scoped to, which is checked when checking the
+    /// permission to perform kit actions.
+    pub personal_access_token_id: Option<PersonalAccessTokenId>,
+}
+
+impl AuthenticatedUser {
+    /// The user id if the user authenticated with the full power of their account, i.e., not
+    /// with a personal access token.
+    pub fn full_user_id(&self) -> Option<UserId> {
+        match self.personal_access_token_id {
+            Some(_) => None,
+            None => Some(self.user_id),
+        }
+    }
+}
+
+/// A filter to authenticate a user through a normal token or a personal access token in the
+/// Authorization header. If there is no Authorization header, returns None.
 ///
 /// Rejects the request if the Authorization header is malformed.
-pub fn option_by_token() -> impl Filter<Extract = (Option<UserId>,), Error = Rejection> + Copy {
+pub fn option_by_token(
+) -> impl Filter<Extract = (Option<AuthenticatedUser>,), Error = Rejection> + Copy {
     warp::header("Authorization")
         .map(|a| Some(a))
         .or_else(|_| futures::future::ok::<_, Rejection>((None,)))
@@ -33,7 +55,7 @@ pub fn option_by_token() -> impl Filter<Extract = (Option<UserId>,), Error = Rej
 
                     let access_token = parts[1];
                     let authentication_state: token::AuthenticationState =
-                        match token_signer.decode_access_token(&access_token) {
+                        match token_signer.decode_authorization_token(&access_token) {
                             Ok(authentication_state) => authentication_state,
                             Err(token::Error::Expired) => {
                                 return Err(warp::reject::custom(Problem::AuthorizationHeader {
@@ -48,7 +70,12 @@ pub fn option_by_token() -> impl Filter<Extract = (Option<UserId>,), Error = Rej
                         };
 
                     trace!("User authenticated with state {:?}", authentication_state);
-                    Ok(Some(UserId(authentication_state.user_id)))
+                    Ok(Some(AuthenticatedUser {
+                        user_id: UserId(authentication_state.user_id),
+                        personal_access_token_id: authentication_state
+                            .personal_access_token_id
+                            .map(PersonalAccessTokenId),
+                    }))
                 } else {
                     Ok(None)
                 }
@@ -57,13 +84,17 @@ pub fn option_by_token() -> impl Filter<Extract = (Option<UserId>,), Error = Rej
 }
 
 /// A filter to authenticate a user through a normal token in the Accept header.
-/// Rejects the request if the Authorization header is missing or malformed.
+/// Rejects the request if the Authorization header is missing or malformed, and rejects
+/// personal access tokens, which cannot act on behalf of the user outside of their scope.
 pub fn by_token() -> impl Filter<Extract = (UserId,), Error = Rejection> + Copy {
-    option_by_token().and_then(|user: Option<UserId>| {
-        futures::future::ready(
-            user.ok_or(warp::reject::custom(Problem::AuthorizationHeader {
+    option_by_token().and_then(|user: Option<AuthenticatedUser>| {
+        futures::future::ready(match user {
+            Some(user) => user
+                .full_user_id()
+                .ok_or(warp::reject::custom(FORBIDDEN)),
+            None => Err(warp::reject::custom(Problem::AuthorizationHeader {
                 category: Missing,
             })),
-        )
+        })
     })
 }
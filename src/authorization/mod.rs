@@ -1,5 +1,5 @@
 use crate::models::{Kit, KitMembership, User};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 pub trait Permission {
     type Actor;
@@ -8,22 +8,51 @@ pub trait Permission {
     fn permitted(self, actor: &Self::Actor, object: &Self::Object) -> bool;
 }
 
-#[derive(Serialize, Copy, Clone, Debug, EnumIter)]
+#[derive(
+    Serialize, Deserialize, Copy, Clone, Debug, PartialEq, Eq, EnumIter, EnumString, IntoStaticStr,
+)]
 #[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
 pub enum KitAction {
     View,
     SubscribeRealTimeMeasurements,
     ResetPassword,
     EditDetails,
     EditConfiguration,
+    DeleteMedia,
+    EditAlerts,
+    EditWebhooks,
     EditMembers,
     SetSuperMember,
+    Delete,
     RpcVersion,
     RpcUptime,
     RpcPeripheralCommand,
     RpcPeripheralCommandLock,
 }
 
+impl KitAction {
+    /// Whether the action can be granted to personal access tokens. Managing the kit's members,
+    /// resetting the kit's password and deleting the kit require the user themselves.
+    pub fn grantable_to_token(self) -> bool {
+        use KitAction::*;
+        match self {
+            ResetPassword | EditMembers | SetSuperMember | Delete => false,
+            View
+            | SubscribeRealTimeMeasurements
+            | EditDetails
+            | EditConfiguration
+            | DeleteMedia
+            | EditAlerts
+            | EditWebhooks
+            | RpcVersion
+            | RpcUptime
+            | RpcPeripheralCommand
+            | RpcPeripheralCommandLock => true,
+        }
+    }
+}
+
 pub enum KitUser {
     Anonymous,
     User(User),
@@ -44,8 +73,10 @@ impl Permission for KitAction {
             },
             UserWithMembership(_user, membership) => match self {
                 View | SubscribeRealTimeMeasurements => true,
-                EditDetails | EditConfiguration => membership.access_configure,
-                ResetPassword | EditMembers | SetSuperMember => membership.access_super,
+                EditDetails | EditConfiguration | DeleteMedia | EditAlerts | EditWebhooks => {
+                    membership.access_configure
+                }
+                ResetPassword | EditMembers | SetSuperMember | Delete => membership.access_super,
                 RpcVersion | RpcUptime | RpcPeripheralCommand | RpcPeripheralCommandLock => {
                     membership.access_super
                 }
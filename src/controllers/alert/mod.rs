@@ -0,0 +1,375 @@
+use futures::future::FutureExt;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+use crate::cursors;
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, InvalidParameters};
+use crate::response::{Response, ResponseBuilder};
+use crate::utils::outbound;
+use crate::{authentication, authorization, helpers, models, views};
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up alerts router.");
+
+    kit_alerts(pg.clone())
+        .or(kit_alert_rules(pg.clone()))
+        .unify()
+        .or(create_alert_rule(pg.clone()))
+        .unify()
+        .or(patch_or_delete_alert_rule(pg))
+        .unify()
+        .boxed()
+}
+
+/// Check the parts of an alert rule that are not checked by its validator. The webhook URL's host
+/// must resolve to public addresses only.
+async fn check_alert_rule(alert_rule: &models::NewAlertRule) -> AppResult<()> {
+    let mut invalid_parameters = match alert_rule.validate() {
+        Ok(()) => InvalidParameters::new(),
+        Err(validation_errors) => InvalidParameters::from(validation_errors),
+    };
+
+    if alert_rule
+        .condition
+        .parse::<models::AlertCondition>()
+        .is_err()
+    {
+        invalid_parameters.add("condition", InvalidParameterReason::Other);
+    }
+    if !alert_rule.threshold.is_finite() {
+        invalid_parameters.add("threshold", InvalidParameterReason::Other);
+    }
+    if let Some(webhook_url) = &alert_rule.webhook_url {
+        match outbound::check_url(webhook_url).await {
+            Ok(_) => {}
+            Err(outbound::UrlError::Invalid) => {
+                invalid_parameters.add("webhookUrl", InvalidParameterReason::MustBeUrl)
+            }
+            Err(_) => invalid_parameters.add("webhookUrl", InvalidParameterReason::Other),
+        }
+    }
+
+    if !invalid_parameters.is_empty() {
+        return Err(invalid_parameters.into_problem());
+    }
+
+    Ok(())
+}
+
+/// Get an alert rule, checking the user is permitted to edit the alerts of the rule's kit.
+async fn alert_rule_permission_or_forbidden(
+    pg: PgPool,
+    user_id: Option<authentication::AuthenticatedUser>,
+    kit_serial: String,
+    alert_rule_id: models::AlertRuleId,
+) -> AppResult<models::AlertRule> {
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::EditAlerts,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    helpers::threadpool(
+        move || match models::AlertRule::by_id(&conn, alert_rule_id)? {
+            Some(alert_rule) if alert_rule.kit_id == kit.id => Ok(alert_rule),
+            _ => Err(problem::NOT_FOUND),
+        },
+    )
+    .await
+}
+
+/// Handles the `GET /kits/{kitSerial}/alerts` route.
+fn kit_alerts(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Query {
+        cursor: Option<String>,
+        rule: Option<i32>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        query: Query,
+    ) -> AppResult<Response> {
+        let mut out_query = query.clone();
+        let cursor = (&query).cursor.as_ref().map(|s| s.parse()).transpose()?;
+        let base_uri = format!("/kits/{}/alerts", kit_serial);
+
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::View,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        let mut response = ResponseBuilder::ok();
+        let alerts = helpers::threadpool(move || {
+            models::Alert::page(&conn, kit.get_id(), query.rule, cursor)
+        })
+        .await?;
+
+        if let Some(next_cursor) = cursors::Alerts::next_from_page(&alerts) {
+            out_query.cursor = Some(next_cursor.into());
+            let next_page_uri = format!(
+                "{}?{}",
+                base_uri,
+                serde_urlencoded::to_string(&out_query).unwrap()
+            );
+            response = response.link(&next_page_uri, "next");
+        }
+
+        let body: Vec<views::Alert> = alerts.into_iter().map(views::Alert::from).collect();
+        Ok(response.body(body))
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "alerts"))
+        .and(authentication::option_by_token())
+        .and(warp::query())
+        .and_then(move |kit_serial, user_id, query: Query| {
+            implementation(pg.clone(), kit_serial, user_id, query).never_error()
+        })
+}
+
+/// Handles the `GET /kits/{kitSerial}/alerts/rules` route.
+fn kit_alert_rules(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::EditAlerts,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let alert_rules = models::AlertRule::alert_rules_of_kit_id(&conn, kit.get_id())?;
+            let body: Vec<views::AlertRule> = alert_rules
+                .into_iter()
+                .map(views::AlertRule::from)
+                .collect();
+            Ok(ResponseBuilder::ok().body(body))
+        })
+        .await
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "alerts" / "rules"))
+        .and(authentication::option_by_token())
+        .and_then(move |kit_serial, user_id| {
+            implementation(pg.clone(), kit_serial, user_id).never_error()
+        })
+}
+
+/// Handles the `POST /kits/{kitSerial}/alerts/rules` route.
+fn create_alert_rule(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct AlertRule {
+        peripheral_id: i32,
+        quantity_type_id: i32,
+        condition: String,
+        threshold: f64,
+        #[serde(default)]
+        duration_seconds: i32,
+        description: Option<String>,
+        webhook_url: Option<String>,
+        enabled: Option<bool>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        alert_rule: AlertRule,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::EditAlerts,
+        )
+        .await?;
+
+        let new_alert_rule = models::NewAlertRule {
+            kit_id: kit.id,
+            peripheral_id: alert_rule.peripheral_id,
+            quantity_type_id: alert_rule.quantity_type_id,
+            condition: alert_rule.condition,
+            threshold: alert_rule.threshold,
+            duration_seconds: alert_rule.duration_seconds,
+            description: alert_rule.description,
+            webhook_url: alert_rule.webhook_url,
+            enabled: alert_rule.enabled.unwrap_or(true),
+        };
+        check_alert_rule(&new_alert_rule).await?;
+
+        let conn = pg.get().await?;
+        let created_alert_rule = helpers::threadpool(move || {
+            conn.transaction(|| {
+                let mut invalid_parameters = InvalidParameters::new();
+                match models::Peripheral::by_id(
+                    &conn,
+                    models::PeripheralId(new_alert_rule.peripheral_id),
+                )? {
+                    Some(peripheral) if peripheral.kit_id == new_alert_rule.kit_id => {}
+                    _ => invalid_parameters.add("peripheralId", InvalidParameterReason::NotFound),
+                }
+                if let Err(diesel::result::Error::NotFound) =
+                    models::QuantityType::by_id(&conn, new_alert_rule.quantity_type_id)
+                {
+                    invalid_parameters.add("quantityTypeId", InvalidParameterReason::NotFound);
+                }
+                if !invalid_parameters.is_empty() {
+                    return Err(invalid_parameters.into_problem());
+                }
+
+                Ok(new_alert_rule.create(&conn)?)
+            })
+        })
+        .await?;
+
+        Ok(ResponseBuilder::created().body(views::AlertRule::from(created_alert_rule)))
+    }
+
+    warp::post()
+        .and(warp::path!("kits" / String / "alerts" / "rules"))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  alert_rule: AlertRule| {
+                implementation(pg.clone(), kit_serial, user_id, alert_rule).never_error()
+            },
+        )
+}
+
+/// Handles the `PATCH` and `DELETE /kits/{kitSerial}/alerts/rules/{alertRuleId}` routes.
+fn patch_or_delete_alert_rule(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use crate::utils::deserialize_some;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct AlertRulePatch {
+        condition: Option<String>,
+        threshold: Option<f64>,
+        duration_seconds: Option<i32>,
+        #[serde(default, deserialize_with = "deserialize_some")]
+        description: Option<Option<String>>,
+        #[serde(default, deserialize_with = "deserialize_some")]
+        webhook_url: Option<Option<String>>,
+        enabled: Option<bool>,
+    }
+
+    async fn patch_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        alert_rule_id: models::AlertRuleId,
+        user_id: Option<authentication::AuthenticatedUser>,
+        alert_rule_patch: AlertRulePatch,
+    ) -> AppResult<Response> {
+        let alert_rule =
+            alert_rule_permission_or_forbidden(pg.clone(), user_id, kit_serial, alert_rule_id)
+                .await?;
+
+        let update_alert_rule = models::UpdateAlertRule {
+            id: alert_rule.id,
+            condition: alert_rule_patch.condition,
+            threshold: alert_rule_patch.threshold,
+            duration_seconds: alert_rule_patch.duration_seconds,
+            description: alert_rule_patch.description,
+            webhook_url: alert_rule_patch.webhook_url,
+            enabled: alert_rule_patch.enabled,
+        };
+        check_alert_rule(&update_alert_rule.apply(&alert_rule)).await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let patched_alert_rule = update_alert_rule.update(&conn)?;
+            Ok(ResponseBuilder::ok().body(views::AlertRule::from(patched_alert_rule)))
+        })
+        .await
+    }
+
+    async fn delete_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        alert_rule_id: models::AlertRuleId,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let alert_rule =
+            alert_rule_permission_or_forbidden(pg.clone(), user_id, kit_serial, alert_rule_id)
+                .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            alert_rule.delete(&conn)?;
+            Ok(ResponseBuilder::ok().empty())
+        })
+        .await
+    }
+
+    let pg2 = pg.clone();
+    (warp::patch()
+        .and(warp::path!("kits" / String / "alerts" / "rules" / i32))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  alert_rule_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  alert_rule_patch: AlertRulePatch| {
+                patch_implementation(
+                    pg.clone(),
+                    kit_serial,
+                    models::AlertRuleId(alert_rule_id),
+                    user_id,
+                    alert_rule_patch,
+                )
+                .never_error()
+            },
+        ))
+    .or(warp::delete()
+        .and(warp::path!("kits" / String / "alerts" / "rules" / i32))
+        .and(authentication::option_by_token())
+        .and_then(
+            move |kit_serial: String,
+                  alert_rule_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>| {
+                delete_implementation(
+                    pg2.clone(),
+                    kit_serial,
+                    models::AlertRuleId(alert_rule_id),
+                    user_id,
+                )
+                .never_error()
+            },
+        ))
+    .unify()
+}
@@ -3,24 +3,35 @@ use serde::{Deserialize, Serialize};
 use warp::{filters::BoxedFilter, path, Filter, Rejection};
 
 use crate::database::PgPool;
-use crate::problem::{self, AppResult, Problem};
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
 use crate::response::{Response, ResponseBuilder};
 use crate::{authentication, helpers, models, views};
 
-pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+pub fn router(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+    kit_offline_after: chrono::Duration,
+    kit_creation_requires_verified_email: bool,
+) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     trace!("Setting up kits router.");
 
     (warp::get().and(kit_by_serial(pg.clone())))
+        .or(warp::get().and(kit_status(pg.clone(), kit_offline_after)))
+        .unify()
         .or(warp::post().and(reset_password(pg.clone())))
         .unify()
         .or(warp::path::end().and(warp::get()).and(kits(pg.clone())))
         .unify()
         .or(warp::path::end()
             .and(warp::post())
-            .and(create_kit(pg.clone())))
+            .and(create_kit(pg.clone(), kit_creation_requires_verified_email)))
+        .unify()
+        .or(patch_kit(pg.clone()))
+        .unify()
+        .or(delete_kit(pg.clone(), object_store))
         .unify()
-        .or(patch_kit(pg))
+        .or(warp::post().and(transfer_kit(pg)))
         .unify()
         .boxed()
 }
@@ -64,7 +75,7 @@ pub fn kit_by_serial(
     async fn implementation(
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
             pg,
@@ -78,9 +89,46 @@ pub fn kit_by_serial(
 
     path!(String)
         .and(authentication::option_by_token())
-        .and_then(move |kit_serial: String, user_id: Option<models::UserId>| {
-            implementation(pg.clone(), kit_serial, user_id).never_error()
-        })
+        .and_then(
+            move |kit_serial: String, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(pg.clone(), kit_serial, user_id).never_error()
+            },
+        )
+}
+
+/// Handles the `GET /kits/{kitSerial}/status` route.
+pub fn kit_status(
+    pg: PgPool,
+    kit_offline_after: chrono::Duration,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_offline_after: chrono::Duration,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg,
+            user_id,
+            kit_serial,
+            crate::authorization::KitAction::View,
+        )
+        .await?;
+        Ok(ResponseBuilder::ok().body(views::KitStatus::new(
+            kit.serial,
+            kit.last_seen,
+            kit_offline_after,
+            chrono::Utc::now(),
+        )))
+    }
+
+    path!(String / "status")
+        .and(authentication::option_by_token())
+        .and_then(
+            move |kit_serial: String, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(pg.clone(), kit_offline_after, kit_serial, user_id).never_error()
+            },
+        )
 }
 
 /// Handles the `POST /kits/{kitSerial}/password` route.
@@ -90,7 +138,7 @@ pub fn reset_password(
     async fn implementation(
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
             pg.clone(),
@@ -112,14 +160,17 @@ pub fn reset_password(
 
     path!(String / "password")
         .and(authentication::option_by_token())
-        .and_then(move |kit_serial: String, user_id: Option<models::UserId>| {
-            implementation(pg.clone(), kit_serial, user_id).never_error()
-        })
+        .and_then(
+            move |kit_serial: String, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(pg.clone(), kit_serial, user_id).never_error()
+            },
+        )
 }
 
 /// Handles the `POST /kits` route.
 pub fn create_kit(
     pg: PgPool,
+    requires_verified_email: bool,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
     use bigdecimal::{BigDecimal, FromPrimitive};
     use diesel::Connection;
@@ -143,7 +194,12 @@ pub fn create_kit(
         password: String,
     }
 
-    async fn implementation(pg: PgPool, user_id: models::UserId, kit: Kit) -> AppResult<Response> {
+    async fn implementation(
+        pg: PgPool,
+        requires_verified_email: bool,
+        user_id: models::UserId,
+        kit: Kit,
+    ) -> AppResult<Response> {
         let (new_kit, password) = models::NewKit::new_with_generated_password(
             kit.name,
             kit.description,
@@ -161,6 +217,13 @@ pub fn create_kit(
         let conn = pg.get().await?;
         helpers::threadpool(move || {
             conn.transaction(|| {
+                if requires_verified_email {
+                    let user = models::User::by_id(&conn, user_id)?.ok_or(problem::FORBIDDEN)?;
+                    if !user.email_verified {
+                        return Err(problem::FORBIDDEN);
+                    }
+                }
+
                 let created_kit: models::Kit = new_kit.create(&conn)?;
                 let kit_serial = created_kit.serial;
                 debug!("Created kit \"{}\"", kit_serial);
@@ -182,7 +245,7 @@ pub fn create_kit(
     authentication::by_token()
         .and(crate::helpers::deserialize())
         .and_then(move |user_id: models::UserId, kit: Kit| {
-            implementation(pg.clone(), user_id, kit).never_error()
+            implementation(pg.clone(), requires_verified_email, user_id, kit).never_error()
         })
 }
 
@@ -212,7 +275,7 @@ fn patch_kit(
     async fn implementation(
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_patch: KitPatch,
     ) -> AppResult<Response> {
         let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
@@ -251,8 +314,193 @@ fn patch_kit(
         .and(authentication::option_by_token())
         .and(crate::helpers::deserialize())
         .and_then(
-            move |kit_serial: String, user_id: Option<models::UserId>, kit_patch: KitPatch| {
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  kit_patch: KitPatch| {
                 implementation(pg.clone(), kit_serial, user_id, kit_patch).never_error()
             },
         )
 }
+
+/// Handles the `DELETE /kits/{kitSerial}` route.
+///
+/// The kit's serial must be given in the request body to confirm the deletion. The kit, its media
+/// and all its other data are deleted in one transaction. The media's content is removed from the
+/// object store afterwards, in the background, retrying failed deletions.
+fn delete_kit(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Confirmation {
+        serial: String,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        object_store: astroplant_object::ObjectStore,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        confirmation: Confirmation,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            crate::authorization::KitAction::Delete,
+        )
+        .await?;
+
+        if confirmation.serial != kit.serial {
+            return Err(InvalidParameterReason::Other
+                .singleton("serial")
+                .into_problem());
+        }
+
+        info!("Deleting kit \"{}\"", kit.serial);
+        let kit_serial = kit.serial.clone();
+        let conn = pg.get().await?;
+        let object_names = helpers::threadpool(move || {
+            conn.transaction(|| {
+                let object_names: Vec<String> =
+                    models::Media::delete_all_of_kit(&conn, kit.get_id())?
+                        .iter()
+                        .flat_map(crate::media::object_names)
+                        .collect();
+                kit.delete(&conn)?;
+                Ok::<_, Problem>(object_names)
+            })
+        })
+        .await?;
+
+        // The objects are deleted only once the kit is gone, such that the kit's media are never
+        // listed without content. Deleting them can take a while, so it does not hold up the
+        // response.
+        tokio::spawn(async move {
+            crate::media::delete_objects(&object_store, &kit_serial, object_names).await;
+        });
+
+        Ok(ResponseBuilder::ok().empty())
+    }
+
+    warp::delete()
+        .and(path!(String))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  confirmation: Confirmation| {
+                implementation(
+                    pg.clone(),
+                    object_store.clone(),
+                    kit_serial,
+                    user_id,
+                    confirmation,
+                )
+                .never_error()
+            },
+        )
+}
+
+/// Handles the `POST /kits/{kitSerial}/transfer` route.
+///
+/// Hands super membership of the kit to another user, who becomes a member if they are not one
+/// yet. The transferring user keeps configuration access, or leaves the kit if requested.
+fn transfer_kit(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Transfer {
+        username: String,
+        #[serde(default)]
+        leave: bool,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        transfer: Transfer,
+    ) -> AppResult<Response> {
+        let (_, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            crate::authorization::KitAction::SetSuperMember,
+        )
+        .await?;
+        let membership = membership.ok_or(problem::FORBIDDEN)?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let user = match models::User::by_username(&conn, &transfer.username)? {
+                    Some(user) if user.id != membership.user_id => user,
+                    Some(_) => {
+                        return Err(InvalidParameterReason::Other
+                            .singleton("username")
+                            .into_problem())
+                    }
+                    None => {
+                        return Err(InvalidParameterReason::NotFound
+                            .singleton("username")
+                            .into_problem())
+                    }
+                };
+
+                let new_membership = match models::KitMembership::by_user_id_and_kit_id(
+                    &conn,
+                    user.get_id(),
+                    kit.get_id(),
+                )? {
+                    Some(existing) => models::UpdateKitMembership {
+                        id: existing.id,
+                        access_super: Some(true),
+                        access_configure: Some(true),
+                    }
+                    .update(&conn)?,
+                    None => models::NewKitMembership::new(user.get_id(), kit.get_id(), true, true)
+                        .create(&conn)?,
+                };
+
+                if transfer.leave {
+                    membership.delete(&conn)?;
+                } else {
+                    models::UpdateKitMembership {
+                        id: membership.id,
+                        access_super: Some(false),
+                        access_configure: Some(true),
+                    }
+                    .update(&conn)?;
+                }
+
+                Ok::<_, Problem>(
+                    ResponseBuilder::ok().body(
+                        views::KitMembership::from(new_membership)
+                            .with_user(views::User::from(user))
+                            .with_kit(kit.serial),
+                    ),
+                )
+            })
+        })
+        .await
+    }
+
+    path!(String / "transfer")
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  transfer: Transfer| {
+                implementation(pg.clone(), kit_serial, user_id, transfer).never_error()
+            },
+        )
+}
@@ -30,7 +30,7 @@ fn configurations_by_kit_serial(
 
     async fn implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_serial: String,
     ) -> AppResult<Response> {
         let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
@@ -87,7 +87,7 @@ fn create_configuration(
 
     async fn implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_serial: String,
         configuration: Configuration,
     ) -> AppResult<Response> {
@@ -137,7 +137,7 @@ fn patch_configuration(
 
     async fn implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_configuration_id: models::KitConfigurationId,
         kit_configuration_patch: KitConfigurationPatch,
     ) -> AppResult<Response> {
@@ -176,7 +176,11 @@ fn patch_configuration(
             },
         };
 
-        let conn = pg.get().await?;
+        let activated = patch.active == Some(true) && !kit_configuration.active;
+        let kit_id = kit.get_id();
+        let kit_serial = kit.serial.clone();
+
+        let conn = pg.clone().get().await?;
         let patched_configuration = helpers::threadpool(move || {
             conn.transaction(|| {
                 if let Some(active) = patch.active {
@@ -188,7 +192,19 @@ fn patch_configuration(
             })
         })
         .await?;
-        Ok(ResponseBuilder::ok().body(views::KitConfiguration::from(patched_configuration)))
+        let patched_configuration = views::KitConfiguration::from(patched_configuration);
+
+        if activated {
+            crate::webhook::publish(
+                pg,
+                kit_id,
+                kit_serial,
+                models::WebhookEvent::ConfigurationActivated,
+                &patched_configuration,
+            );
+        }
+
+        Ok(ResponseBuilder::ok().body(patched_configuration))
     }
 
     warp::patch()
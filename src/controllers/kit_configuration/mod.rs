@@ -6,7 +6,7 @@ use warp::{filters::BoxedFilter, Filter};
 use crate::database::PgPool;
 use crate::problem::{self, AppResult};
 use crate::response::Response;
-use crate::{authorization, helpers, models};
+use crate::{authentication, authorization, helpers, models};
 
 pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
@@ -53,7 +53,7 @@ async fn get_models_from_peripheral_id(
 
 async fn authorize(
     pg: PgPool,
-    user_id: Option<models::UserId>,
+    user_id: Option<authentication::AuthenticatedUser>,
     kit: &models::Kit,
     action: authorization::KitAction,
 ) -> AppResult<(Option<models::User>, Option<models::KitMembership>)> {
@@ -64,7 +64,7 @@ fn add_peripheral_to_configuration(
 
     async fn implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_configuration_id: models::KitConfigurationId,
         peripheral: Peripheral,
     ) -> AppResult<Response> {
@@ -155,7 +155,7 @@ fn patch_or_delete_peripheral(
     /// Check user authorization and make sure the configuration has never been activated.
     async fn base(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         peripheral_id: models::PeripheralId,
     ) -> AppResult<(models::Kit, models::KitConfiguration, models::Peripheral)> {
         let (kit, kit_configuration, peripheral) =
@@ -179,7 +179,7 @@ fn patch_or_delete_peripheral(
 
     async fn patch_implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         peripheral_id: models::PeripheralId,
         peripheral_patch: PeripheralPatch,
     ) -> AppResult<Response> {
@@ -224,7 +224,7 @@ fn patch_or_delete_peripheral(
 
     async fn delete_implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         peripheral_id: models::PeripheralId,
     ) -> AppResult<Response> {
         let (_, _, peripheral) = base(pg.clone(), user_id, peripheral_id).await?;
@@ -244,7 +244,7 @@ fn patch_or_delete_peripheral(
         .and(crate::helpers::deserialize())
         .and_then(
             move |peripheral_id: i32,
-                  user_id: Option<models::UserId>,
+                  user_id: Option<authentication::AuthenticatedUser>,
                   peripheral_patch: PeripheralPatch| {
                 patch_implementation(
                     pg.clone(),
@@ -258,9 +258,11 @@ fn patch_or_delete_peripheral(
     .or(warp::delete()
         .and(warp::path!("peripherals" / i32))
         .and(authentication::option_by_token())
-        .and_then(move |peripheral_id: i32, user_id: Option<models::UserId>| {
-            delete_implementation(pg2.clone(), user_id, models::PeripheralId(peripheral_id))
-                .never_error()
-        }))
+        .and_then(
+            move |peripheral_id: i32, user_id: Option<authentication::AuthenticatedUser>| {
+                delete_implementation(pg2.clone(), user_id, models::PeripheralId(peripheral_id))
+                    .never_error()
+            },
+        ))
     .unify()
 }
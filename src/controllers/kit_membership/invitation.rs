@@ -0,0 +1,262 @@
+use chrono::{DateTime, Utc};
+use futures::future::FutureExt;
+use serde::Deserialize;
+use validator::Validate;
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, InvalidParameters, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models, views};
+
+/// The time invitations are valid for, if no expiry is given.
+const DEFAULT_VALIDITY_DAYS: i64 = 7;
+
+/// The maximum time invitations can be valid for.
+const MAX_VALIDITY_DAYS: i64 = 90;
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up kit invitations router.");
+
+    kit_invitations(pg.clone())
+        .or(create_kit_invitation(pg.clone()))
+        .unify()
+        .or(delete_kit_invitation(pg.clone()))
+        .unify()
+        .or(redeem_kit_invitation(pg))
+        .unify()
+        .boxed()
+}
+
+/// Handles the `GET /kits/{kitSerial}/invitations` route.
+fn kit_invitations(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::EditMembers,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let invitations = models::KitInvitation::invitations_of_kit_id(&conn, kit.get_id())?;
+            let body: Vec<views::KitInvitation> = invitations
+                .into_iter()
+                .map(views::KitInvitation::from)
+                .collect();
+            Ok(ResponseBuilder::ok().body(body))
+        })
+        .await
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "invitations"))
+        .and(authentication::option_by_token())
+        .and_then(move |kit_serial, user_id| {
+            implementation(pg.clone(), kit_serial, user_id).never_error()
+        })
+}
+
+/// Handles the `POST /kits/{kitSerial}/invitations` route.
+///
+/// Only super members may create invitations granting super access.
+fn create_kit_invitation(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Invitation {
+        #[serde(default)]
+        access_super: bool,
+        #[serde(default)]
+        access_configure: bool,
+        max_uses: Option<i32>,
+        datetime_expires: Option<DateTime<Utc>>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        invitation: Invitation,
+    ) -> AppResult<Response> {
+        let (user, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::EditMembers,
+        )
+        .await?;
+        let user_id = user.as_ref().ok_or(problem::FORBIDDEN)?.get_id();
+        if invitation.access_super {
+            super::member_permission_or_forbidden(
+                user,
+                membership,
+                &kit,
+                KitAction::SetSuperMember,
+            )?;
+        }
+
+        let now = Utc::now();
+        let datetime_expires = invitation
+            .datetime_expires
+            .unwrap_or_else(|| now + chrono::Duration::days(DEFAULT_VALIDITY_DAYS));
+        let new_invitation = models::NewKitInvitation::new(
+            kit.get_id(),
+            user_id,
+            invitation.access_super,
+            invitation.access_configure,
+            invitation.max_uses.unwrap_or(1),
+            datetime_expires,
+        );
+
+        let mut invalid_parameters = match new_invitation.validate() {
+            Ok(()) => InvalidParameters::new(),
+            Err(validation_errors) => InvalidParameters::from(validation_errors),
+        };
+        if datetime_expires <= now
+            || datetime_expires > now + chrono::Duration::days(MAX_VALIDITY_DAYS)
+        {
+            invalid_parameters.add("datetimeExpires", InvalidParameterReason::Other);
+        }
+        if !invalid_parameters.is_empty() {
+            return Err(invalid_parameters.into_problem());
+        }
+
+        let conn = pg.get().await?;
+        let created_invitation = helpers::threadpool(move || new_invitation.create(&conn)).await?;
+        Ok(ResponseBuilder::created().body(views::KitInvitation::from(created_invitation)))
+    }
+
+    warp::post()
+        .and(warp::path!("kits" / String / "invitations"))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  invitation: Invitation| {
+                implementation(pg.clone(), kit_serial, user_id, invitation).never_error()
+            },
+        )
+}
+
+/// Handles the `DELETE /kits/{kitSerial}/invitations/{kitInvitationId}` route.
+fn delete_kit_invitation(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        kit_invitation_id: models::KitInvitationId,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::EditMembers,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(
+            move || match models::KitInvitation::by_id(&conn, kit_invitation_id)? {
+                Some(invitation) if invitation.kit_id == kit.id => {
+                    invitation.delete(&conn)?;
+                    Ok(ResponseBuilder::ok().empty())
+                }
+                _ => Err(problem::NOT_FOUND),
+            },
+        )
+        .await
+    }
+
+    warp::delete()
+        .and(warp::path!("kits" / String / "invitations" / i32))
+        .and(authentication::option_by_token())
+        .and_then(
+            move |kit_serial: String,
+                  kit_invitation_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(
+                    pg.clone(),
+                    kit_serial,
+                    models::KitInvitationId(kit_invitation_id),
+                    user_id,
+                )
+                .never_error()
+            },
+        )
+}
+
+/// Handles the `POST /kit-invitations/{code}/redeem` route.
+///
+/// Makes the user a member of the invitation's kit, with the access granted by the invitation.
+fn redeem_kit_invitation(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    async fn implementation(
+        pg: PgPool,
+        code: String,
+        user_id: models::UserId,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let invitation = match models::KitInvitation::by_code_for_update(&conn, &code)? {
+                    Some(invitation) if invitation.redeemable(Utc::now()) => invitation,
+                    _ => return Err(problem::NOT_FOUND),
+                };
+                let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+                let kit = models::Kit::by_id(&conn, invitation.get_kit_id())?
+                    .ok_or(problem::INTERNAL_SERVER_ERROR)?;
+
+                if models::KitMembership::by_user_id_and_kit_id(&conn, user_id, kit.get_id())?
+                    .is_some()
+                {
+                    return Err(InvalidParameterReason::AlreadyExists
+                        .singleton("code")
+                        .into_problem());
+                }
+
+                let created_membership = models::NewKitMembership::new(
+                    user_id,
+                    kit.get_id(),
+                    invitation.access_super,
+                    invitation.access_configure,
+                )
+                .create(&conn)?;
+                invitation.redeem(&conn)?;
+
+                Ok::<_, Problem>(
+                    ResponseBuilder::created().body(
+                        views::KitMembership::from(created_membership)
+                            .with_user(user.username)
+                            .with_kit(views::Kit::from(kit)),
+                    ),
+                )
+            })
+        })
+        .await
+    }
+
+    warp::post()
+        .and(warp::path!("kit-invitations" / String / "redeem"))
+        .and(authentication::by_token())
+        .and_then(move |code: String, user_id: models::UserId| {
+            implementation(pg.clone(), code, user_id).never_error()
+        })
+}
@@ -0,0 +1,358 @@
+mod invitation;
+
+use futures::future::FutureExt;
+use serde::Deserialize;
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+use crate::authorization::{KitAction, KitUser};
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models, views};
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up kit memberships and invitations router.");
+
+    kit_members(pg.clone())
+        .or(add_kit_member(pg.clone()))
+        .unify()
+        .or(patch_or_delete_kit_member(pg.clone()))
+        .unify()
+        .or(invitation::router(pg))
+        .unify()
+        .boxed()
+}
+
+/// Ensure a member of the kit is permitted to perform the action on the kit.
+fn member_permission_or_forbidden(
+    user: Option<models::User>,
+    membership: Option<models::KitMembership>,
+    kit: &models::Kit,
+    action: KitAction,
+) -> AppResult<()> {
+    match (user, membership) {
+        (Some(user), Some(membership)) => helpers::permission_or_forbidden(
+            &KitUser::UserWithMembership(user, membership),
+            kit,
+            action,
+        ),
+        _ => Err(problem::FORBIDDEN),
+    }
+}
+
+/// Get the membership of a user of the kit by the user's username.
+fn membership_by_username(
+    conn: &diesel::pg::PgConnection,
+    kit: &models::Kit,
+    username: &str,
+) -> AppResult<(models::User, models::KitMembership)> {
+    let user = models::User::by_username(conn, username)?.ok_or(problem::NOT_FOUND)?;
+    let membership =
+        models::KitMembership::by_user_id_and_kit_id(conn, user.get_id(), kit.get_id())?
+            .ok_or(problem::NOT_FOUND)?;
+    Ok((user, membership))
+}
+
+/// Ensure the super membership is not the kit's last super membership.
+fn not_last_super_member(
+    conn: &diesel::pg::PgConnection,
+    membership: &models::KitMembership,
+    parameter: &'static str,
+) -> AppResult<()> {
+    if membership.access_super {
+        let super_memberships = models::KitMembership::super_memberships_of_kit_id_for_update(
+            conn,
+            models::KitId(membership.kit_id),
+        )?;
+        if super_memberships.len() <= 1 {
+            return Err(InvalidParameterReason::LastSuperMember
+                .singleton(parameter)
+                .into_problem());
+        }
+    }
+    Ok(())
+}
+
+/// Handles the `GET /kits/{kitSerial}/members` route.
+fn kit_members(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::View,
+        )
+        .await?;
+        // Only members can see who the other members are.
+        if membership.is_none() {
+            return Err(problem::FORBIDDEN);
+        }
+
+        let kit_serial = kit.serial.clone();
+        let conn = pg.get().await?;
+        let memberships = helpers::threadpool(move || {
+            models::KitMembership::memberships_with_user_of_kit_id(&conn, kit.get_id())
+        })
+        .await?;
+
+        let body: Vec<views::KitMembership<views::User, String>> = memberships
+            .into_iter()
+            .map(|(user, membership)| {
+                views::KitMembership::from(membership)
+                    .with_user(views::User::from(user))
+                    .with_kit(kit_serial.clone())
+            })
+            .collect();
+        Ok(ResponseBuilder::ok().body(body))
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "members"))
+        .and(authentication::option_by_token())
+        .and_then(move |kit_serial, user_id| {
+            implementation(pg.clone(), kit_serial, user_id).never_error()
+        })
+}
+
+/// Handles the `POST /kits/{kitSerial}/members` route.
+///
+/// Only super members may add super members.
+fn add_kit_member(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Member {
+        username: String,
+        #[serde(default)]
+        access_super: bool,
+        #[serde(default)]
+        access_configure: bool,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        member: Member,
+    ) -> AppResult<Response> {
+        let (user, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::EditMembers,
+        )
+        .await?;
+        if member.access_super {
+            member_permission_or_forbidden(user, membership, &kit, KitAction::SetSuperMember)?;
+        }
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let user = match models::User::by_username(&conn, &member.username)? {
+                    Some(user) => user,
+                    None => {
+                        return Err(InvalidParameterReason::NotFound
+                            .singleton("username")
+                            .into_problem())
+                    }
+                };
+
+                let already_exists = || {
+                    InvalidParameterReason::AlreadyExists
+                        .singleton("username")
+                        .into_problem()
+                };
+                if models::KitMembership::by_user_id_and_kit_id(&conn, user.get_id(), kit.get_id())?
+                    .is_some()
+                {
+                    return Err(already_exists());
+                }
+
+                let created_membership = match models::NewKitMembership::new(
+                    user.get_id(),
+                    kit.get_id(),
+                    member.access_super,
+                    member.access_configure,
+                )
+                .create(&conn)
+                {
+                    Ok(created_membership) => created_membership,
+                    // The membership was created concurrently.
+                    Err(diesel::result::Error::NotFound) => return Err(already_exists()),
+                    Err(err) => return Err(err.into()),
+                };
+
+                Ok::<_, Problem>(
+                    ResponseBuilder::created().body(
+                        views::KitMembership::from(created_membership)
+                            .with_user(views::User::from(user))
+                            .with_kit(kit.serial),
+                    ),
+                )
+            })
+        })
+        .await
+    }
+
+    warp::post()
+        .and(warp::path!("kits" / String / "members"))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  member: Member| {
+                implementation(pg.clone(), kit_serial, user_id, member).never_error()
+            },
+        )
+}
+
+/// Handles the `PATCH` and `DELETE /kits/{kitSerial}/members/{username}` routes.
+///
+/// Only super members may grant or revoke super access, and remove super members. Members may
+/// always remove themselves. The last super member of a kit cannot lose super access or leave.
+fn patch_or_delete_kit_member(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct MemberPatch {
+        access_super: Option<bool>,
+        access_configure: Option<bool>,
+    }
+
+    async fn patch_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        username: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        member_patch: MemberPatch,
+    ) -> AppResult<Response> {
+        let (user, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::EditMembers,
+        )
+        .await?;
+        if member_patch.access_super.is_some() {
+            member_permission_or_forbidden(user, membership, &kit, KitAction::SetSuperMember)?;
+        }
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let (user, membership) = membership_by_username(&conn, &kit, &username)?;
+
+                if member_patch.access_super == Some(false) {
+                    not_last_super_member(&conn, &membership, "accessSuper")?;
+                }
+
+                let patched_membership = if member_patch.access_super.is_none()
+                    && member_patch.access_configure.is_none()
+                {
+                    membership
+                } else {
+                    models::UpdateKitMembership {
+                        id: membership.id,
+                        access_super: member_patch.access_super,
+                        access_configure: member_patch.access_configure,
+                    }
+                    .update(&conn)?
+                };
+
+                Ok::<_, Problem>(
+                    ResponseBuilder::ok().body(
+                        views::KitMembership::from(patched_membership)
+                            .with_user(views::User::from(user))
+                            .with_kit(kit.serial),
+                    ),
+                )
+            })
+        })
+        .await
+    }
+
+    async fn delete_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        username: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (user, membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            KitAction::View,
+        )
+        .await?;
+        let (user, membership) = match (user, membership) {
+            (Some(user), Some(membership)) => (user, membership),
+            _ => return Err(problem::FORBIDDEN),
+        };
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let (_, removed_membership) = membership_by_username(&conn, &kit, &username)?;
+
+                if removed_membership.id != membership.id {
+                    let kit_user = KitUser::UserWithMembership(user, membership);
+                    helpers::permission_or_forbidden(&kit_user, &kit, KitAction::EditMembers)?;
+                    if removed_membership.access_super {
+                        helpers::permission_or_forbidden(
+                            &kit_user,
+                            &kit,
+                            KitAction::SetSuperMember,
+                        )?;
+                    }
+                }
+
+                not_last_super_member(&conn, &removed_membership, "username")?;
+                removed_membership.delete(&conn)?;
+
+                Ok::<_, Problem>(ResponseBuilder::ok().empty())
+            })
+        })
+        .await
+    }
+
+    let pg2 = pg.clone();
+    (warp::patch()
+        .and(warp::path!("kits" / String / "members" / String))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  username: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  member_patch: MemberPatch| {
+                patch_implementation(pg.clone(), kit_serial, username, user_id, member_patch)
+                    .never_error()
+            },
+        ))
+    .or(warp::delete()
+        .and(warp::path!("kits" / String / "members" / String))
+        .and(authentication::option_by_token())
+        .and_then(
+            move |kit_serial: String,
+                  username: String,
+                  user_id: Option<authentication::AuthenticatedUser>| {
+                delete_implementation(pg2.clone(), kit_serial, username, user_id).never_error()
+            },
+        ))
+    .unify()
+}
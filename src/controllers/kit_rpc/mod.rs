@@ -6,7 +6,7 @@ use warp::{filters::BoxedFilter, path, Filter, Rejection};
 use crate::database::PgPool;
 use crate::problem::{self, AppResult};
 use crate::response::{Response, ResponseBuilder};
-use crate::{authentication, helpers, models};
+use crate::{authentication, helpers};
 
 pub fn router(kits_rpc: KitsRpc, pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
@@ -29,7 +29,7 @@ pub fn version(
         kits_rpc: KitsRpc,
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let kits_rpc = kits_rpc.clone();
         let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
@@ -50,9 +50,11 @@ pub fn version(
 
     path!(String / "version")
         .and(authentication::option_by_token())
-        .and_then(move |kit_serial: String, user_id: Option<models::UserId>| {
-            implementation(kits_rpc.clone(), pg.clone(), kit_serial, user_id).never_error()
-        })
+        .and_then(
+            move |kit_serial: String, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(kits_rpc.clone(), pg.clone(), kit_serial, user_id).never_error()
+            },
+        )
 }
 
 /// Handles the `GET /kit-rpc/{kitSerial}/uptime` route.
@@ -64,7 +66,7 @@ pub fn uptime(
         kits_rpc: KitsRpc,
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let kits_rpc = kits_rpc.clone();
         let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
@@ -85,9 +87,11 @@ pub fn uptime(
 
     path!(String / "uptime")
         .and(authentication::option_by_token())
-        .and_then(move |kit_serial: String, user_id: Option<models::UserId>| {
-            implementation(kits_rpc.clone(), pg.clone(), kit_serial, user_id).never_error()
-        })
+        .and_then(
+            move |kit_serial: String, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(kits_rpc.clone(), pg.clone(), kit_serial, user_id).never_error()
+            },
+        )
 }
 
 /// Handles the `POST /kit-rpc/{kitSerial}/peripheral-command` route.
@@ -105,7 +109,7 @@ pub fn peripheral_command(
         kits_rpc: KitsRpc,
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         peripheral_command: PeripheralCommand,
     ) -> AppResult<Response> {
         let kits_rpc = kits_rpc.clone();
@@ -1,5 +1,7 @@
+use chrono::Utc;
 use futures::future::FutureExt;
 use serde::{Deserialize, Serialize};
+use uuid::Uuid;
 use warp::{Filter, Rejection};
 
 use crate::database::PgPool;
@@ -7,8 +9,57 @@ use crate::problem::{self, AppResult, Problem};
 use crate::response::{Response, ResponseBuilder};
 use crate::{helpers, models};
 
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct AuthenticationTokens {
+    refresh_token: String,
+    access_token: String,
+}
+
+/// Create a session for an authenticated user, and issue a refresh token and access token for it.
+async fn issue_tokens(
+    pg: PgPool,
+    user: models::User,
+    user_agent: Option<String>,
+) -> AppResult<Response> {
+    use astroplant_auth::token;
+
+    let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+
+    let user_id = user.get_id();
+    let conn = pg.get().await?;
+    let session = helpers::threadpool(move || {
+        let now = Utc::now();
+        models::Session::delete_expired_of_user_id(&conn, user_id, now)?;
+        models::NewSession::new(
+            user_id,
+            user_agent,
+            now,
+            chrono::Duration::seconds(token::REFRESH_TOKEN_VALIDITY_TIME as i64),
+        )
+        .create(&conn)
+    })
+    .await?;
+
+    let authentication_state =
+        token::AuthenticationState::new(user.id, session.id.to_hyphenated().to_string());
+    let refresh_token = token_signer.create_refresh_token(authentication_state.clone());
+    let access_token = token_signer.create_access_token(authentication_state);
+    debug!("Authenticated user: {}.", user.username);
+
+    Ok(ResponseBuilder::ok().body(AuthenticationTokens {
+        refresh_token,
+        access_token,
+    }))
+}
+
 /// Authenticate a user through provided credentials.
-/// Returns both a refresh token and authentication token.
+/// Returns both a refresh token and authentication token. If the user has two-factor
+/// authentication enabled, instead returns a challenge token to provide alongside the second
+/// factor to `authenticate_by_two_factor`.
+///
+/// Failed attempts are tracked per username. After a few failures further attempts are delayed
+/// progressively, and eventually the username is locked out temporarily.
 pub fn authenticate_by_credentials(
     pg: PgPool,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
@@ -21,50 +72,91 @@ pub fn authenticate_by_credentials(
 
     #[derive(Serialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    struct AuthenticationTokens {
-        refresh_token: String,
-        access_token: String,
+    struct TwoFactorChallenge {
+        challenge_token: String,
     }
 
     async fn implementation(
         pg: PgPool,
         authentication_details: AuthenticationDetails,
+        user_agent: Option<String>,
     ) -> AppResult<Response> {
         use astroplant_auth::{hash, token};
 
-        let conn = pg.get().await?;
+        let conn = pg.clone().get().await?;
 
-        let (user, password) = helpers::threadpool(move || {
+        let (user, password_check, two_factor) = helpers::threadpool(move || {
+            let password = authentication_details.password;
             let user_by_username =
                 models::User::by_username(&conn, &authentication_details.username)?;
-            Ok::<_, Problem>((user_by_username, authentication_details.password))
-        })
-        .await?;
 
-        match user {
-            Some(user) => {
-                if hash::check_user_password(&password, &user.password_hash) {
-                    let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+            // Attempts are throttled per user if the username exists, such that differently
+            // written usernames of the same user share their failures.
+            let username = match &user_by_username {
+                Some(user) => user.username.clone(),
+                None => authentication_details.username,
+            };
 
-                    let authentication_state = token::AuthenticationState::new(user.id);
-                    let refresh_token = token_signer.create_refresh_token(authentication_state);
-                    let access_token = token_signer
-                        .access_token_from_refresh_token(&refresh_token)
-                        .unwrap();
-                    debug!("Authenticated user: {}.", user.username);
+            if let Some(wait) = models::LoginFailure::attempt(&conn, &username, Utc::now())? {
+                debug!("Login throttled for username: {}.", username);
+                return Err(Problem::LoginThrottled {
+                    retry_after_millis: wait.num_milliseconds().max(0) as u64,
+                });
+            }
 
-                    let response = ResponseBuilder::ok().body(AuthenticationTokens {
-                        refresh_token,
-                        access_token,
-                    });
+            let password_check = match &user_by_username {
+                Some(user) => {
+                    let password_check = hash::check_user_password(&password, &user.password_hash);
+                    if password_check == hash::PasswordCheck::CorrectOutdated {
+                        // Now the password is known, upgrade its hash.
+                        let password_hash = hash::hash_user_password(&password);
+                        match user.update_password_hash(&conn, password_hash) {
+                            Ok(_) => debug!("Rehashed password of user: {}.", user.username),
+                            Err(err) => warn!(
+                                "could not rehash password of user {}: {:?}",
+                                user.username, err
+                            ),
+                        }
+                    }
+                    password_check
+                }
+                None => {
+                    // Probably unnecessary, but hash the provided password to help defeat timing
+                    // attacks.
+                    hash::hash_user_password(&password);
+                    hash::PasswordCheck::Incorrect
+                }
+            };
 
-                    return Ok(response);
+            let two_factor = match &user_by_username {
+                Some(user) if password_check.is_correct() => {
+                    models::TotpSecret::enabled_by_user_id(&conn, user.get_id())?.is_some()
                 }
+                _ => false,
+            };
+
+            // With two-factor authentication enabled, failures are only cleared once the second
+            // factor is provided as well, such that codes cannot be guessed indefinitely.
+            if password_check.is_correct() && !two_factor {
+                models::LoginFailure::clear(&conn, &username)?;
             }
-            None => {
-                // Probably unnecessary, but hash the provided password to help defeat timing
-                // attacks.
-                hash::hash_user_password(&password);
+
+            Ok::<_, Problem>((user_by_username, password_check, two_factor))
+        })
+        .await?;
+
+        if let Some(user) = user {
+            if password_check.is_correct() {
+                if two_factor {
+                    let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+                    let challenge_token = token_signer
+                        .create_challenge_token(token::AuthenticationState::challenge(user.id));
+                    debug!("Awaiting second factor of user: {}.", user.username);
+
+                    return Ok(ResponseBuilder::ok().body(TwoFactorChallenge { challenge_token }));
+                }
+
+                return issue_tokens(pg, user, user_agent).await;
             }
         }
 
@@ -75,60 +167,194 @@ pub fn authenticate_by_credentials(
         Err(Problem::InvalidParameters { invalid_parameters })
     }
 
-    crate::helpers::deserialize().and_then(move |authentication_details: AuthenticationDetails| {
-        implementation(pg.clone(), authentication_details).never_error()
-    })
+    crate::helpers::deserialize()
+        .and(warp::header::optional::<String>("user-agent"))
+        .and_then(
+            move |authentication_details: AuthenticationDetails, user_agent: Option<String>| {
+                implementation(pg.clone(), authentication_details, user_agent).never_error()
+            },
+        )
 }
 
-/// Get an access token through a refresh token.
+/// Authenticate a user with two-factor authentication enabled, through the challenge token
+/// issued by `authenticate_by_credentials` and a code or recovery code.
+/// Returns both a refresh token and authentication token.
 ///
-/// # TODO
-/// Check refresh token against the database for revocation.
-pub fn access_token_from_refresh_token(
+/// Failed attempts count towards the same per-username throttling as failed passwords.
+pub fn authenticate_by_two_factor(
+    pg: PgPool,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
-    use astroplant_auth::token;
-    use problem::{AccessTokenProblemCategory::*, InvalidParameterReason, InvalidParameters};
-
     #[derive(Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
-    struct TaggedToken {
-        refresh_token: String,
+    struct TwoFactorDetails {
+        challenge_token: String,
+        code: String,
     }
 
-    crate::helpers::deserialize().and_then(|TaggedToken { refresh_token }| async move {
-        let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+    async fn implementation(
+        pg: PgPool,
+        two_factor_details: TwoFactorDetails,
+        user_agent: Option<String>,
+    ) -> AppResult<Response> {
+        use astroplant_auth::token;
+        use problem::{AccessTokenProblemCategory, InvalidParameterReason};
 
-        Ok(
-            match token_signer.access_token_from_refresh_token(&refresh_token) {
-                Ok(access_token) => {
-                    trace!("Token refreshed.");
-                    Ok(ResponseBuilder::ok().body(access_token))
-                }
+        let invalid_token = |category: AccessTokenProblemCategory| {
+            InvalidParameterReason::InvalidToken { category }
+                .singleton("challengeToken")
+                .into_problem()
+        };
+
+        let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+        let authentication_state =
+            match token_signer.decode_challenge_token(&two_factor_details.challenge_token) {
+                Ok(authentication_state) => authentication_state,
                 Err(token::Error::Expired) => {
-                    let mut invalid_parameters = InvalidParameters::new();
-                    invalid_parameters.add(
-                        "refreshToken",
-                        InvalidParameterReason::InvalidToken { category: Expired },
-                    );
-
-                    return Err(Rejection::from(Problem::InvalidParameters {
-                        invalid_parameters,
-                    }));
+                    return Err(invalid_token(AccessTokenProblemCategory::Expired))
                 }
-                Err(_) => {
-                    let mut invalid_parameters = InvalidParameters::new();
-                    invalid_parameters.add(
-                        "refreshToken",
-                        InvalidParameterReason::InvalidToken {
-                            category: Malformed,
-                        },
-                    );
-
-                    return Err(Rejection::from(Problem::InvalidParameters {
-                        invalid_parameters,
-                    }));
+                Err(_) => return Err(invalid_token(AccessTokenProblemCategory::Malformed)),
+            };
+        let user_id = models::UserId(authentication_state.user_id);
+
+        let conn = pg.get().await?;
+        let user = helpers::threadpool(move || {
+            let user = models::User::by_id(&conn, user_id)?
+                .ok_or_else(|| invalid_token(AccessTokenProblemCategory::Revoked))?;
+
+            if let Some(wait) = models::LoginFailure::attempt(&conn, &user.username, Utc::now())? {
+                debug!("Login throttled for username: {}.", user.username);
+                return Err(Problem::LoginThrottled {
+                    retry_after_millis: wait.num_milliseconds().max(0) as u64,
+                });
+            }
+
+            // Two-factor authentication may have been disabled since the challenge was issued.
+            let totp_secret = models::TotpSecret::enabled_by_user_id(&conn, user_id)?
+                .ok_or_else(|| invalid_token(AccessTokenProblemCategory::Revoked))?;
+
+            let code = &two_factor_details.code;
+            if !totp_secret.use_code(&conn, code, Utc::now())? {
+                if models::RecoveryCode::use_code(&conn, user_id, code)? {
+                    info!("User {} used a recovery code.", user.username);
+                } else {
+                    return Err(InvalidParameterReason::Other
+                        .singleton("code")
+                        .into_problem());
                 }
+            }
+
+            models::LoginFailure::clear(&conn, &user.username)?;
+            Ok::<_, Problem>(user)
+        })
+        .await?;
+
+        issue_tokens(pg, user, user_agent).await
+    }
+
+    crate::helpers::deserialize()
+        .and(warp::header::optional::<String>("user-agent"))
+        .and_then(
+            move |two_factor_details: TwoFactorDetails, user_agent: Option<String>| {
+                implementation(pg.clone(), two_factor_details, user_agent).never_error()
             },
         )
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TaggedToken {
+    refresh_token: String,
+}
+
+/// Get the session a refresh token was issued for. Fails if the token is invalid, or if its
+/// session has expired or was revoked.
+async fn session_from_refresh_token(
+    pg: PgPool,
+    refresh_token: &str,
+) -> AppResult<(astroplant_auth::token::AuthenticationState, models::Session)> {
+    use astroplant_auth::token;
+    use problem::{AccessTokenProblemCategory, InvalidParameterReason};
+
+    let invalid_token = |category: AccessTokenProblemCategory| {
+        InvalidParameterReason::InvalidToken { category }
+            .singleton("refreshToken")
+            .into_problem()
+    };
+
+    let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+    let authentication_state = match token_signer.decode_refresh_token(refresh_token) {
+        Ok(authentication_state) => authentication_state,
+        Err(token::Error::Expired) => {
+            return Err(invalid_token(AccessTokenProblemCategory::Expired))
+        }
+        Err(_) => return Err(invalid_token(AccessTokenProblemCategory::Malformed)),
+    };
+
+    // Refresh tokens issued before sessions were introduced cannot be revoked, so they are no
+    // longer accepted.
+    let session_id = match authentication_state
+        .session_id
+        .as_ref()
+        .and_then(|session_id| session_id.parse::<Uuid>().ok())
+    {
+        Some(session_id) => models::SessionId(session_id),
+        None => return Err(invalid_token(AccessTokenProblemCategory::Revoked)),
+    };
+
+    let user_id = models::UserId(authentication_state.user_id);
+    let conn = pg.get().await?;
+    let session = helpers::threadpool(move || {
+        models::Session::active_by_id(&conn, user_id, session_id, Utc::now())
+    })
+    .await?;
+
+    match session {
+        Some(session) => Ok((authentication_state, session)),
+        None => Err(invalid_token(AccessTokenProblemCategory::Revoked)),
+    }
+}
+
+/// Get an access token through a refresh token.
+pub fn access_token_from_refresh_token(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use astroplant_auth::token;
+
+    async fn implementation(pg: PgPool, refresh_token: String) -> AppResult<Response> {
+        let (authentication_state, session) =
+            session_from_refresh_token(pg.clone(), &refresh_token).await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || session.touch(&conn, Utc::now())).await?;
+
+        let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+        let access_token = token_signer.create_access_token(authentication_state);
+        trace!("Token refreshed.");
+
+        Ok(ResponseBuilder::ok().body(access_token))
+    }
+
+    crate::helpers::deserialize().and_then(move |TaggedToken { refresh_token }| {
+        implementation(pg.clone(), refresh_token).never_error()
+    })
+}
+
+/// Revoke a refresh token by deleting its session.
+///
+/// Access tokens that were already issued remain valid until they expire.
+pub fn logout(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(pg: PgPool, refresh_token: String) -> AppResult<Response> {
+        let (_, session) = session_from_refresh_token(pg.clone(), &refresh_token).await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || session.delete(&conn)).await?;
+
+        Ok(ResponseBuilder::ok().empty())
+    }
+
+    crate::helpers::deserialize().and_then(move |TaggedToken { refresh_token }| {
+        implementation(pg.clone(), refresh_token).never_error()
     })
 }
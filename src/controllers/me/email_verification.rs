@@ -0,0 +1,169 @@
+use chrono::Utc;
+use diesel::pg::PgConnection;
+use diesel::QueryResult;
+use futures::future::FutureExt;
+use warp::{filters::BoxedFilter, path, Filter, Rejection};
+
+use crate::database::PgPool;
+use crate::mail::{self, Mailer};
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models};
+
+/// The time email verification tokens are valid for.
+const TOKEN_VALIDITY_HOURS: i64 = 48;
+
+/// The minimum time between sending email verification tokens to the same user.
+const TOKEN_INTERVAL_MINUTES: i64 = 2;
+
+pub fn router(
+    pg: PgPool,
+    mailer: Mailer,
+    auth_rate_limit: BoxedFilter<()>,
+) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up me email verification router.");
+
+    resend_verification(pg.clone(), mailer)
+        .or(verify_email(pg, auth_rate_limit))
+        .unify()
+        .boxed()
+}
+
+/// Create an email verification token for the user's current email address. Returns the token
+/// to be mailed to the user.
+pub fn create_token(conn: &PgConnection, user: &models::User) -> QueryResult<String> {
+    let now = Utc::now();
+    models::EmailVerificationToken::delete_expired_of_user_id(conn, user.get_id(), now)?;
+
+    let (new_token, token) = models::NewEmailVerificationToken::new(
+        user,
+        now,
+        chrono::Duration::hours(TOKEN_VALIDITY_HOURS),
+    );
+    new_token.create(conn)?;
+    Ok(token)
+}
+
+/// Mail an email verification token to the user, in the background.
+pub fn mail_token(mailer: &Mailer, user: &models::User, token: &str) {
+    debug!(
+        "Sending email verification token to user {}.",
+        user.username
+    );
+    mailer.send_in_background(mail::Message {
+        to: user.email_address.clone(),
+        subject: "Verify your AstroPlant email address".to_owned(),
+        body: format!(
+            "Hello {},\n\n\
+             Use the following token to verify this is the email address of your AstroPlant \
+             account {}. The token is valid for {} hours.\n\n\
+             {}\n\n\
+             If you did not create an AstroPlant account, you can ignore this email.",
+            user.display_name, user.username, TOKEN_VALIDITY_HOURS, token
+        ),
+    });
+}
+
+/// Handles the `POST /me/verify-email` route.
+///
+/// Mails a new email verification token to the user.
+fn resend_verification(
+    pg: PgPool,
+    mailer: Mailer,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        mailer: Mailer,
+        user_id: models::UserId,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        let verification = helpers::threadpool(move || {
+            let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+            if user.email_verified {
+                return Err(InvalidParameterReason::AlreadyActivated
+                    .singleton("emailAddress")
+                    .into_problem());
+            }
+
+            // Limit how often tokens are mailed to a user.
+            if let Some(latest) =
+                models::EmailVerificationToken::latest_of_user_id(&conn, user.get_id())?
+            {
+                if Utc::now() - latest.datetime_created
+                    < chrono::Duration::minutes(TOKEN_INTERVAL_MINUTES)
+                {
+                    return Ok(None);
+                }
+            }
+
+            let token = create_token(&conn, &user)?;
+            Ok::<_, Problem>(Some((user, token)))
+        })
+        .await?;
+
+        if let Some((user, token)) = verification {
+            mail_token(&mailer, &user, &token);
+        }
+
+        Ok(ResponseBuilder::ok().empty())
+    }
+
+    path!("verify-email")
+        .and(warp::post())
+        .and(authentication::by_token())
+        .and_then(move |user_id: models::UserId| {
+            implementation(pg.clone(), mailer.clone(), user_id).never_error()
+        })
+}
+
+/// Handles the `POST /me/verify-email/{token}` route.
+///
+/// The token is sufficient to verify the email address, such that the link can be followed
+/// without being logged in.
+fn verify_email(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    async fn implementation(pg: PgPool, token: String) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let invalid_token = || {
+                    InvalidParameterReason::Other
+                        .singleton("token")
+                        .into_problem()
+                };
+
+                let verification_token =
+                    match models::EmailVerificationToken::by_token_for_update(&conn, &token)? {
+                        Some(verification_token) => verification_token,
+                        None => return Err(invalid_token()),
+                    };
+                let user_id = verification_token.get_user_id();
+                let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+                if !verification_token.verifies(&user, Utc::now()) {
+                    return Err(invalid_token());
+                }
+
+                models::EmailVerificationToken::delete_all_of_user_id(&conn, user_id)?;
+                models::UpdateUser {
+                    email_verified: Some(true),
+                    ..models::UpdateUser::unchanged_for_id(user.id)
+                }
+                .update(&conn)?;
+                info!("User {} verified their email address.", user.username);
+
+                Ok::<_, Problem>(ResponseBuilder::ok().empty())
+            })
+        })
+        .await
+    }
+
+    path!("verify-email" / String)
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and_then(move |token: String| implementation(pg.clone(), token).never_error())
+}
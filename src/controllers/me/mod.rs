@@ -1,23 +1,66 @@
 mod auth;
+pub mod email_verification;
+mod password;
+mod personal_access_token;
+mod session;
+mod two_factor;
 
 use futures::future::FutureExt;
 use warp::{filters::BoxedFilter, path, Filter, Rejection};
 
 use crate::database::PgPool;
+use crate::mail::Mailer;
 use crate::problem::AppResult;
+use crate::rate_limit;
 use crate::response::{Response, ResponseBuilder};
 use crate::{authentication, helpers, models, views};
 
-pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+pub fn router(
+    pg: PgPool,
+    mailer: Mailer,
+    auth_rate_limit: rate_limit::Limit,
+) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     trace!("Setting up me router.");
 
+    // Gates the routes authenticating users. Applied after matching the routes, such that only
+    // requests to these routes count towards the limit.
+    let auth_rate_limit = rate_limit::leaky_bucket(auth_rate_limit).boxed();
+
     (path!("auth")
         .and(warp::post())
+        .and(auth_rate_limit.clone())
         .and(auth::authenticate_by_credentials(pg.clone())))
+    .or(path!("auth" / "2fa")
+        .and(warp::post())
+        .and(auth_rate_limit.clone())
+        .and(auth::authenticate_by_two_factor(pg.clone())))
+    .unify()
     .or(path!("refresh")
         .and(warp::post())
-        .and(auth::access_token_from_refresh_token()))
+        .and(auth::access_token_from_refresh_token(pg.clone())))
+    .unify()
+    .or(path!("logout")
+        .and(warp::post())
+        .and(auth::logout(pg.clone())))
+    .unify()
+    .or(session::router(pg.clone()))
+    .unify()
+    .or(personal_access_token::router(pg.clone()))
+    .unify()
+    .or(two_factor::router(pg.clone(), auth_rate_limit.clone()))
+    .unify()
+    .or(password::router(
+        pg.clone(),
+        mailer.clone(),
+        auth_rate_limit.clone(),
+    ))
+    .unify()
+    .or(email_verification::router(
+        pg.clone(),
+        mailer,
+        auth_rate_limit,
+    ))
     .unify()
     .or(warp::path::end().and(warp::get()).and(me(pg.clone())))
     .unify()
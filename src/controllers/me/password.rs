@@ -0,0 +1,252 @@
+use chrono::Utc;
+use futures::future::FutureExt;
+use serde::Deserialize;
+use warp::{filters::BoxedFilter, path, Filter, Rejection};
+
+use crate::database::PgPool;
+use crate::mail::{self, Mailer};
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models};
+
+/// The minimum number of characters of new passwords.
+const MIN_PASSWORD_LENGTH: usize = 8;
+
+/// The time password reset tokens are valid for.
+const RESET_TOKEN_VALIDITY_MINUTES: i64 = 60;
+
+/// The minimum time between sending password reset tokens to the same user.
+const RESET_TOKEN_INTERVAL_MINUTES: i64 = 2;
+
+pub fn router(
+    pg: PgPool,
+    mailer: Mailer,
+    auth_rate_limit: BoxedFilter<()>,
+) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up me password router.");
+
+    change_password(pg.clone(), auth_rate_limit.clone())
+        .or(request_password_reset(
+            pg.clone(),
+            mailer,
+            auth_rate_limit.clone(),
+        ))
+        .unify()
+        .or(reset_password(pg, auth_rate_limit))
+        .unify()
+        .boxed()
+}
+
+/// Hash a new password, ensuring it is sufficiently long.
+fn hash_new_password(password: &str) -> AppResult<String> {
+    if password.chars().count() < MIN_PASSWORD_LENGTH {
+        return Err(InvalidParameterReason::MustHaveLengthBetween {
+            min: Some(MIN_PASSWORD_LENGTH as u64),
+            max: None,
+        }
+        .singleton("newPassword")
+        .into_problem());
+    }
+
+    Ok(astroplant_auth::hash::hash_user_password(password))
+}
+
+/// Handles the `POST /me/password` route.
+///
+/// Changing the password ends all of the user's sessions.
+fn change_password(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct PasswordChange {
+        current_password: String,
+        new_password: String,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        user_id: models::UserId,
+        password_change: PasswordChange,
+    ) -> AppResult<Response> {
+        use astroplant_auth::hash;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+
+            if !hash::check_user_password(&password_change.current_password, &user.password_hash)
+                .is_correct()
+            {
+                return Err(InvalidParameterReason::Other
+                    .singleton("currentPassword")
+                    .into_problem());
+            }
+            let password_hash = hash_new_password(&password_change.new_password)?;
+
+            models::UpdateUser {
+                password_hash: Some(password_hash),
+                ..models::UpdateUser::unchanged_for_id(user.id)
+            }
+            .update(&conn)?;
+            info!("User {} changed their password.", user.username);
+
+            Ok::<_, Problem>(ResponseBuilder::ok().empty())
+        })
+        .await
+    }
+
+    path!("password")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(authentication::by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |user_id: models::UserId, password_change: PasswordChange| {
+                implementation(pg.clone(), user_id, password_change).never_error()
+            },
+        )
+}
+
+/// Handles the `POST /me/password-reset` route.
+///
+/// Mails a password reset token to the user with the given email address. To not disclose which
+/// email addresses are in use, the response is the same whether or not a user was found.
+fn request_password_reset(
+    pg: PgPool,
+    mailer: Mailer,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct PasswordResetRequest {
+        email_address: String,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        mailer: Mailer,
+        password_reset_request: PasswordResetRequest,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        let reset = helpers::threadpool(move || {
+            let user =
+                match models::User::by_email_address(&conn, &password_reset_request.email_address)?
+                {
+                    Some(user) => user,
+                    None => return Ok(None),
+                };
+
+            let now = Utc::now();
+            models::PasswordResetToken::delete_expired_of_user_id(&conn, user.get_id(), now)?;
+
+            // Limit how often tokens are mailed to a user.
+            if let Some(latest) =
+                models::PasswordResetToken::latest_of_user_id(&conn, user.get_id())?
+            {
+                if now - latest.datetime_created
+                    < chrono::Duration::minutes(RESET_TOKEN_INTERVAL_MINUTES)
+                {
+                    return Ok(None);
+                }
+            }
+
+            let (new_token, token) = models::NewPasswordResetToken::new(
+                user.get_id(),
+                now,
+                chrono::Duration::minutes(RESET_TOKEN_VALIDITY_MINUTES),
+            );
+            new_token.create(&conn)?;
+
+            Ok::<_, Problem>(Some((user, token)))
+        })
+        .await?;
+
+        if let Some((user, token)) = reset {
+            debug!("Sending password reset token to user {}.", user.username);
+            mailer.send_in_background(mail::Message {
+                to: user.email_address,
+                subject: "Reset your AstroPlant password".to_owned(),
+                body: format!(
+                    "Hello {},\n\n\
+                     A password reset was requested for your AstroPlant account. Use the \
+                     following token to choose a new password. The token is valid for {} \
+                     minutes.\n\n\
+                     {}\n\n\
+                     If you did not request a password reset, you can ignore this email.",
+                    user.display_name, RESET_TOKEN_VALIDITY_MINUTES, token
+                ),
+            });
+        }
+
+        Ok(ResponseBuilder::ok().empty())
+    }
+
+    path!("password-reset")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(crate::helpers::deserialize())
+        .and_then(move |password_reset_request: PasswordResetRequest| {
+            implementation(pg.clone(), mailer.clone(), password_reset_request).never_error()
+        })
+}
+
+/// Handles the `POST /me/password-reset/confirm` route.
+///
+/// Sets a new password using a password reset token. Resetting the password uses up all of the
+/// user's reset tokens, and ends all of the user's sessions.
+fn reset_password(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct PasswordReset {
+        token: String,
+        new_password: String,
+    }
+
+    async fn implementation(pg: PgPool, password_reset: PasswordReset) -> AppResult<Response> {
+        let password_hash = hash_new_password(&password_reset.new_password)?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let reset_token = match models::PasswordResetToken::by_token_for_update(
+                    &conn,
+                    &password_reset.token,
+                )? {
+                    Some(reset_token) if reset_token.usable(Utc::now()) => reset_token,
+                    _ => {
+                        return Err(InvalidParameterReason::Other
+                            .singleton("token")
+                            .into_problem())
+                    }
+                };
+
+                let user_id = reset_token.get_user_id();
+                let user = models::UpdateUser {
+                    password_hash: Some(password_hash),
+                    ..models::UpdateUser::unchanged_for_id(user_id.0)
+                }
+                .update(&conn)?;
+                info!("User {} reset their password.", user.username);
+
+                Ok::<_, Problem>(ResponseBuilder::ok().empty())
+            })
+        })
+        .await
+    }
+
+    path!("password-reset" / "confirm")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(crate::helpers::deserialize())
+        .and_then(move |password_reset: PasswordReset| {
+            implementation(pg.clone(), password_reset).never_error()
+        })
+}
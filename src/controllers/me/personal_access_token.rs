@@ -0,0 +1,236 @@
+use chrono::Utc;
+use futures::future::FutureExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use warp::{filters::BoxedFilter, path, Filter, Rejection};
+
+use astroplant_auth::token;
+
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models, views};
+
+/// The maximum number of characters of personal access token names.
+const MAX_NAME_LENGTH: usize = 100;
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up me personal access tokens router.");
+
+    personal_access_tokens(pg.clone())
+        .or(create_personal_access_token(pg.clone()))
+        .unify()
+        .or(delete_personal_access_token(pg))
+        .unify()
+        .boxed()
+}
+
+/// Replace the kit ids of personal access tokens by the kits' serials.
+fn with_kit_serials(
+    conn: &diesel::pg::PgConnection,
+    personal_access_tokens: Vec<models::PersonalAccessToken>,
+) -> AppResult<Vec<views::PersonalAccessToken<String>>> {
+    let mut kit_ids: Vec<i32> = personal_access_tokens
+        .iter()
+        .flat_map(|personal_access_token| personal_access_token.kit_ids.iter().cloned())
+        .collect();
+    kit_ids.sort();
+    kit_ids.dedup();
+
+    let serials: HashMap<i32, String> = models::Kit::by_ids(conn, &kit_ids)?
+        .into_iter()
+        .map(|kit| (kit.id, kit.serial))
+        .collect();
+
+    Ok(personal_access_tokens
+        .into_iter()
+        .map(|personal_access_token| {
+            let view = views::PersonalAccessToken::from(personal_access_token);
+            // Kits that were deleted since the token was created are left out.
+            let kits = view
+                .kits
+                .iter()
+                .filter_map(|kit_id| serials.get(kit_id).cloned())
+                .collect();
+            view.with_kits(kits)
+        })
+        .collect())
+}
+
+/// Handles the `GET /me/tokens` route.
+fn personal_access_tokens(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(pg: PgPool, user_id: models::UserId) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let personal_access_tokens =
+                models::PersonalAccessToken::active_of_user_id(&conn, user_id, Utc::now())?;
+            let body = with_kit_serials(&conn, personal_access_tokens)?;
+            Ok(ResponseBuilder::ok().body(body))
+        })
+        .await
+    }
+
+    path!("tokens")
+        .and(warp::get())
+        .and(authentication::by_token())
+        .and_then(move |user_id: models::UserId| implementation(pg.clone(), user_id).never_error())
+}
+
+/// Handles the `POST /me/tokens` route.
+///
+/// Creates a personal access token permitting the given actions on the given kits. The token is
+/// only returned once.
+fn create_personal_access_token(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct PersonalAccessToken {
+        name: String,
+        kits: Vec<String>,
+        actions: Vec<KitAction>,
+    }
+
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Created {
+        token: String,
+        personal_access_token: views::PersonalAccessToken<String>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        user_id: models::UserId,
+        personal_access_token: PersonalAccessToken,
+    ) -> AppResult<Response> {
+        let PersonalAccessToken {
+            name,
+            kits: mut kit_serials,
+            actions: requested_actions,
+        } = personal_access_token;
+
+        let name = name.trim().to_owned();
+        if name.is_empty() || name.chars().count() > MAX_NAME_LENGTH {
+            return Err(InvalidParameterReason::MustHaveLengthBetween {
+                min: Some(1),
+                max: Some(MAX_NAME_LENGTH as u64),
+            }
+            .singleton("name")
+            .into_problem());
+        }
+
+        kit_serials.sort();
+        kit_serials.dedup();
+        if kit_serials.is_empty() {
+            return Err(InvalidParameterReason::MustHaveLengthBetween {
+                min: Some(1),
+                max: None,
+            }
+            .singleton("kits")
+            .into_problem());
+        }
+
+        let mut actions: Vec<KitAction> = Vec::with_capacity(requested_actions.len());
+        for action in requested_actions {
+            if !actions.contains(&action) {
+                actions.push(action);
+            }
+        }
+        if actions.is_empty() || !actions.iter().all(|action| action.grantable_to_token()) {
+            return Err(InvalidParameterReason::Other
+                .singleton("actions")
+                .into_problem());
+        }
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let kits = models::Kit::by_serials(&conn, &kit_serials)?;
+            if kits.len() != kit_serials.len() {
+                return Err(InvalidParameterReason::NotFound
+                    .singleton("kits")
+                    .into_problem());
+            }
+
+            let kit_ids: Vec<models::KitId> = kits.iter().map(|kit| kit.get_id()).collect();
+            let personal_access_token =
+                models::NewPersonalAccessToken::new(user_id, name, &kit_ids, &actions, Utc::now())
+                    .create(&conn)?;
+            info!(
+                "User with id {} created personal access token {}.",
+                user_id.0, personal_access_token.id
+            );
+
+            let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+            let token = token_signer.create_personal_token(token::AuthenticationState::personal(
+                user_id.0,
+                personal_access_token.id,
+            ));
+            let personal_access_token = views::PersonalAccessToken::from(personal_access_token)
+                .with_kits(kits.into_iter().map(|kit| kit.serial).collect());
+
+            Ok::<_, Problem>(ResponseBuilder::created().body(Created {
+                token,
+                personal_access_token,
+            }))
+        })
+        .await
+    }
+
+    path!("tokens")
+        .and(warp::post())
+        .and(authentication::by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |user_id: models::UserId, personal_access_token: PersonalAccessToken| {
+                implementation(pg.clone(), user_id, personal_access_token).never_error()
+            },
+        )
+}
+
+/// Handles the `DELETE /me/tokens/{personalAccessTokenId}` route.
+///
+/// Revokes the personal access token.
+fn delete_personal_access_token(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        personal_access_token_id: models::PersonalAccessTokenId,
+        user_id: models::UserId,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            match models::PersonalAccessToken::active_by_id(
+                &conn,
+                user_id,
+                personal_access_token_id,
+                Utc::now(),
+            )? {
+                Some(personal_access_token) => {
+                    personal_access_token.delete(&conn)?;
+                    Ok(ResponseBuilder::ok().empty())
+                }
+                None => Err(problem::NOT_FOUND),
+            }
+        })
+        .await
+    }
+
+    path!("tokens" / i32)
+        .and(warp::delete())
+        .and(authentication::by_token())
+        .and_then(
+            move |personal_access_token_id: i32, user_id: models::UserId| {
+                implementation(
+                    pg.clone(),
+                    models::PersonalAccessTokenId(personal_access_token_id),
+                    user_id,
+                )
+                .never_error()
+            },
+        )
+}
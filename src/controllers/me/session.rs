@@ -0,0 +1,69 @@
+use chrono::Utc;
+use futures::future::FutureExt;
+use uuid::Uuid;
+use warp::{filters::BoxedFilter, path, Filter, Rejection};
+
+use crate::database::PgPool;
+use crate::problem::{self, AppResult};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models, views};
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up me sessions router.");
+
+    sessions(pg.clone()).or(delete_session(pg)).unify().boxed()
+}
+
+/// Handles the `GET /me/sessions` route.
+fn sessions(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(pg: PgPool, user_id: models::UserId) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let sessions = models::Session::active_of_user_id(&conn, user_id, Utc::now())?;
+            let body: Vec<views::Session> =
+                sessions.into_iter().map(views::Session::from).collect();
+            Ok(ResponseBuilder::ok().body(body))
+        })
+        .await
+    }
+
+    path!("sessions")
+        .and(warp::get())
+        .and(authentication::by_token())
+        .and_then(move |user_id: models::UserId| implementation(pg.clone(), user_id).never_error())
+}
+
+/// Handles the `DELETE /me/sessions/{sessionId}` route.
+///
+/// Revokes the session's refresh token.
+fn delete_session(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        session_id: models::SessionId,
+        user_id: models::UserId,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            match models::Session::active_by_id(&conn, user_id, session_id, Utc::now())? {
+                Some(session) => {
+                    session.delete(&conn)?;
+                    Ok(ResponseBuilder::ok().empty())
+                }
+                None => Err(problem::NOT_FOUND),
+            }
+        })
+        .await
+    }
+
+    path!("sessions" / Uuid)
+        .and(warp::delete())
+        .and(authentication::by_token())
+        .and_then(move |session_id: Uuid, user_id: models::UserId| {
+            implementation(pg.clone(), models::SessionId(session_id), user_id).never_error()
+        })
+}
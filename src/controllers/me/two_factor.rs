@@ -0,0 +1,282 @@
+use chrono::Utc;
+use futures::future::FutureExt;
+use serde::{Deserialize, Serialize};
+use warp::{filters::BoxedFilter, path, Filter, Rejection};
+
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, Problem};
+use crate::response::{Response, ResponseBuilder};
+use crate::{authentication, helpers, models};
+
+/// The issuer shown in authenticator apps.
+const TOTP_ISSUER: &str = "AstroPlant";
+
+pub fn router(pg: PgPool, auth_rate_limit: BoxedFilter<()>) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up me two-factor authentication router.");
+
+    two_factor(pg.clone())
+        .or(enroll(pg.clone()))
+        .unify()
+        .or(confirm(pg.clone(), auth_rate_limit.clone()))
+        .unify()
+        .or(regenerate_recovery_codes(
+            pg.clone(),
+            auth_rate_limit.clone(),
+        ))
+        .unify()
+        .or(disable(pg, auth_rate_limit))
+        .unify()
+        .boxed()
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct TwoFactorCode {
+    code: String,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct RecoveryCodes {
+    recovery_codes: Vec<String>,
+}
+
+fn invalid_code() -> Problem {
+    InvalidParameterReason::Other
+        .singleton("code")
+        .into_problem()
+}
+
+/// Generate new recovery codes for a user, replacing the user's existing recovery codes.
+fn generate_recovery_codes(
+    conn: &diesel::pg::PgConnection,
+    user_id: models::UserId,
+) -> AppResult<RecoveryCodes> {
+    let (new_codes, recovery_codes) = models::NewRecoveryCode::generate(user_id);
+    models::NewRecoveryCode::replace_all_of_user_id(conn, user_id, &new_codes)?;
+    Ok(RecoveryCodes { recovery_codes })
+}
+
+/// Handles the `GET /me/2fa` route.
+fn two_factor(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct TwoFactor {
+        enabled: bool,
+    }
+
+    async fn implementation(pg: PgPool, user_id: models::UserId) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let totp_secret = models::TotpSecret::enabled_by_user_id(&conn, user_id)?;
+            Ok(ResponseBuilder::ok().body(TwoFactor {
+                enabled: totp_secret.is_some(),
+            }))
+        })
+        .await
+    }
+
+    path!("2fa")
+        .and(warp::get())
+        .and(authentication::by_token())
+        .and_then(move |user_id: models::UserId| implementation(pg.clone(), user_id).never_error())
+}
+
+/// Handles the `POST /me/2fa` route.
+///
+/// Starts enrolling in two-factor authentication by generating a new TOTP secret. The secret is
+/// pending until it is confirmed.
+fn enroll(pg: PgPool) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Serialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Enrollment {
+        secret: String,
+        otpauth_uri: String,
+    }
+
+    async fn implementation(pg: PgPool, user_id: models::UserId) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+            if models::TotpSecret::enabled_by_user_id(&conn, user_id)?.is_some() {
+                return Err(InvalidParameterReason::AlreadyActivated
+                    .singleton("twoFactor")
+                    .into_problem());
+            }
+
+            let totp_secret = models::NewTotpSecret::new(user_id, Utc::now()).create(&conn)?;
+            let otpauth_uri = astroplant_auth::totp::otpauth_uri(
+                TOTP_ISSUER,
+                &user.username,
+                &totp_secret.secret,
+            );
+
+            Ok::<_, Problem>(ResponseBuilder::created().body(Enrollment {
+                secret: totp_secret.secret,
+                otpauth_uri,
+            }))
+        })
+        .await
+    }
+
+    path!("2fa")
+        .and(warp::post())
+        .and(authentication::by_token())
+        .and_then(move |user_id: models::UserId| implementation(pg.clone(), user_id).never_error())
+}
+
+/// Handles the `POST /me/2fa/confirm` route.
+///
+/// Enables two-factor authentication after the user has proven to have configured the pending
+/// TOTP secret by providing a code. Returns one-time recovery codes.
+fn confirm(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    async fn implementation(
+        pg: PgPool,
+        user_id: models::UserId,
+        two_factor_code: TwoFactorCode,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let totp_secret = match models::TotpSecret::by_user_id(&conn, user_id)? {
+                    Some(totp_secret) if !totp_secret.enabled => totp_secret,
+                    Some(_) => {
+                        return Err(InvalidParameterReason::AlreadyActivated
+                            .singleton("twoFactor")
+                            .into_problem())
+                    }
+                    None => return Err(problem::NOT_FOUND),
+                };
+
+                if !totp_secret.use_code(&conn, &two_factor_code.code, Utc::now())? {
+                    return Err(invalid_code());
+                }
+                totp_secret.enable(&conn)?;
+                let recovery_codes = generate_recovery_codes(&conn, user_id)?;
+                info!(
+                    "User with id {} enabled two-factor authentication.",
+                    user_id.0
+                );
+
+                Ok::<_, Problem>(ResponseBuilder::ok().body(recovery_codes))
+            })
+        })
+        .await
+    }
+
+    path!("2fa" / "confirm")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(authentication::by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |user_id: models::UserId, two_factor_code: TwoFactorCode| {
+                implementation(pg.clone(), user_id, two_factor_code).never_error()
+            },
+        )
+}
+
+/// Handles the `POST /me/2fa/recovery-codes` route.
+///
+/// Replaces the user's recovery codes by new recovery codes. Requires a code, such that the
+/// recovery codes cannot be regenerated with only an access token.
+fn regenerate_recovery_codes(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    async fn implementation(
+        pg: PgPool,
+        user_id: models::UserId,
+        two_factor_code: TwoFactorCode,
+    ) -> AppResult<Response> {
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            conn.transaction(|| {
+                let totp_secret = models::TotpSecret::enabled_by_user_id(&conn, user_id)?
+                    .ok_or(problem::NOT_FOUND)?;
+
+                if !totp_secret.use_code(&conn, &two_factor_code.code, Utc::now())? {
+                    return Err(invalid_code());
+                }
+                let recovery_codes = generate_recovery_codes(&conn, user_id)?;
+
+                Ok::<_, Problem>(ResponseBuilder::ok().body(recovery_codes))
+            })
+        })
+        .await
+    }
+
+    path!("2fa" / "recovery-codes")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(authentication::by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |user_id: models::UserId, two_factor_code: TwoFactorCode| {
+                implementation(pg.clone(), user_id, two_factor_code).never_error()
+            },
+        )
+}
+
+/// Handles the `POST /me/2fa/disable` route.
+///
+/// Disables two-factor authentication. Requires the user's password, such that two-factor
+/// authentication cannot be disabled with only an access token.
+fn disable(
+    pg: PgPool,
+    auth_rate_limit: BoxedFilter<()>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    use diesel::Connection;
+
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Disable {
+        password: String,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        user_id: models::UserId,
+        disable: Disable,
+    ) -> AppResult<Response> {
+        use astroplant_auth::hash;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let user = models::User::by_id(&conn, user_id)?.ok_or(problem::NOT_FOUND)?;
+            if !hash::check_user_password(&disable.password, &user.password_hash).is_correct() {
+                return Err(InvalidParameterReason::Other
+                    .singleton("password")
+                    .into_problem());
+            }
+
+            conn.transaction(|| {
+                models::TotpSecret::delete_of_user_id(&conn, user_id)?;
+                models::RecoveryCode::delete_all_of_user_id(&conn, user_id)
+            })?;
+            info!("User {} disabled two-factor authentication.", user.username);
+
+            Ok::<_, Problem>(ResponseBuilder::ok().empty())
+        })
+        .await
+    }
+
+    path!("2fa" / "disable")
+        .and(warp::post())
+        .and(auth_rate_limit)
+        .and(authentication::by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(move |user_id: models::UserId, disable: Disable| {
+            implementation(pg.clone(), user_id, disable).never_error()
+        })
+}
@@ -0,0 +1,476 @@
+//! Streaming exports of measurement history as CSV or as newline-delimited JSON.
+
+use bytes::Bytes;
+use diesel::pg::PgConnection;
+use diesel::QueryResult;
+use futures::stream::{self, StreamExt};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use crate::database::PgPool;
+use crate::response::ByteStream;
+use crate::{cursors, helpers, models, views};
+
+/// The amount of measurements fetched from the database at once.
+const CHUNK_SIZE: usize = 1_000;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    Ndjson,
+}
+
+impl Format {
+    /// Negotiate an export format from an `Accept` header. The media range with the highest
+    /// quality value (`q`) wins, with ties broken by the order given. Media ranges with a quality
+    /// of 0 are not acceptable, and media ranges with an invalid quality are ignored. Returns
+    /// `None` if JSON is preferred, or if no export format is acceptable.
+    pub fn from_accept(accept: &str) -> Option<Self> {
+        let mut preferred: Option<(f32, Option<Self>)> = None;
+        for media_range in accept.split(',') {
+            let mut parameters = media_range.split(';');
+            let media_type = parameters.next().unwrap_or("").trim();
+            let format = match media_type.to_ascii_lowercase().as_str() {
+                "text/csv" => Some(Format::Csv),
+                "application/x-ndjson" => Some(Format::Ndjson),
+                "application/json" | "application/*" | "*/*" => None,
+                _ => continue,
+            };
+            let quality = match quality(parameters) {
+                Some(quality) => quality,
+                None => continue,
+            };
+            let better = match preferred {
+                Some((preferred_quality, _)) => quality > preferred_quality,
+                None => true,
+            };
+            if quality > 0.0 && better {
+                preferred = Some((quality, format));
+            }
+        }
+        preferred.and_then(|(_, format)| format)
+    }
+
+    pub fn media_type(self) -> &'static str {
+        match self {
+            Format::Csv => "text/csv",
+            Format::Ndjson => "application/x-ndjson",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Ndjson => "ndjson",
+        }
+    }
+}
+
+/// Parse the quality value (`q`) from the parameters of a media range. Defaults to 1 if the media
+/// range has no quality value. Returns `None` if the quality value is invalid.
+fn quality<'a>(parameters: impl Iterator<Item = &'a str>) -> Option<f32> {
+    for parameter in parameters {
+        let mut parts = parameter.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        if name.eq_ignore_ascii_case("q") {
+            let quality: f32 = parts.next()?.trim().parse().ok()?;
+            return if (0.0..=1.0).contains(&quality) {
+                Some(quality)
+            } else {
+                None
+            };
+        }
+    }
+    Some(1.0)
+}
+
+/// Escape a CSV field according to RFC 4180.
+fn csv_field(field: &str) -> std::borrow::Cow<str> {
+    if field.contains(|c| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        format!("\"{}\"", field.replace('"', "\"\"")).into()
+    } else {
+        field.into()
+    }
+}
+
+/// The names of peripherals and the quantity types referred to by exported measurements. Cached
+/// for the duration of an export.
+#[derive(Default)]
+struct Labels {
+    peripheral_names: HashMap<i32, String>,
+    quantity_types: HashMap<i32, models::QuantityType>,
+}
+
+impl Labels {
+    /// Load the labels of the given measurements that have not been loaded yet.
+    fn load<T: Export>(&mut self, conn: &PgConnection, measurements: &[T]) -> QueryResult<()> {
+        let mut peripheral_ids = vec![];
+        let mut quantity_type_ids = vec![];
+        for measurement in measurements {
+            let (peripheral_id, quantity_type_id) = measurement.peripheral_and_quantity_type();
+            if !self.peripheral_names.contains_key(&peripheral_id) {
+                peripheral_ids.push(peripheral_id);
+            }
+            if !self.quantity_types.contains_key(&quantity_type_id) {
+                quantity_type_ids.push(quantity_type_id);
+            }
+        }
+        peripheral_ids.sort_unstable();
+        peripheral_ids.dedup();
+        quantity_type_ids.sort_unstable();
+        quantity_type_ids.dedup();
+
+        if !peripheral_ids.is_empty() {
+            for peripheral in models::Peripheral::by_ids(conn, peripheral_ids)? {
+                self.peripheral_names.insert(peripheral.id, peripheral.name);
+            }
+        }
+        if !quantity_type_ids.is_empty() {
+            for quantity_type in models::QuantityType::by_ids(conn, quantity_type_ids)? {
+                self.quantity_types.insert(quantity_type.id, quantity_type);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn peripheral_name(&self, peripheral_id: i32) -> &str {
+        self.peripheral_names
+            .get(&peripheral_id)
+            .map(|name| name.as_str())
+            .unwrap_or("")
+    }
+
+    fn quantity_type(&self, quantity_type_id: i32) -> (&str, &str) {
+        self.quantity_types
+            .get(&quantity_type_id)
+            .map(|quantity_type| {
+                (
+                    quantity_type.physical_quantity.as_str(),
+                    quantity_type.physical_unit.as_str(),
+                )
+            })
+            .unwrap_or(("", ""))
+    }
+}
+
+/// A measurement that can be exported.
+trait Export: Sized + Send + 'static {
+    type Cursor: Send + 'static;
+
+    const CSV_HEADER: &'static str;
+
+    fn cursor(&self) -> Self::Cursor;
+
+    fn peripheral_and_quantity_type(&self) -> (i32, i32);
+
+    fn write(self, format: Format, labels: &Labels, out: &mut Vec<u8>) -> Result<(), String>;
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Labeled<'a, T> {
+    #[serde(flatten)]
+    measurement: T,
+    peripheral_name: &'a str,
+    physical_quantity: &'a str,
+    physical_unit: &'a str,
+}
+
+impl Export for models::AggregateMeasurement {
+    type Cursor = cursors::AggregateMeasurements;
+
+    const CSV_HEADER: &'static str = "id,kitConfigurationId,peripheralId,peripheralName,\
+        quantityTypeId,physicalQuantity,physicalUnit,datetimeStart,datetimeEnd,aggregateType,\
+        value\n";
+
+    fn cursor(&self) -> Self::Cursor {
+        cursors::AggregateMeasurements(self.datetime_start, self.id)
+    }
+
+    fn peripheral_and_quantity_type(&self) -> (i32, i32) {
+        (self.peripheral_id, self.quantity_type_id)
+    }
+
+    fn write(self, format: Format, labels: &Labels, out: &mut Vec<u8>) -> Result<(), String> {
+        use std::io::Write;
+
+        let peripheral_name = labels.peripheral_name(self.peripheral_id);
+        let (physical_quantity, physical_unit) = labels.quantity_type(self.quantity_type_id);
+        let measurement = views::AggregateMeasurement::try_from(self)
+            .map_err(|_| "invalid aggregate measurement values".to_owned())?;
+
+        match format {
+            Format::Csv => {
+                // One row per aggregate, sorted by aggregate type for a stable output.
+                let mut values: Vec<_> = measurement.values.iter().collect();
+                values.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+                for (aggregate_type, value) in values {
+                    writeln!(
+                        out,
+                        "{},{},{},{},{},{},{},{},{},{},{}",
+                        measurement.id,
+                        measurement.kit_configuration_id,
+                        measurement.peripheral_id,
+                        csv_field(peripheral_name),
+                        measurement.quantity_type_id,
+                        csv_field(physical_quantity),
+                        csv_field(physical_unit),
+                        measurement.datetime_start.to_rfc3339(),
+                        measurement.datetime_end.to_rfc3339(),
+                        csv_field(aggregate_type),
+                        value,
+                    )
+                    .map_err(|err| err.to_string())?;
+                }
+            }
+            Format::Ndjson => {
+                let labeled = Labeled {
+                    measurement,
+                    peripheral_name,
+                    physical_quantity,
+                    physical_unit,
+                };
+                serde_json::to_writer(&mut *out, &labeled).map_err(|err| err.to_string())?;
+                out.push(b'\n');
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Export for models::RawMeasurement {
+    type Cursor = cursors::RawMeasurements;
+
+    const CSV_HEADER: &'static str = "id,kitConfigurationId,peripheralId,peripheralName,\
+        quantityTypeId,physicalQuantity,physicalUnit,datetime,value\n";
+
+    fn cursor(&self) -> Self::Cursor {
+        cursors::RawMeasurements(self.datetime, self.id)
+    }
+
+    fn peripheral_and_quantity_type(&self) -> (i32, i32) {
+        (self.peripheral_id, self.quantity_type_id)
+    }
+
+    fn write(self, format: Format, labels: &Labels, out: &mut Vec<u8>) -> Result<(), String> {
+        use std::io::Write;
+
+        let peripheral_name = labels.peripheral_name(self.peripheral_id);
+        let (physical_quantity, physical_unit) = labels.quantity_type(self.quantity_type_id);
+        let measurement = views::RawMeasurement::from(self);
+
+        match format {
+            Format::Csv => writeln!(
+                out,
+                "{},{},{},{},{},{},{},{},{}",
+                measurement.id,
+                measurement.kit_configuration_id,
+                measurement.peripheral_id,
+                csv_field(peripheral_name),
+                measurement.quantity_type_id,
+                csv_field(physical_quantity),
+                csv_field(physical_unit),
+                measurement.datetime.to_rfc3339(),
+                measurement.value,
+            )
+            .map_err(|err| err.to_string())?,
+            Format::Ndjson => {
+                let labeled = Labeled {
+                    measurement,
+                    peripheral_name,
+                    physical_quantity,
+                    physical_unit,
+                };
+                serde_json::to_writer(&mut *out, &labeled).map_err(|err| err.to_string())?;
+                out.push(b'\n');
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Stream all measurements returned by successive calls to `fetch`, starting at the given cursor.
+/// `fetch` is called with the cursor of the last measurement of the previous chunk, and must return
+/// at most `CHUNK_SIZE` measurements.
+fn export<T, F>(pg: PgPool, format: Format, cursor: Option<T::Cursor>, fetch: F) -> ByteStream
+where
+    T: Export,
+    F: Fn(&PgConnection, Option<T::Cursor>) -> QueryResult<Vec<T>> + Send + Sync + 'static,
+{
+    fn io_error(message: String) -> std::io::Error {
+        std::io::Error::new(std::io::ErrorKind::Other, message)
+    }
+
+    let fetch = Arc::new(fetch);
+    let body = stream::unfold(Some((cursor, Labels::default())), move |state| {
+        let pg = pg.clone();
+        let fetch = fetch.clone();
+        async move {
+            let (cursor, mut labels) = state?;
+            let conn = match pg.get().await {
+                Ok(conn) => conn,
+                Err(_) => return Some((Err(io_error("no database connection".to_owned())), None)),
+            };
+
+            let chunk = helpers::threadpool(move || {
+                let measurements = (*fetch)(&conn, cursor)?;
+                labels.load(&conn, &measurements)?;
+                Ok::<_, diesel::result::Error>((measurements, labels))
+            })
+            .await;
+
+            let (measurements, labels) = match chunk {
+                Ok(chunk) => chunk,
+                Err(err) => {
+                    warn!("error while exporting measurements: {:?}", err);
+                    return Some((Err(io_error(err.to_string())), None));
+                }
+            };
+            if measurements.is_empty() {
+                return None;
+            }
+
+            let next_cursor = if measurements.len() >= CHUNK_SIZE {
+                measurements.last().map(|measurement| measurement.cursor())
+            } else {
+                None
+            };
+
+            let mut out = Vec::new();
+            for measurement in measurements {
+                if let Err(err) = measurement.write(format, &labels, &mut out) {
+                    warn!("error while exporting measurements: {}", err);
+                    return Some((Err(io_error(err)), None));
+                }
+            }
+
+            let next_state = next_cursor.map(|cursor| (Some(cursor), labels));
+            Some((Ok(Bytes::from(out)), next_state))
+        }
+    });
+
+    let header = match format {
+        Format::Csv => Some(Ok(Bytes::from_static(T::CSV_HEADER.as_bytes()))),
+        Format::Ndjson => None,
+    };
+    Box::pin(stream::iter(header).chain(body))
+}
+
+/// Stream the aggregate measurements of a kit matching the given filters.
+pub fn aggregate_measurements(
+    pg: PgPool,
+    format: Format,
+    kit_id: models::KitId,
+    configuration_id: Option<i32>,
+    peripheral_id: Option<i32>,
+    quantity_type_id: Option<i32>,
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+    order: cursors::Order,
+    cursor: Option<cursors::AggregateMeasurements>,
+) -> ByteStream {
+    export(pg, format, cursor, move |conn, cursor| {
+        models::AggregateMeasurement::page_of_size(
+            conn,
+            kit_id,
+            configuration_id,
+            peripheral_id,
+            quantity_type_id,
+            from,
+            to,
+            order,
+            cursor,
+            CHUNK_SIZE,
+        )
+    })
+}
+
+/// Stream the raw measurements of a kit matching the given filters.
+pub fn raw_measurements(
+    pg: PgPool,
+    format: Format,
+    kit_id: models::KitId,
+    configuration_id: Option<i32>,
+    peripheral_id: Option<i32>,
+    quantity_type_id: Option<i32>,
+    from: Option<chrono::DateTime<chrono::Utc>>,
+    to: Option<chrono::DateTime<chrono::Utc>>,
+    order: cursors::Order,
+    cursor: Option<cursors::RawMeasurements>,
+) -> ByteStream {
+    export(pg, format, cursor, move |conn, cursor| {
+        models::RawMeasurement::page_of_size(
+            conn,
+            kit_id,
+            configuration_id,
+            peripheral_id,
+            quantity_type_id,
+            from,
+            to,
+            order,
+            cursor,
+            CHUNK_SIZE,
+        )
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{csv_field, Format};
+
+    #[test]
+    fn negotiate_format() {
+        assert_eq!(Format::from_accept("text/csv"), Some(Format::Csv));
+        assert_eq!(
+            Format::from_accept("application/x-ndjson"),
+            Some(Format::Ndjson)
+        );
+        assert_eq!(
+            Format::from_accept("text/html, text/csv;q=0.9"),
+            Some(Format::Csv)
+        );
+        assert_eq!(Format::from_accept("application/json, text/csv"), None);
+        assert_eq!(Format::from_accept("*/*"), None);
+        assert_eq!(Format::from_accept("text/html"), None);
+    }
+
+    #[test]
+    fn negotiate_format_by_quality() {
+        assert_eq!(Format::from_accept("text/csv;q=0, application/json"), None);
+        assert_eq!(Format::from_accept("text/csv;q=0"), None);
+        assert_eq!(
+            Format::from_accept("application/json;q=0.5, text/csv"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv;q=0.8, application/x-ndjson;q=0.9"),
+            Some(Format::Ndjson)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv; Q=0.9, application/json;q=0.5"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv;q=0.5, application/x-ndjson;q=0.5"),
+            Some(Format::Csv)
+        );
+        assert_eq!(
+            Format::from_accept("text/csv;q=2, application/x-ndjson"),
+            Some(Format::Ndjson)
+        );
+        assert_eq!(Format::from_accept("text/csv;q=nan"), None);
+    }
+
+    #[test]
+    fn escape_csv_fields() {
+        assert_eq!(csv_field("Temperature"), "Temperature");
+        assert_eq!(csv_field("Sensor, left"), "\"Sensor, left\"");
+        assert_eq!(csv_field("The \"big\" one"), "\"The \"\"big\"\" one\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
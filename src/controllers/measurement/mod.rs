@@ -1,9 +1,13 @@
+mod export;
+
+use chrono::{DateTime, Utc};
 use futures::future::FutureExt;
 use serde::{Deserialize, Serialize};
 use warp::{filters::BoxedFilter, Filter, Rejection};
 
+use crate::cursors;
 use crate::database::PgPool;
-use crate::problem::AppResult;
+use crate::problem::{AppResult, InvalidParameterReason, InvalidParameters};
 use crate::response::{Response, ResponseBuilder};
 use crate::{authentication, authorization, helpers, models, views};
 
@@ -11,7 +15,12 @@ pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     trace!("Setting up measurements router.");
 
-    kit_aggregate_measurements(pg.clone()).boxed()
+    kit_aggregate_measurements(pg.clone())
+        .or(kit_raw_measurements(pg.clone()))
+        .unify()
+        .or(kit_measurement_statistics(pg.clone()))
+        .unify()
+        .boxed()
 }
 
 /// Handles the `GET /kits/{kitSerial}/aggregate-measurements` route.
@@ -25,15 +34,18 @@ fn kit_aggregate_measurements(
         configuration: Option<i32>,
         peripheral: Option<i32>,
         quantity_type: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: Option<cursors::Order>,
     }
 
     async fn implementation(
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         query: Query,
+        accept: Option<String>,
     ) -> AppResult<Response> {
-        use crate::cursors;
         use std::convert::TryFrom;
 
         let mut out_query = query.clone();
@@ -48,6 +60,28 @@ fn kit_aggregate_measurements(
         )
         .await?;
 
+        if let Some(format) = accept.as_deref().and_then(export::Format::from_accept) {
+            let stream = export::aggregate_measurements(
+                pg,
+                format,
+                kit.get_id(),
+                query.configuration,
+                query.peripheral,
+                query.quantity_type,
+                query.from,
+                query.to,
+                query.order.unwrap_or_default(),
+                cursor,
+            );
+            return Ok(ResponseBuilder::ok()
+                .attachment_filename(&format!(
+                    "{}-aggregate-measurements.{}",
+                    kit.serial,
+                    format.extension()
+                ))
+                .stream(format.media_type().to_owned(), stream));
+        }
+
         let conn = pg.get().await?;
         let mut response = ResponseBuilder::ok();
         let aggregate_measurements = helpers::threadpool(move || {
@@ -57,6 +91,9 @@ fn kit_aggregate_measurements(
                 query.configuration,
                 query.peripheral,
                 query.quantity_type,
+                query.from,
+                query.to,
+                query.order.unwrap_or_default(),
                 cursor,
             )
         })
@@ -88,7 +125,275 @@ fn kit_aggregate_measurements(
         .and(warp::path!("kits" / String / "aggregate-measurements"))
         .and(authentication::option_by_token())
         .and(warp::query())
+        .and(warp::header::optional("Accept"))
+        .and_then(
+            move |kit_serial, user_id, query: Query, accept: Option<String>| {
+                implementation(pg.clone(), kit_serial, user_id, query, accept).never_error()
+            },
+        )
+}
+
+/// Handles the `GET /kits/{kitSerial}/raw-measurements` route.
+fn kit_raw_measurements(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Query {
+        cursor: Option<String>,
+        configuration: Option<i32>,
+        peripheral: Option<i32>,
+        quantity_type: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: Option<cursors::Order>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        query: Query,
+        accept: Option<String>,
+    ) -> AppResult<Response> {
+        let mut out_query = query.clone();
+        let cursor = (&query).cursor.as_ref().map(|s| s.parse()).transpose()?;
+        let base_uri = format!("/kits/{}/raw-measurements", kit_serial);
+
+        let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::View,
+        )
+        .await?;
+
+        if let Some(format) = accept.as_deref().and_then(export::Format::from_accept) {
+            let stream = export::raw_measurements(
+                pg,
+                format,
+                kit.get_id(),
+                query.configuration,
+                query.peripheral,
+                query.quantity_type,
+                query.from,
+                query.to,
+                query.order.unwrap_or_default(),
+                cursor,
+            );
+            return Ok(ResponseBuilder::ok()
+                .attachment_filename(&format!(
+                    "{}-raw-measurements.{}",
+                    kit.serial,
+                    format.extension()
+                ))
+                .stream(format.media_type().to_owned(), stream));
+        }
+
+        let conn = pg.get().await?;
+        let mut response = ResponseBuilder::ok();
+        let raw_measurements = helpers::threadpool(move || {
+            models::RawMeasurement::page(
+                &conn,
+                kit.get_id(),
+                query.configuration,
+                query.peripheral,
+                query.quantity_type,
+                query.from,
+                query.to,
+                query.order.unwrap_or_default(),
+                cursor,
+            )
+        })
+        .await?;
+
+        if let Some(next_cursor) = cursors::RawMeasurements::next_from_page(&raw_measurements) {
+            out_query.cursor = Some(next_cursor.into());
+            let next_page_uri = format!(
+                "{}?{}",
+                base_uri,
+                serde_urlencoded::to_string(&out_query).unwrap()
+            );
+            response = response.link(&next_page_uri, "next");
+        }
+
+        let body = raw_measurements
+            .into_iter()
+            .map(views::RawMeasurement::from)
+            .collect::<Vec<_>>();
+
+        Ok(response.body(body))
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "raw-measurements"))
+        .and(authentication::option_by_token())
+        .and(warp::query())
+        .and(warp::header::optional("Accept"))
+        .and_then(
+            move |kit_serial, user_id, query: Query, accept: Option<String>| {
+                implementation(pg.clone(), kit_serial, user_id, query, accept).never_error()
+            },
+        )
+}
+
+/// The maximum amount of buckets statistics can be requested for at once.
+const MAX_STATISTICS_BUCKETS: i64 = 5_000;
+
+/// The maximum size of statistics buckets in seconds: 52 weeks.
+const MAX_STATISTICS_BUCKET_SECONDS: i64 = 52 * 7 * 24 * 60 * 60;
+
+/// Parse a bucket size such as `30m`, `1h` or `7d` into seconds. Supported units are seconds
+/// (`s`), minutes (`m`), hours (`h`), days (`d`) and weeks (`w`). Buckets larger than
+/// `MAX_STATISTICS_BUCKET_SECONDS` are rejected.
+fn parse_bucket(bucket: &str) -> Option<i64> {
+    if bucket.len() < 2 || !bucket.is_ascii() {
+        return None;
+    }
+    let (amount, unit) = bucket.split_at(bucket.len() - 1);
+    let amount: i64 = amount.parse().ok()?;
+    let unit_seconds = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        "w" => 7 * 24 * 60 * 60,
+        _ => return None,
+    };
+
+    if amount > 0 {
+        amount
+            .checked_mul(unit_seconds)
+            .filter(|&seconds| seconds <= MAX_STATISTICS_BUCKET_SECONDS)
+    } else {
+        None
+    }
+}
+
+/// Handles the `GET /kits/{kitSerial}/measurement-statistics` route.
+fn kit_measurement_statistics(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    enum Source {
+        Aggregate,
+        Raw,
+    }
+
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Query {
+        peripheral: i32,
+        quantity_type: i32,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket: String,
+        source: Option<Source>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        query: Query,
+    ) -> AppResult<Response> {
+        let mut invalid_parameters = InvalidParameters::new();
+        let bucket_seconds = parse_bucket(&query.bucket);
+        match bucket_seconds {
+            Some(bucket_seconds) => {
+                if (query.to - query.from).num_seconds() / bucket_seconds > MAX_STATISTICS_BUCKETS {
+                    invalid_parameters.add("bucket", InvalidParameterReason::Other);
+                }
+            }
+            None => invalid_parameters.add("bucket", InvalidParameterReason::Other),
+        }
+        if query.to <= query.from {
+            invalid_parameters.add("to", InvalidParameterReason::Other);
+        }
+        if !invalid_parameters.is_empty() {
+            return Err(invalid_parameters.into_problem());
+        }
+        let bucket_seconds = bucket_seconds.unwrap();
+
+        let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::View,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        let statistics = helpers::threadpool(move || {
+            let peripheral_id = models::PeripheralId(query.peripheral);
+            let quantity_type_id = models::QuantityTypeId(query.quantity_type);
+            match query.source.unwrap_or(Source::Aggregate) {
+                Source::Aggregate => models::AggregateMeasurement::statistics(
+                    &conn,
+                    kit.get_id(),
+                    peripheral_id,
+                    quantity_type_id,
+                    query.from,
+                    query.to,
+                    bucket_seconds,
+                ),
+                Source::Raw => models::RawMeasurement::statistics(
+                    &conn,
+                    kit.get_id(),
+                    peripheral_id,
+                    quantity_type_id,
+                    query.from,
+                    query.to,
+                    bucket_seconds,
+                ),
+            }
+        })
+        .await?;
+
+        let bucket = chrono::Duration::seconds(bucket_seconds);
+        let body = statistics
+            .into_iter()
+            .map(|statistics| views::MeasurementStatistics::new(statistics, bucket))
+            .collect::<Vec<_>>();
+
+        Ok(ResponseBuilder::ok().body(body))
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "measurement-statistics"))
+        .and(authentication::option_by_token())
+        .and(warp::query())
         .and_then(move |kit_serial, user_id, query: Query| {
             implementation(pg.clone(), kit_serial, user_id, query).never_error()
         })
 }
+
+#[cfg(test)]
+mod test {
+    use super::parse_bucket;
+
+    #[test]
+    fn parse_valid_buckets() {
+        assert_eq!(parse_bucket("30s"), Some(30));
+        assert_eq!(parse_bucket("15m"), Some(15 * 60));
+        assert_eq!(parse_bucket("1h"), Some(60 * 60));
+        assert_eq!(parse_bucket("1d"), Some(24 * 60 * 60));
+        assert_eq!(parse_bucket("2w"), Some(2 * 7 * 24 * 60 * 60));
+        assert_eq!(parse_bucket("52w"), Some(52 * 7 * 24 * 60 * 60));
+    }
+
+    #[test]
+    fn reject_invalid_buckets() {
+        assert_eq!(parse_bucket(""), None);
+        assert_eq!(parse_bucket("h"), None);
+        assert_eq!(parse_bucket("0h"), None);
+        assert_eq!(parse_bucket("-1h"), None);
+        assert_eq!(parse_bucket("1y"), None);
+        assert_eq!(parse_bucket("1.5h"), None);
+        assert_eq!(parse_bucket("1é"), None);
+        assert_eq!(parse_bucket("53w"), None);
+        assert_eq!(parse_bucket("9223372036854775807s"), None);
+        assert_eq!(parse_bucket("1000000000000000w"), None);
+    }
+}
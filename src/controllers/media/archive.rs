@@ -0,0 +1,163 @@
+//! Streaming zip archives of media, with a JSON manifest describing the archived media.
+
+use bytes::Bytes;
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use serde::Serialize;
+use std::convert::TryFrom;
+
+use crate::problem::Problem;
+use crate::response::ByteStream;
+use crate::utils::zip::{ZipWriter, MAX_ARCHIVE_SIZE, MAX_FILES};
+use crate::{models, views};
+
+const MANIFEST_FILE_NAME: &str = "manifest.json";
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ManifestEntry {
+    /// The path of the media's file in the archive.
+    file: String,
+    #[serde(flatten)]
+    media: views::Media,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Manifest<'a> {
+    kit_serial: &'a str,
+    media: Vec<ManifestEntry>,
+}
+
+/// The path of a media's file in the archive. Files are grouped per peripheral, and are named
+/// such that they sort chronologically.
+fn file_name(media: &models::Media) -> String {
+    let name: String = media
+        .name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' => '_',
+            c => c,
+        })
+        .collect();
+    format!(
+        "{}/{}-{}-{}",
+        media.peripheral_id,
+        media.datetime.format("%Y%m%dT%H%M%S%.3fZ"),
+        media.id.to_hyphenated(),
+        name
+    )
+}
+
+/// The size of the archive of the given files.
+fn archive_size<'a>(files: impl Iterator<Item = (&'a str, u64)>) -> u64 {
+    const LOCAL_HEADER: u64 = 30;
+    const DATA_DESCRIPTOR: u64 = 16;
+    const DIRECTORY_HEADER: u64 = 46;
+    const END_OF_DIRECTORY: u64 = 22;
+
+    files
+        .map(|(name, size)| {
+            LOCAL_HEADER + DATA_DESCRIPTOR + DIRECTORY_HEADER + 2 * name.len() as u64 + size
+        })
+        .sum::<u64>()
+        + END_OF_DIRECTORY
+}
+
+async fn send(
+    sender: &mut mpsc::Sender<std::io::Result<Bytes>>,
+    bytes: Bytes,
+) -> std::io::Result<()> {
+    sender.send(Ok(bytes)).await.map_err(|_| {
+        std::io::Error::new(std::io::ErrorKind::BrokenPipe, "archive receiver dropped")
+    })
+}
+
+async fn write(
+    object_store: astroplant_object::ObjectStore,
+    kit_serial: String,
+    manifest: Vec<u8>,
+    files: Vec<(String, models::Media)>,
+    sender: &mut mpsc::Sender<std::io::Result<Bytes>>,
+) -> std::io::Result<()> {
+    let mut zip = ZipWriter::new();
+
+    send(
+        sender,
+        zip.start_file(MANIFEST_FILE_NAME, chrono::Utc::now())?,
+    )
+    .await?;
+    zip.write(&manifest)?;
+    send(sender, manifest.into()).await?;
+    send(sender, zip.finish_file()?).await?;
+
+    for (file_name, media) in files {
+        let mut object = object_store
+            .get(&kit_serial, &media.id.to_hyphenated().to_string())
+            .await
+            .map_err(|err| std::io::Error::new(std::io::ErrorKind::Other, format!("{:?}", err)))?;
+
+        send(sender, zip.start_file(&file_name, media.datetime)?).await?;
+        while let Some(data) = object.next().await {
+            let data = data?;
+            zip.write(&data)?;
+            send(sender, data).await?;
+        }
+        send(sender, zip.finish_file()?).await?;
+    }
+
+    send(sender, zip.finish()?).await
+}
+
+/// Stream a zip archive of the given media of a kit. The archive starts with a manifest
+/// describing the media.
+///
+/// Returns `None` if the archive would be too large.
+pub fn stream(
+    object_store: astroplant_object::ObjectStore,
+    kit_serial: String,
+    media: Vec<models::Media>,
+) -> Result<Option<ByteStream>, Problem> {
+    let files: Vec<(String, models::Media)> = media
+        .into_iter()
+        .map(|media| (file_name(&media), media))
+        .collect();
+
+    let manifest = Manifest {
+        kit_serial: &kit_serial,
+        media: files
+            .iter()
+            .map(|(file_name, media)| {
+                Ok(ManifestEntry {
+                    file: file_name.clone(),
+                    media: views::Media::try_from(media.clone())?,
+                })
+            })
+            .collect::<Result<_, Problem>>()?,
+    };
+    let manifest = serde_json::to_vec_pretty(&manifest).unwrap();
+
+    let size = archive_size(
+        std::iter::once((MANIFEST_FILE_NAME, manifest.len() as u64)).chain(
+            files
+                .iter()
+                .map(|(file_name, media)| (file_name.as_str(), media.size.max(0) as u64)),
+        ),
+    );
+    if files.len() + 1 > MAX_FILES || size > MAX_ARCHIVE_SIZE {
+        return Ok(None);
+    }
+
+    let (mut sender, receiver) = mpsc::channel(4);
+    tokio::spawn(async move {
+        if let Err(err) = write(object_store, kit_serial, manifest, files, &mut sender).await {
+            if err.kind() != std::io::ErrorKind::BrokenPipe {
+                warn!("error while streaming media archive: {:?}", err);
+                let _ = sender.send(Err(err)).await;
+            }
+        }
+    });
+
+    Ok(Some(receiver.boxed()))
+}
@@ -1,22 +1,35 @@
+mod archive;
+
+use chrono::{DateTime, Utc};
 use futures::future::FutureExt;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 use warp::{filters::BoxedFilter, Filter, Rejection};
 
+use crate::cursors;
 use crate::database::PgPool;
-use crate::problem::{AppResult, Problem, NOT_FOUND};
+use crate::problem::{AppResult, InvalidParameterReason, InvalidParameters, Problem, NOT_FOUND};
 use crate::response::{Response, ResponseBuilder};
 use crate::{authentication, authorization, helpers, models, views};
 
 pub fn router(
     pg: PgPool,
     object_store: astroplant_object::ObjectStore,
+    media_quota: Option<crate::media::Quota>,
 ) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     trace!("Setting up media router.");
 
     kit_media(pg.clone())
-        .or(download_media(pg.clone(), object_store))
+        .or(kit_media_archive(pg.clone(), object_store.clone()))
+        .unify()
+        .or(kit_media_usage(pg.clone(), media_quota))
+        .unify()
+        .or(download_media(pg.clone(), object_store.clone()))
+        .unify()
+        .or(media_thumbnail(pg.clone(), object_store.clone()))
+        .unify()
+        .or(delete_media(pg.clone(), object_store))
         .unify()
         .boxed()
 }
@@ -31,15 +44,17 @@ fn kit_media(
         cursor: Option<String>,
         configuration: Option<i32>,
         peripheral: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: Option<cursors::Order>,
     }
 
     async fn implementation(
         pg: PgPool,
         kit_serial: String,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         query: Query,
     ) -> AppResult<Response> {
-        use crate::cursors;
         use std::convert::TryFrom;
 
         let mut out_query = query.clone();
@@ -62,6 +77,9 @@ fn kit_media(
                 kit.get_id(),
                 query.configuration,
                 query.peripheral,
+                query.from,
+                query.to,
+                query.order.unwrap_or_default(),
                 cursor,
             )
         })
@@ -94,16 +112,243 @@ fn kit_media(
         })
 }
 
+/// Handles the `GET /kits/{kitSerial}/media/archive` route.
+fn kit_media_archive(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Query {
+        peripheral: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        object_store: astroplant_object::ObjectStore,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        query: Query,
+    ) -> AppResult<Response> {
+        let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::View,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        let kit_id = kit.get_id();
+        let media = helpers::threadpool(move || {
+            models::Media::in_range(&conn, kit_id, query.peripheral, query.from, query.to)
+        })
+        .await?;
+
+        match archive::stream(object_store, kit.serial.clone(), media)? {
+            Some(stream) => Ok(ResponseBuilder::ok()
+                .attachment_filename(&format!("{}-media.zip", kit.serial))
+                .stream("application/zip".to_owned(), stream)),
+            None => {
+                // The archive would be too large: the time range must be narrowed.
+                let mut invalid_parameters = InvalidParameters::new();
+                invalid_parameters.add("from", InvalidParameterReason::Other);
+                invalid_parameters.add("to", InvalidParameterReason::Other);
+                Err(invalid_parameters.into_problem())
+            }
+        }
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "media" / "archive"))
+        .and(authentication::option_by_token())
+        .and(warp::query())
+        .and_then(move |kit_serial, user_id, query: Query| {
+            implementation(pg.clone(), object_store.clone(), kit_serial, user_id, query)
+                .never_error()
+        })
+}
+
+/// Handles the `GET /kits/{kitSerial}/media/usage` route.
+fn kit_media_usage(
+    pg: PgPool,
+    media_quota: Option<crate::media::Quota>,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        media_quota: Option<crate::media::Quota>,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_user, _membership, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::View,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        let usage =
+            helpers::threadpool(move || models::Media::usage_of_kit(&conn, kit.get_id())).await?;
+
+        let body = views::MediaUsage {
+            bytes: usage.iter().map(|usage| usage.bytes).sum(),
+            count: usage.iter().map(|usage| usage.count).sum(),
+            quota_bytes: media_quota.map(|quota| quota.bytes),
+            peripherals: usage
+                .into_iter()
+                .map(views::PeripheralMediaUsage::from)
+                .collect(),
+        };
+
+        Ok(ResponseBuilder::ok().body(body))
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "media" / "usage"))
+        .and(authentication::option_by_token())
+        .and_then(move |kit_serial, user_id| {
+            implementation(pg.clone(), media_quota, kit_serial, user_id).never_error()
+        })
+}
+
+/// Get the content of media, resized to the given width if any.
+async fn media_content(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+    user_id: Option<authentication::AuthenticatedUser>,
+    media_id: models::MediaId,
+    width: Option<u32>,
+) -> AppResult<Response> {
+    let conn = pg.clone().get().await?;
+    let (media, kit) = helpers::threadpool(move || {
+        let media = models::Media::by_id(&conn, media_id)?.ok_or_else(|| NOT_FOUND)?;
+        let kit = models::Kit::by_id(&conn, media.get_kit_id())?.ok_or_else(|| NOT_FOUND)?;
+
+        Ok::<_, Problem>((media, kit))
+    })
+    .await?;
+
+    // FIXME: this unnecessarily queries for the kit: we already have it.
+    helpers::fut_kit_permission_or_forbidden(
+        pg,
+        user_id,
+        kit.serial.to_owned(),
+        authorization::KitAction::View,
+    )
+    .await?;
+
+    let stream = match width {
+        Some(width) => crate::media::derivative(&object_store, &kit.serial, &media, width).await?,
+        None => object_store
+            .get(&kit.serial, &crate::media::object_name(media.get_id()))
+            .await
+            .unwrap(),
+    };
+
+    Ok(ResponseBuilder::ok()
+        .attachment_filename(&media.name)
+        .stream(media.r#type, stream))
+}
+
 /// Handles the `GET` /media/{mediaId}/content` route.
+///
+/// If a width is given, image media are resized to that width (rounded up to one of the widths
+/// derivatives are generated at).
 fn download_media(
     pg: PgPool,
     object_store: astroplant_object::ObjectStore,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
-    /// Check user authorization and make sure the configuration has never been activated.
+    #[derive(Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct ContentQuery {
+        width: Option<u32>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        object_store: astroplant_object::ObjectStore,
+        user_id: Option<authentication::AuthenticatedUser>,
+        media_id: models::MediaId,
+        query: ContentQuery,
+    ) -> AppResult<Response> {
+        let width = match query.width {
+            Some(requested) => {
+                let width = crate::media::derivative_width(requested).ok_or_else(|| {
+                    let max_width =
+                        crate::media::DERIVATIVE_WIDTHS[crate::media::DERIVATIVE_WIDTHS.len() - 1];
+                    let mut invalid_parameters = InvalidParameters::new();
+                    invalid_parameters.add(
+                        "width",
+                        InvalidParameterReason::MustBeInRange {
+                            min: 1.0,
+                            max: max_width.into(),
+                        },
+                    );
+                    invalid_parameters.into_problem()
+                })?;
+                Some(width)
+            }
+            None => None,
+        };
+
+        media_content(pg, object_store, user_id, media_id, width).await
+    }
+
+    warp::get()
+        .and(warp::path!("media" / Uuid / "content"))
+        .and(authentication::option_by_token())
+        .and(warp::query())
+        .and_then(
+            move |media_id: Uuid,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  query: ContentQuery| {
+                implementation(
+                    pg.clone(),
+                    object_store.clone(),
+                    user_id,
+                    models::MediaId(media_id),
+                    query,
+                )
+                .never_error()
+            },
+        )
+}
+
+/// Handles the `GET` /media/{mediaId}/thumbnail` route.
+fn media_thumbnail(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    warp::get()
+        .and(warp::path!("media" / Uuid / "thumbnail"))
+        .and(authentication::option_by_token())
+        .and_then(
+            move |media_id: Uuid, user_id: Option<authentication::AuthenticatedUser>| {
+                media_content(
+                    pg.clone(),
+                    object_store.clone(),
+                    user_id,
+                    models::MediaId(media_id),
+                    Some(crate::media::THUMBNAIL_WIDTH),
+                )
+                .never_error()
+            },
+        )
+}
+
+/// Handles the `DELETE /media/{mediaId}` route.
+fn delete_media(
+    pg: PgPool,
+    object_store: astroplant_object::ObjectStore,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
     async fn implementation(
         pg: PgPool,
         object_store: astroplant_object::ObjectStore,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         media_id: models::MediaId,
     ) -> AppResult<Response> {
         let conn = pg.clone().get().await?;
@@ -115,35 +360,31 @@ fn download_media(
         })
         .await?;
 
-        // FIXME: this unnecessarily queries for the kit: we already have it.
         helpers::fut_kit_permission_or_forbidden(
-            pg,
+            pg.clone(),
             user_id,
             kit.serial.to_owned(),
-            authorization::KitAction::View,
+            authorization::KitAction::DeleteMedia,
         )
         .await?;
 
-        let stream = object_store
-            .get(&kit.serial, &media.id.to_hyphenated().to_string())
-            .await
-            .unwrap();
+        crate::media::delete(pg, &object_store, &kit.serial, media).await?;
 
-        Ok(ResponseBuilder::ok()
-            .attachment_filename(&media.name)
-            .stream(media.r#type, stream))
+        Ok(ResponseBuilder::ok().empty())
     }
 
-    warp::get()
-        .and(warp::path!("media" / Uuid / "content"))
+    warp::delete()
+        .and(warp::path!("media" / Uuid))
         .and(authentication::option_by_token())
-        .and_then(move |media_id: Uuid, user_id: Option<models::UserId>| {
-            implementation(
-                pg.clone(),
-                object_store.clone(),
-                user_id,
-                models::MediaId(media_id),
-            )
-            .never_error()
-        })
+        .and_then(
+            move |media_id: Uuid, user_id: Option<authentication::AuthenticatedUser>| {
+                implementation(
+                    pg.clone(),
+                    object_store.clone(),
+                    user_id,
+                    models::MediaId(media_id),
+                )
+                .never_error()
+            },
+        )
 }
@@ -1,5 +1,7 @@
+pub mod alert;
 pub mod kit;
 pub mod kit_configuration;
+pub mod kit_membership;
 pub mod kit_rpc;
 pub mod me;
 pub mod measurement;
@@ -8,3 +10,4 @@ pub mod peripheral_definition;
 pub mod permission;
 pub mod quantity_type;
 pub mod user;
+pub mod webhook;
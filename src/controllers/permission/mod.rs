@@ -4,9 +4,9 @@ use warp::{filters::BoxedFilter, Filter, Rejection};
 
 use crate::authorization::{KitUser, Permission};
 use crate::database::PgPool;
-use crate::problem::{self, AppResult, Problem};
+use crate::problem::AppResult;
 use crate::response::{Response, ResponseBuilder};
-use crate::{authentication, helpers, models};
+use crate::{authentication, helpers};
 
 pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
@@ -21,8 +21,6 @@ pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
 pub fn user_kit_permissions(
     pg: PgPool,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
-    use diesel::Connection;
-
     #[derive(Deserialize)]
     #[serde(rename_all = "camelCase")]
     struct KitSerial {
@@ -31,38 +29,14 @@ pub fn user_kit_permissions(
 
     async fn implementation(
         pg: PgPool,
-        user_id: Option<models::UserId>,
+        user_id: Option<authentication::AuthenticatedUser>,
         kit_serial: KitSerial,
     ) -> AppResult<Response> {
         use crate::authorization::KitAction;
         use strum::IntoEnumIterator;
 
-        let conn = pg.get().await?;
-        let (user, membership, kit) = helpers::threadpool(move || {
-            conn.transaction(|| {
-                let user = if let Some(user_id) = user_id {
-                    models::User::by_id(&conn, user_id)?
-                } else {
-                    None
-                };
-
-                let kit = models::Kit::by_serial(&conn, kit_serial.kit_serial)?;
-                if kit.is_none() {
-                    return Ok(None);
-                }
-                let kit = kit.unwrap();
-
-                let membership = if let Some(user_id) = user_id {
-                    models::KitMembership::by_user_id_and_kit_id(&conn, user_id, kit.get_id())?
-                } else {
-                    None
-                };
-
-                Ok::<_, Problem>(Some((user, membership, kit)))
-            })
-        })
-        .await?
-        .ok_or_else(|| problem::NOT_FOUND)?;
+        let (user, membership, kit, personal_access_token) =
+            helpers::fut_kit_actor(pg, user_id, kit_serial.kit_serial).await?;
 
         let kit_user = match (user, membership) {
             (None, _) => KitUser::Anonymous,
@@ -70,8 +44,14 @@ pub fn user_kit_permissions(
             (Some(user), Some(kit_membership)) => KitUser::UserWithMembership(user, kit_membership),
         };
 
+        // Personal access tokens only have the permissions they are scoped to.
         let permissions: Vec<KitAction> = KitAction::iter()
             .filter(|action| action.permitted(&kit_user, &kit))
+            .filter(|&action| {
+                personal_access_token
+                    .as_ref()
+                    .map_or(true, |token| token.permits(kit.get_id(), action))
+            })
             .collect();
 
         Ok(ResponseBuilder::ok().body(permissions))
@@ -80,7 +60,7 @@ pub fn user_kit_permissions(
     authentication::option_by_token()
         .and(warp::query::query::<KitSerial>())
         .and_then(
-            move |user_id: Option<models::UserId>, kit_serial: KitSerial| {
+            move |user_id: Option<authentication::AuthenticatedUser>, kit_serial: KitSerial| {
                 implementation(pg.clone(), user_id, kit_serial).never_error()
             },
         )
@@ -3,22 +3,24 @@ use serde::Deserialize;
 use validator::Validate;
 use warp::{filters::BoxedFilter, path, Filter, Rejection};
 
+use crate::controllers::me::email_verification;
 use crate::database::PgPool;
+use crate::mail::Mailer;
 use crate::problem::{self, AppResult, Problem};
 use crate::response::{Response, ResponseBuilder};
 use crate::{authentication, helpers, models, views};
 
-pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+pub fn router(pg: PgPool, mailer: Mailer) -> BoxedFilter<(AppResult<Response>,)> {
     //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
     trace!("Setting up users router.");
 
     //TODO implement deleting users.
     (user_by_username(pg.clone()))
-        .or(patch_user(pg.clone()))
+        .or(patch_user(pg.clone(), mailer.clone()))
         .unify()
         .or(list_kit_memberships(pg.clone()))
         .unify()
-        .or(create_user(pg.clone()))
+        .or(create_user(pg.clone(), mailer))
         .unify()
         .boxed()
 }
@@ -30,7 +32,7 @@ pub fn user_by_username(
     async fn implementation(
         pg: PgPool,
         object_username: String,
-        actor_user_id: Option<models::UserId>,
+        actor_user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let (_target_user, object_user) = helpers::fut_user_permission_or_forbidden(
             pg,
@@ -47,17 +49,24 @@ pub fn user_by_username(
         .and(path!(String))
         .and(authentication::option_by_token())
         .and_then(
-            move |object_username: String, actor_user_id: Option<models::UserId>| {
+            move |object_username: String,
+                  actor_user_id: Option<authentication::AuthenticatedUser>| {
                 implementation(pg.clone(), object_username, actor_user_id).never_error()
             },
         )
 }
 
 // Handles the `PATCH /users/{username}` route.
+//
+// Changing the email address marks it as unverified, and mails a verification token to the new
+// address.
 pub fn patch_user(
     pg: PgPool,
+    mailer: Mailer,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
-    //TODO implement password patching.
+    use diesel::Connection;
+
+    // Passwords are changed through `POST /me/password`, which requires the current password.
     #[derive(Deserialize, Debug)]
     #[serde(rename_all = "camelCase")]
     struct UserPatch {
@@ -68,8 +77,9 @@ pub fn patch_user(
 
     async fn implementation(
         pg: PgPool,
+        mailer: Mailer,
         object_username: String,
-        actor_user_id: Option<models::UserId>,
+        actor_user_id: Option<authentication::AuthenticatedUser>,
         user_patch: UserPatch,
     ) -> AppResult<Response> {
         let (_actor_user, user) = helpers::fut_user_permission_or_forbidden(
@@ -80,16 +90,26 @@ pub fn patch_user(
         )
         .await?;
 
+        let email_address_changed = user_patch
+            .email_address
+            .as_ref()
+            .map(|email_address| email_address.to_lowercase() != user.email_address.to_lowercase())
+            .unwrap_or(false);
         let update_user = models::UpdateUser {
             id: user.id,
             display_name: user_patch.display_name,
             password_hash: None,
             email_address: user_patch.email_address,
             use_email_address_for_gravatar: user_patch.use_email_address_for_gravatar,
+            email_verified: if email_address_changed {
+                Some(false)
+            } else {
+                None
+            },
         };
 
         let conn = pg.get().await?;
-        let patched_user = helpers::threadpool_result(move || {
+        let (patched_user, verification_token) = helpers::threadpool_result(move || {
             if let Some(email_address) = &update_user.email_address {
                 if let Some(user_by_email_address) =
                     models::User::by_email_address(&conn, email_address)?
@@ -110,10 +130,22 @@ pub fn patch_user(
                 return Err(problem::Problem::InvalidParameters { invalid_parameters });
             }
 
-            Ok::<_, Problem>(update_user.update(&conn)?)
+            conn.transaction(|| {
+                let patched_user = update_user.update(&conn)?;
+                let verification_token = if email_address_changed {
+                    Some(email_verification::create_token(&conn, &patched_user)?)
+                } else {
+                    None
+                };
+                Ok::<_, Problem>((patched_user, verification_token))
+            })
         })
         .await?;
 
+        if let Some(verification_token) = verification_token {
+            email_verification::mail_token(&mailer, &patched_user, &verification_token);
+        }
+
         Ok(ResponseBuilder::ok().body(views::User::from(patched_user)))
     }
 
@@ -123,9 +155,16 @@ pub fn patch_user(
         .and(crate::helpers::deserialize())
         .and_then(
             move |object_username: String,
-                  actor_user_id: Option<models::UserId>,
+                  actor_user_id: Option<authentication::AuthenticatedUser>,
                   user_patch: UserPatch| {
-                implementation(pg.clone(), object_username, actor_user_id, user_patch).never_error()
+                implementation(
+                    pg.clone(),
+                    mailer.clone(),
+                    object_username,
+                    actor_user_id,
+                    user_patch,
+                )
+                .never_error()
             },
         )
 }
@@ -137,7 +176,7 @@ pub fn list_kit_memberships(
     async fn implementation(
         pg: PgPool,
         object_username: String,
-        actor_user_id: Option<models::UserId>,
+        actor_user_id: Option<authentication::AuthenticatedUser>,
     ) -> AppResult<Response> {
         let (_actor_user, user) = helpers::fut_user_permission_or_forbidden(
             pg.clone(),
@@ -168,7 +207,8 @@ pub fn list_kit_memberships(
         .and(path!(String / "kit-memberships"))
         .and(authentication::option_by_token())
         .and_then(
-            move |object_username: String, actor_user_id: Option<models::UserId>| {
+            move |object_username: String,
+                  actor_user_id: Option<authentication::AuthenticatedUser>| {
                 implementation(pg.clone(), object_username, actor_user_id).never_error()
             },
         )
@@ -176,6 +216,7 @@ pub fn list_kit_memberships(
 
 pub fn create_user(
     pg: PgPool,
+    mailer: Mailer,
 ) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
     use diesel::Connection;
 
@@ -187,12 +228,12 @@ pub fn create_user(
         email_address: String,
     }
 
-    async fn implementation(pg: PgPool, user: User) -> AppResult<Response> {
+    async fn implementation(pg: PgPool, mailer: Mailer, user: User) -> AppResult<Response> {
         let username = user.username.clone();
         trace!("Got request to create user with username: {}", username);
 
         let conn = pg.get().await?;
-        helpers::threadpool(move || {
+        let (created_user, verification_token) = helpers::threadpool(move || {
                 conn.transaction(|| {
                     let user_by_username = models::User::by_username(&conn, &user.username)?;
                     let user_by_email_address = models::User::by_email_address(&conn, &user.email_address)?;
@@ -219,20 +260,25 @@ pub fn create_user(
                     }
 
                     let created_user = new_user.create(&conn)?;
-                    if created_user.is_some() {
+                    if let Some(created_user) = created_user {
                         info!("Created user {:?}", username);
 
-                        Ok(ResponseBuilder::created().empty())
+                        let verification_token = email_verification::create_token(&conn, &created_user)?;
+                        Ok((created_user, verification_token))
                     } else {
                         warn!("Unexpected database error: username and email address don't exist, yet user could not be created: {:?}", username);
                         Err(problem::INTERNAL_SERVER_ERROR)
                     }
                 })
-            }).await
+            }).await?;
+
+        email_verification::mail_token(&mailer, &created_user, &verification_token);
+
+        Ok(ResponseBuilder::created().empty())
     }
 
     warp::post()
         .and(warp::path::end())
         .and(crate::helpers::deserialize())
-        .and_then(move |user: User| implementation(pg.clone(), user).never_error())
+        .and_then(move |user: User| implementation(pg.clone(), mailer.clone(), user).never_error())
 }
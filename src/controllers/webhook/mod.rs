@@ -0,0 +1,357 @@
+use futures::future::FutureExt;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+use warp::{filters::BoxedFilter, Filter, Rejection};
+
+use crate::cursors;
+use crate::database::PgPool;
+use crate::problem::{self, AppResult, InvalidParameterReason, InvalidParameters};
+use crate::response::{Response, ResponseBuilder};
+use crate::utils::outbound;
+use crate::{authentication, authorization, helpers, models, views};
+
+pub fn router(pg: PgPool) -> BoxedFilter<(AppResult<Response>,)> {
+    //impl Filter<Extract = (Response,), Error = Rejection> + Clone {
+    trace!("Setting up webhooks router.");
+
+    kit_webhooks(pg.clone())
+        .or(create_webhook(pg.clone()))
+        .unify()
+        .or(patch_or_delete_webhook(pg.clone()))
+        .unify()
+        .or(webhook_deliveries(pg))
+        .unify()
+        .boxed()
+}
+
+/// Check the parts of a webhook that are not checked by its validator. The URL's host must resolve
+/// to public addresses only.
+async fn check_webhook(
+    validation: Result<(), validator::ValidationErrors>,
+    url: Option<&str>,
+    events: Option<&[String]>,
+) -> AppResult<()> {
+    let mut invalid_parameters = match validation {
+        Ok(()) => InvalidParameters::new(),
+        Err(validation_errors) => InvalidParameters::from(validation_errors),
+    };
+
+    if let Some(url) = url {
+        match outbound::check_url(url).await {
+            Ok(_) => {}
+            Err(outbound::UrlError::Invalid) => {
+                invalid_parameters.add("url", InvalidParameterReason::MustBeUrl)
+            }
+            Err(_) => invalid_parameters.add("url", InvalidParameterReason::Other),
+        }
+    }
+    if let Some(events) = events {
+        if events.is_empty()
+            || events
+                .iter()
+                .any(|event| event.parse::<models::WebhookEvent>().is_err())
+        {
+            invalid_parameters.add("events", InvalidParameterReason::Other);
+        }
+    }
+
+    if !invalid_parameters.is_empty() {
+        return Err(invalid_parameters.into_problem());
+    }
+
+    Ok(())
+}
+
+/// Get a webhook, checking the user is permitted to edit the webhooks of the webhook's kit.
+async fn webhook_permission_or_forbidden(
+    pg: PgPool,
+    user_id: Option<authentication::AuthenticatedUser>,
+    kit_serial: String,
+    webhook_id: models::WebhookId,
+) -> AppResult<models::Webhook> {
+    let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+        pg.clone(),
+        user_id,
+        kit_serial,
+        authorization::KitAction::EditWebhooks,
+    )
+    .await?;
+
+    let conn = pg.get().await?;
+    helpers::threadpool(move || match models::Webhook::by_id(&conn, webhook_id)? {
+        Some(webhook) if webhook.kit_id == kit.id => Ok(webhook),
+        _ => Err(problem::NOT_FOUND),
+    })
+    .await
+}
+
+/// Handles the `GET /kits/{kitSerial}/webhooks` route.
+fn kit_webhooks(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::EditWebhooks,
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let webhooks = models::Webhook::webhooks_of_kit_id(&conn, kit.get_id())?;
+            let body: Vec<views::Webhook> =
+                webhooks.into_iter().map(views::Webhook::from).collect();
+            Ok(ResponseBuilder::ok().body(body))
+        })
+        .await
+    }
+
+    warp::get()
+        .and(warp::path!("kits" / String / "webhooks"))
+        .and(authentication::option_by_token())
+        .and_then(move |kit_serial, user_id| {
+            implementation(pg.clone(), kit_serial, user_id).never_error()
+        })
+}
+
+/// Handles the `POST /kits/{kitSerial}/webhooks` route.
+///
+/// The webhook's secret is generated, and is only included in this route's response.
+fn create_webhook(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct Webhook {
+        url: String,
+        events: Vec<String>,
+        enabled: Option<bool>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        user_id: Option<authentication::AuthenticatedUser>,
+        webhook: Webhook,
+    ) -> AppResult<Response> {
+        let (_, _, kit) = helpers::fut_kit_permission_or_forbidden(
+            pg.clone(),
+            user_id,
+            kit_serial,
+            authorization::KitAction::EditWebhooks,
+        )
+        .await?;
+
+        let mut events = webhook.events;
+        events.sort();
+        events.dedup();
+        let new_webhook = models::NewWebhook::new(
+            kit.get_id(),
+            webhook.url,
+            events,
+            webhook.enabled.unwrap_or(true),
+        );
+        check_webhook(
+            new_webhook.validate(),
+            Some(&new_webhook.url),
+            Some(&new_webhook.events),
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        let created_webhook = helpers::threadpool(move || new_webhook.create(&conn)).await?;
+        Ok(ResponseBuilder::created().body(views::WebhookWithSecret::from(created_webhook)))
+    }
+
+    warp::post()
+        .and(warp::path!("kits" / String / "webhooks"))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  webhook: Webhook| {
+                implementation(pg.clone(), kit_serial, user_id, webhook).never_error()
+            },
+        )
+}
+
+/// Handles the `PATCH` and `DELETE /kits/{kitSerial}/webhooks/{webhookId}` routes.
+fn patch_or_delete_webhook(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Deserialize, Debug)]
+    #[serde(rename_all = "camelCase")]
+    struct WebhookPatch {
+        url: Option<String>,
+        events: Option<Vec<String>>,
+        enabled: Option<bool>,
+    }
+
+    async fn patch_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        webhook_id: models::WebhookId,
+        user_id: Option<authentication::AuthenticatedUser>,
+        webhook_patch: WebhookPatch,
+    ) -> AppResult<Response> {
+        let webhook =
+            webhook_permission_or_forbidden(pg.clone(), user_id, kit_serial, webhook_id).await?;
+
+        let update_webhook = models::UpdateWebhook {
+            id: webhook.id,
+            url: webhook_patch.url,
+            events: webhook_patch.events.map(|mut events| {
+                events.sort();
+                events.dedup();
+                events
+            }),
+            enabled: webhook_patch.enabled,
+        };
+        check_webhook(
+            update_webhook.validate(),
+            update_webhook.url.as_deref(),
+            update_webhook.events.as_deref(),
+        )
+        .await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            let patched_webhook = update_webhook.update(&conn)?;
+            Ok(ResponseBuilder::ok().body(views::Webhook::from(patched_webhook)))
+        })
+        .await
+    }
+
+    async fn delete_implementation(
+        pg: PgPool,
+        kit_serial: String,
+        webhook_id: models::WebhookId,
+        user_id: Option<authentication::AuthenticatedUser>,
+    ) -> AppResult<Response> {
+        let webhook =
+            webhook_permission_or_forbidden(pg.clone(), user_id, kit_serial, webhook_id).await?;
+
+        let conn = pg.get().await?;
+        helpers::threadpool(move || {
+            webhook.delete(&conn)?;
+            Ok(ResponseBuilder::ok().empty())
+        })
+        .await
+    }
+
+    let pg2 = pg.clone();
+    (warp::patch()
+        .and(warp::path!("kits" / String / "webhooks" / i32))
+        .and(authentication::option_by_token())
+        .and(crate::helpers::deserialize())
+        .and_then(
+            move |kit_serial: String,
+                  webhook_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  webhook_patch: WebhookPatch| {
+                patch_implementation(
+                    pg.clone(),
+                    kit_serial,
+                    models::WebhookId(webhook_id),
+                    user_id,
+                    webhook_patch,
+                )
+                .never_error()
+            },
+        ))
+    .or(warp::delete()
+        .and(warp::path!("kits" / String / "webhooks" / i32))
+        .and(authentication::option_by_token())
+        .and_then(
+            move |kit_serial: String,
+                  webhook_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>| {
+                delete_implementation(
+                    pg2.clone(),
+                    kit_serial,
+                    models::WebhookId(webhook_id),
+                    user_id,
+                )
+                .never_error()
+            },
+        ))
+    .unify()
+}
+
+/// Handles the `GET /kits/{kitSerial}/webhooks/{webhookId}/deliveries` route.
+fn webhook_deliveries(
+    pg: PgPool,
+) -> impl Filter<Extract = (AppResult<Response>,), Error = Rejection> + Clone {
+    #[derive(Clone, Debug, Serialize, Deserialize)]
+    #[serde(rename_all = "camelCase")]
+    struct Query {
+        cursor: Option<String>,
+    }
+
+    async fn implementation(
+        pg: PgPool,
+        kit_serial: String,
+        webhook_id: models::WebhookId,
+        user_id: Option<authentication::AuthenticatedUser>,
+        query: Query,
+    ) -> AppResult<Response> {
+        let mut out_query = query.clone();
+        let cursor = (&query).cursor.as_ref().map(|s| s.parse()).transpose()?;
+        let base_uri = format!("/kits/{}/webhooks/{}/deliveries", kit_serial, webhook_id.0);
+
+        let webhook =
+            webhook_permission_or_forbidden(pg.clone(), user_id, kit_serial, webhook_id).await?;
+
+        let conn = pg.get().await?;
+        let mut response = ResponseBuilder::ok();
+        let deliveries = helpers::threadpool(move || {
+            models::WebhookDelivery::page(&conn, webhook.get_id(), cursor)
+        })
+        .await?;
+
+        if let Some(next_cursor) = cursors::WebhookDeliveries::next_from_page(&deliveries) {
+            out_query.cursor = Some(next_cursor.into());
+            let next_page_uri = format!(
+                "{}?{}",
+                base_uri,
+                serde_urlencoded::to_string(&out_query).unwrap()
+            );
+            response = response.link(&next_page_uri, "next");
+        }
+
+        let body: Vec<views::WebhookDelivery> = deliveries
+            .into_iter()
+            .map(views::WebhookDelivery::from)
+            .collect();
+        Ok(response.body(body))
+    }
+
+    warp::get()
+        .and(warp::path!(
+            "kits" / String / "webhooks" / i32 / "deliveries"
+        ))
+        .and(authentication::option_by_token())
+        .and(warp::query())
+        .and_then(
+            move |kit_serial: String,
+                  webhook_id: i32,
+                  user_id: Option<authentication::AuthenticatedUser>,
+                  query: Query| {
+                implementation(
+                    pg.clone(),
+                    kit_serial,
+                    models::WebhookId(webhook_id),
+                    user_id,
+                    query,
+                )
+                .never_error()
+            },
+        )
+}
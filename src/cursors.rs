@@ -5,6 +5,21 @@ use serde::{Deserialize, Serialize};
 use std::str::FromStr;
 use uuid::Uuid;
 
+/// The order in which a paginated collection is listed. Collections are ordered by datetime, with
+/// ties broken by id.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Order {
+    Asc,
+    Desc,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Desc
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct AggregateMeasurements(pub DateTime<Utc>, pub Uuid);
 
@@ -35,6 +50,36 @@ impl AggregateMeasurements {
     }
 }
 
+#[derive(Deserialize, Serialize)]
+pub struct RawMeasurements(pub DateTime<Utc>, pub Uuid);
+
+impl FromStr for RawMeasurements {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<RawMeasurements> for String {
+    fn from(cursor: RawMeasurements) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl RawMeasurements {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::RawMeasurement]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let measurement = page.last().unwrap();
+            Some(Self(measurement.datetime, measurement.id))
+        } else {
+            None
+        }
+    }
+}
+
 #[derive(Deserialize, Serialize)]
 pub struct Media(pub DateTime<Utc>, pub Uuid);
 
@@ -64,3 +109,63 @@ impl Media {
         }
     }
 }
+
+#[derive(Deserialize, Serialize)]
+pub struct Alerts(pub DateTime<Utc>, pub i32);
+
+impl FromStr for Alerts {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<Alerts> for String {
+    fn from(cursor: Alerts) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl Alerts {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::Alert]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let alert = page.last().unwrap();
+            Some(Self(alert.datetime_fired, alert.id))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct WebhookDeliveries(pub DateTime<Utc>, pub i32);
+
+impl FromStr for WebhookDeliveries {
+    type Err = Problem;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        serde_json::from_str(s).map_err(|_| BAD_REQUEST)
+    }
+}
+
+impl From<WebhookDeliveries> for String {
+    fn from(cursor: WebhookDeliveries) -> Self {
+        serde_json::to_string(&cursor).unwrap()
+    }
+}
+
+impl WebhookDeliveries {
+    pub const PER_PAGE: usize = 50;
+
+    pub fn next_from_page(page: &[models::WebhookDelivery]) -> Option<Self> {
+        if page.len() >= Self::PER_PAGE {
+            let delivery = page.last().unwrap();
+            Some(Self(delivery.datetime_created, delivery.id))
+        } else {
+            None
+        }
+    }
+}
@@ -3,9 +3,15 @@ use log::error;
 use serde::de::DeserializeOwned;
 use warp::{Filter, Rejection};
 
+use crate::authentication::AuthenticatedUser;
 use crate::authorization::{KitUser, Permission};
 use crate::database::PgPool;
-use crate::problem::{AppResult, Problem, FORBIDDEN, INTERNAL_SERVER_ERROR, NOT_FOUND};
+use crate::problem::{
+    AccessTokenProblemCategory, AppResult, Problem, FORBIDDEN, INTERNAL_SERVER_ERROR, NOT_FOUND,
+};
+
+/// The minimum time between recording uses of a personal access token.
+const PERSONAL_ACCESS_TOKEN_TOUCH_INTERVAL_SECONDS: i64 = 60;
 
 /// Run a blocking function on a threadpool.
 pub async fn threadpool<F, T>(f: F) -> T
@@ -100,26 +106,28 @@ where
 }
 
 /**
- * Ensure the user has permission to perform the action on the kit.
- * Rejects the request with FORBIDDEN otherwise.
+ * Fetch the user, their membership of the kit, the kit, and the personal access token the user
+ * authenticated with (if any).
  *
- * Fetches the required information from the database.
  * If the user id is given but the user cannot be found or if the kit cannot be found with the
- * given serial, the request is rejected with NOT_FOUND. If the request is *not* rejected, this
- * returns the fetched user, membership and kit.
+ * given serial, the request is rejected with NOT_FOUND. If the personal access token has been
+ * revoked, the request is rejected as unauthorized. The token's last use is recorded.
  */
-pub async fn fut_kit_permission_or_forbidden<'a>(
+pub async fn fut_kit_actor(
     pg: PgPool,
-    user_id: Option<crate::models::UserId>,
+    user: Option<AuthenticatedUser>,
     kit_serial: String,
-    action: crate::authorization::KitAction,
 ) -> AppResult<(
     Option<crate::models::User>,
     Option<crate::models::KitMembership>,
     crate::models::Kit,
+    Option<crate::models::PersonalAccessToken>,
 )> {
     use diesel::Connection;
 
+    let user_id = user.map(|user| user.user_id);
+    let personal_access_token_id = user.and_then(|user| user.personal_access_token_id);
+
     let conn = pg.get().await?;
     threadpool(move || {
         conn.transaction(|| {
@@ -144,22 +152,75 @@ pub async fn fut_kit_permission_or_forbidden<'a>(
                 None
             };
 
-            Ok(Some((user, membership, kit)))
+            let personal_access_token = match (user_id, personal_access_token_id) {
+                (Some(user_id), Some(personal_access_token_id)) => {
+                    let now = chrono::Utc::now();
+                    let token = crate::models::PersonalAccessToken::active_by_id(
+                        &conn,
+                        user_id,
+                        personal_access_token_id,
+                        now,
+                    )?
+                    .ok_or(Problem::AuthorizationHeader {
+                        category: AccessTokenProblemCategory::Revoked,
+                    })?;
+
+                    let touch_interval =
+                        chrono::Duration::seconds(PERSONAL_ACCESS_TOKEN_TOUCH_INTERVAL_SECONDS);
+                    match token.datetime_last_used {
+                        Some(last_used) if now - last_used < touch_interval => Some(token),
+                        _ => Some(token.touch(&conn, now)?),
+                    }
+                }
+                _ => None,
+            };
+
+            Ok(Some((user, membership, kit, personal_access_token)))
         })
     })
     .and_then(|v| async { some_or_not_found(v) })
-    .and_then(|(user, membership, kit)| async move {
-        // TODO clone should not be necessary.
-        let kit_user = match (user.clone(), membership.clone()) {
-            (None, _) => KitUser::Anonymous,
-            (Some(user), None) => KitUser::User(user),
-            (Some(user), Some(kit_membership)) => KitUser::UserWithMembership(user, kit_membership),
-        };
-        permission_or_forbidden(&kit_user, &kit, action).map(|_| (user, membership, kit))
-    })
     .await
 }
 
+/**
+ * Ensure the user has permission to perform the action on the kit.
+ * Rejects the request with FORBIDDEN otherwise.
+ *
+ * Fetches the required information from the database, see `fut_kit_actor`. If the request is
+ * *not* rejected, this returns the fetched user, membership and kit.
+ *
+ * If the user authenticated with a personal access token, the token must permit the action on
+ * the kit. The membership is then not returned, such that actions requiring the membership itself
+ * are forbidden.
+ */
+pub async fn fut_kit_permission_or_forbidden<'a>(
+    pg: PgPool,
+    user: Option<AuthenticatedUser>,
+    kit_serial: String,
+    action: crate::authorization::KitAction,
+) -> AppResult<(
+    Option<crate::models::User>,
+    Option<crate::models::KitMembership>,
+    crate::models::Kit,
+)> {
+    let (user, membership, kit, personal_access_token) =
+        fut_kit_actor(pg, user, kit_serial).await?;
+
+    // TODO clone should not be necessary.
+    let kit_user = match (user.clone(), membership.clone()) {
+        (None, _) => KitUser::Anonymous,
+        (Some(user), None) => KitUser::User(user),
+        (Some(user), Some(kit_membership)) => KitUser::UserWithMembership(user, kit_membership),
+    };
+    permission_or_forbidden(&kit_user, &kit, action)?;
+
+    match personal_access_token {
+        None => Ok((user, membership, kit)),
+        Some(token) if token.permits(kit.get_id(), action) => Ok((user, None, kit)),
+        Some(_) => Err(FORBIDDEN),
+    }
+}
+
 /**
  * Ensure the user has permission to perform the action on the target user.
  * Rejects the request with FORBIDDEN otherwise.
@@ -168,15 +229,20 @@ pub async fn fut_kit_permission_or_forbidden<'a>(
  * If the actor user id is given but the user cannot be found or if the target user cannot be found with the
  * given username, the request is rejected with NOT_FOUND. If the request is *not* rejected, this
  * returns the fetched actor and target users.
+ *
+ * Actors authenticated with a personal access token are treated as anonymous.
  */
 pub async fn fut_user_permission_or_forbidden(
     pg: PgPool,
-    actor_user_id: Option<crate::models::UserId>,
+    actor: Option<AuthenticatedUser>,
     object_username: String,
     action: crate::authorization::UserAction,
 ) -> AppResult<(Option<crate::models::User>, crate::models::User)> {
     use diesel::Connection;
 
+    // Personal access tokens are scoped to kits, so they act on users anonymously.
+    let actor_user_id = actor.and_then(|actor| actor.full_user_id());
+
     let conn = pg.get().await?;
     threadpool(move || {
         conn.transaction(|| {
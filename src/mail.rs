@@ -0,0 +1,145 @@
+//! Sends emails to users, e.g. for password resets.
+//!
+//! Mail is sent through a transport chosen at startup. In production this is SMTP; during
+//! development and testing, mail can instead be written to files or to the log.
+
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    Email(lettre_email::error::Error),
+    Smtp(lettre::smtp::error::Error),
+    Io(std::io::Error),
+}
+
+impl From<lettre_email::error::Error> for Error {
+    fn from(err: lettre_email::error::Error) -> Self {
+        Error::Email(err)
+    }
+}
+
+impl From<lettre::smtp::error::Error> for Error {
+    fn from(err: lettre::smtp::error::Error) -> Self {
+        Error::Smtp(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+/// A plain-text email.
+#[derive(Clone, Debug)]
+pub struct Message {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+#[derive(Clone, Debug)]
+enum Transports {
+    Smtp {
+        host: String,
+        credentials: Option<(String, String)>,
+    },
+    File {
+        root: PathBuf,
+    },
+    Log,
+}
+
+// Ideally this would be a trait, but we can't yet "just" use async functions in traits.
+#[derive(Clone, Debug)]
+pub struct Mailer {
+    from: String,
+    transport: Transports,
+}
+
+impl Mailer {
+    /// Send mail through an SMTP server, using TLS on the submissions port.
+    pub fn smtp(from: String, host: String, credentials: Option<(String, String)>) -> Self {
+        Self {
+            from,
+            transport: Transports::Smtp { host, credentials },
+        }
+    }
+
+    /// Write mail to files in the given directory, rather than sending it.
+    pub fn file(from: String, root: &str) -> Self {
+        Self {
+            from,
+            transport: Transports::File {
+                root: PathBuf::from(root),
+            },
+        }
+    }
+
+    /// Write mail to the log, rather than sending it.
+    pub fn log(from: String) -> Self {
+        Self {
+            from,
+            transport: Transports::Log,
+        }
+    }
+
+    pub async fn send(&self, message: Message) -> Result<(), Error> {
+        let from = self.from.clone();
+        let transport = self.transport.clone();
+        crate::helpers::threadpool(move || match transport {
+            Transports::Smtp { host, credentials } => {
+                use lettre::smtp::authentication::Credentials;
+                use lettre::{SmtpClient, Transport};
+
+                let email = lettre_email::EmailBuilder::new()
+                    .to(message.to)
+                    .from(from)
+                    .subject(message.subject)
+                    .text(message.body)
+                    .build()?;
+
+                let mut client = SmtpClient::new_simple(&host)?;
+                if let Some((username, password)) = credentials {
+                    client = client.credentials(Credentials::new(username, password));
+                }
+                client.transport().send(email.into())?;
+                Ok(())
+            }
+            Transports::File { root } => {
+                let path = root.join(format!(
+                    "{}-{}.txt",
+                    chrono::Utc::now().format("%Y%m%dT%H%M%S%.f"),
+                    uuid::Uuid::new_v4()
+                ));
+                std::fs::create_dir_all(&root)?;
+                std::fs::write(&path, format_message(&from, &message))?;
+                Ok(())
+            }
+            Transports::Log => {
+                info!("Mail:\n{}", format_message(&from, &message));
+                Ok(())
+            }
+        })
+        .await
+    }
+
+    /// Send mail in the background, logging failures. Useful where the outcome of sending should
+    /// not influence the response, or its timing.
+    pub fn send_in_background(&self, message: Message) {
+        let mailer = self.clone();
+        tokio::spawn(async move {
+            let to = message.to.clone();
+            if let Err(err) = mailer.send(message).await {
+                warn!("could not send mail to {}: {:?}", to, err);
+            }
+        });
+    }
+}
+
+fn format_message(from: &str, message: &Message) -> String {
+    format!(
+        "From: {}\nTo: {}\nSubject: {}\n\n{}\n",
+        from, message.to, message.subject, message.body
+    )
+}
@@ -25,13 +25,19 @@ mod rate_limit;
 mod schema;
 
 mod controllers;
+mod mail;
+mod media;
 mod models;
 mod response;
 mod views;
 
 mod mqtt;
+mod webhook;
 mod websocket;
 
+#[cfg(test)]
+mod test_support;
+
 use problem::{AppResult, DescriptiveProblem, Problem};
 use response::{Response, ResponseBuilder, ResponseValue};
 
@@ -43,6 +49,11 @@ static DEFAULT_MQTT_USERNAME: &str = "server";
 static DEFAULT_MQTT_PASSWORD: &str = "";
 static DEFAULT_S3_REGION: &str = "us-east-1";
 static DEFAULT_S3_ENDPOINT: &str = "http://localhost";
+const DEFAULT_RAW_MEASUREMENT_RETENTION_DAYS: i64 = 30;
+const DEFAULT_KIT_OFFLINE_AFTER_SECONDS: i64 = 300;
+const DEFAULT_AUTH_RATE_LIMIT_REQUESTS: u32 = 5;
+const DEFAULT_AUTH_RATE_LIMIT_SECONDS: u64 = 60;
+static DEFAULT_MAIL_FROM: &str = "noreply@astroplant.io";
 
 static TOKEN_SIGNER: OnceCell<astroplant_auth::token::TokenSigner> = OnceCell::new();
 
@@ -51,6 +62,7 @@ async fn main() {
     env_logger::init();
 
     init_token_signer();
+    init_password_hashing();
 
     let pg = database::PgPool::new(
         std::env::var("DATABASE_URL").unwrap_or(DEFAULT_DATABASE_URL.to_owned()),
@@ -62,29 +74,88 @@ async fn main() {
         std::env::var("AWS_S3_ENDPOINT").unwrap_or(DEFAULT_S3_ENDPOINT.to_owned()),
     );
 
+    // A quota of zero bytes disables the media quota.
+    let media_quota = std::env::var("KIT_MEDIA_QUOTA_BYTES")
+        .ok()
+        .and_then(|bytes| bytes.parse::<i64>().ok())
+        .filter(|&bytes| bytes > 0)
+        .map(|bytes| media::Quota {
+            bytes,
+            evict_oldest: std::env::var("KIT_MEDIA_QUOTA_EVICT_OLDEST")
+                .map(|evict| evict == "true" || evict == "1")
+                .unwrap_or(false),
+        });
+
+    let mailer = init_mailer();
+
+    // A stricter rate limit for routes authenticating users, such as logging in.
+    let auth_rate_limit = rate_limit::Limit {
+        requests: std::env::var("AUTH_RATE_LIMIT_REQUESTS")
+            .ok()
+            .and_then(|requests| requests.parse().ok())
+            .unwrap_or(std::num::NonZeroU32::new(DEFAULT_AUTH_RATE_LIMIT_REQUESTS).unwrap()),
+        per: std::time::Duration::from_secs(
+            std::env::var("AUTH_RATE_LIMIT_SECONDS")
+                .ok()
+                .and_then(|seconds| seconds.parse().ok())
+                .unwrap_or(DEFAULT_AUTH_RATE_LIMIT_SECONDS),
+        ),
+    };
+
+    let kit_offline_after = chrono::Duration::seconds(
+        std::env::var("KIT_OFFLINE_AFTER_SECONDS")
+            .map_err(|_| ())
+            .and_then(|seconds| seconds.parse().map_err(|_| ()))
+            .unwrap_or(DEFAULT_KIT_OFFLINE_AFTER_SECONDS),
+    );
+
+    let kit_creation_requires_verified_email =
+        std::env::var("KIT_CREATION_REQUIRES_VERIFIED_EMAIL")
+            .map(|requires| requires == "true" || requires == "1")
+            .unwrap_or(false);
+
     // Start MQTT.
-    let (raw_measurement_receiver, kits_rpc) = mqtt::run(pg.clone(), object_store.clone());
+    let (event_receiver, kits_rpc) = mqtt::run(
+        pg.clone(),
+        object_store.clone(),
+        media_quota,
+        kit_offline_after,
+    );
 
     // Start WebSockets.
-    let (ws_endpoint, publisher) = astroplant_websocket::run();
-    tokio::runtime::Handle::current().spawn(websocket::run(publisher, raw_measurement_receiver));
+    let (ws_endpoint, publisher) =
+        astroplant_websocket::run(websocket::authenticate, websocket::authorizer(pg.clone()));
+    tokio::runtime::Handle::current().spawn(websocket::run(publisher, event_receiver));
 
-    let rate_limit = rate_limit::leaky_bucket();
+    // Start webhook deliveries.
+    tokio::runtime::Handle::current().spawn(webhook::run_worker(pg.clone()));
+    tokio::runtime::Handle::current().spawn(webhook::run_pruner(pg.clone()));
+
+    let rate_limit = rate_limit::leaky_bucket(rate_limit::Limit::default());
 
     let rest_endpoints = ((path!("version").map(|| Ok(ResponseBuilder::ok().body(VERSION))))
         .or(path!("time")
             .map(|| Ok(ResponseBuilder::ok().body(chrono::Utc::now().to_rfc3339())))
             .boxed())
         .unify()
-        .or(path!("kits" / ..).and(controllers::kit::router(pg.clone())))
+        .or(path!("kits" / ..).and(controllers::kit::router(
+            pg.clone(),
+            object_store.clone(),
+            kit_offline_after,
+            kit_creation_requires_verified_email,
+        )))
         .unify()
         .or(controllers::kit_configuration::router(pg.clone()))
         .unify()
         .or(path!("kit-rpc" / ..).and(controllers::kit_rpc::router(kits_rpc, pg.clone())))
         .unify()
-        .or(path!("users" / ..).and(controllers::user::router(pg.clone())))
+        .or(path!("users" / ..).and(controllers::user::router(pg.clone(), mailer.clone())))
         .unify()
-        .or(path!("me" / ..).and(controllers::me::router(pg.clone())))
+        .or(path!("me" / ..).and(controllers::me::router(
+            pg.clone(),
+            mailer.clone(),
+            auth_rate_limit,
+        )))
         .unify()
         .or(path!("peripheral-definitions" / ..)
             .and(controllers::peripheral_definition::router(pg.clone())))
@@ -95,11 +166,22 @@ async fn main() {
         .unify()
         .or(controllers::measurement::router(pg.clone()))
         .unify()
-        .or(controllers::media::router(pg.clone(), object_store.clone()))
+        .or(controllers::media::router(
+            pg.clone(),
+            object_store.clone(),
+            media_quota,
+        ))
+        .unify()
+        .or(controllers::alert::router(pg.clone()))
+        .unify()
+        .or(controllers::webhook::router(pg.clone()))
+        .unify()
+        .or(controllers::kit_membership::router(pg.clone()))
         .unify())
     .and(warp::header("Accept"))
     .map(|response: AppResult<Response>, _accept: String| {
-        // TODO: utilize Accept header, e.g. returning XML when requested.
+        // TODO: utilize Accept header, e.g. returning XML when requested. Endpoints that can
+        // export data (e.g. as CSV) negotiate the format themselves.
         let mut http_response_builder = warp::http::response::Builder::new();
         match response {
             Ok(response) => {
@@ -181,7 +263,7 @@ async fn main() {
                 Method::OPTIONS,
             ])
             .allow_headers(vec!["Content-Type", "Authorization"])
-            .expose_headers(vec!["Link"])
+            .expose_headers(vec!["Link", "Content-Disposition"])
             .build(),
     );
 
@@ -228,6 +310,56 @@ fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Rejection> {
     ))
 }
 
+/// Initialize the mailer. Mail is written to the log, unless another transport is configured.
+fn init_mailer() -> mail::Mailer {
+    let from = std::env::var("MAIL_FROM").unwrap_or(DEFAULT_MAIL_FROM.to_owned());
+
+    match std::env::var("MAIL_TRANSPORT").as_ref().map(String::as_str) {
+        Ok("smtp") => {
+            let host = std::env::var("SMTP_HOST").expect("SMTP_HOST must be set to send mail");
+            let credentials = std::env::var("SMTP_USERNAME")
+                .ok()
+                .map(|username| (username, std::env::var("SMTP_PASSWORD").unwrap_or_default()));
+            debug!("Sending mail through SMTP server {}", host);
+            mail::Mailer::smtp(from, host, credentials)
+        }
+        Ok("file") => {
+            let root = std::env::var("MAIL_DIRECTORY").unwrap_or("./mail".to_owned());
+            debug!("Writing mail to directory {}", root);
+            mail::Mailer::file(from, &root)
+        }
+        _ => {
+            debug!("Writing mail to the log");
+            mail::Mailer::log(from)
+        }
+    }
+}
+
+/// Initialize the parameters user passwords are hashed with. Password hashes generated with other
+/// parameters are upgraded when their users log in.
+///
+/// # Panics
+/// This function is only callable once; it panics if called multiple times.
+fn init_password_hashing() {
+    use astroplant_auth::hash::{init_v2_parameters, V2Parameters};
+
+    let default = V2Parameters::default();
+    let parameter = |name: &str, default: u32| {
+        std::env::var(name)
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(default)
+    };
+    let parameters = V2Parameters {
+        memory_kib: parameter("PASSWORD_HASH_MEMORY_KIB", default.memory_kib),
+        iterations: parameter("PASSWORD_HASH_ITERATIONS", default.iterations),
+        parallelism: parameter("PASSWORD_HASH_PARALLELISM", default.parallelism),
+    };
+    debug!("Hashing passwords with parameters {:?}", parameters);
+
+    init_v2_parameters(parameters);
+}
+
 /// Initialize the token signer.
 ///
 /// # Panics
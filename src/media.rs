@@ -0,0 +1,402 @@
+//! Operations on media spanning both the database and the object store.
+
+use futures::stream::{self, StreamExt, TryStreamExt};
+use std::time::Duration;
+
+use crate::database::PgPool;
+use crate::helpers;
+use crate::models;
+use crate::problem::{AppResult, INTERNAL_SERVER_ERROR, NOT_FOUND, UNPROCESSABLE_ENTITY};
+use crate::response::ByteStream;
+
+/// The amount of media fetched at once when evicting media.
+const EVICTION_BATCH_SIZE: i64 = 16;
+
+/// The maximum amount of attempts at deleting an object of deleted media.
+const OBJECT_DELETION_ATTEMPTS: u32 = 5;
+
+/// A storage quota on the media of each kit.
+#[derive(Copy, Clone, Debug)]
+pub struct Quota {
+    /// The maximum amount of bytes the media of a kit may take up.
+    pub bytes: i64,
+    /// Whether to delete the oldest media of a kit to make room for new media, rather than
+    /// rejecting new media.
+    pub evict_oldest: bool,
+}
+
+/// The name of the object holding the content of media.
+pub fn object_name(media_id: models::MediaId) -> String {
+    media_id.0.to_hyphenated().to_string()
+}
+
+/// The URI at which the content of media can be downloaded.
+pub fn content_uri(media_id: models::MediaId) -> String {
+    format!("/media/{}/content", media_id.0.to_hyphenated())
+}
+
+/// The widths in pixels of the resized derivatives generated of image media. Requested widths
+/// are rounded up to the nearest of these, such that a bounded set of derivatives is cached.
+pub const DERIVATIVE_WIDTHS: [u32; 6] = [64, 128, 256, 512, 1024, 2048];
+
+/// The width in pixels of thumbnails.
+pub const THUMBNAIL_WIDTH: u32 = 256;
+
+/// Images larger than this (in bytes) are not resized.
+const MAX_RESIZE_SOURCE_SIZE: i64 = 32 * 1024 * 1024;
+
+/// Images with more pixels than this are not resized, as decoding them would take up too much
+/// memory. Small files can hold images with many pixels, so this is checked separately from
+/// `MAX_RESIZE_SOURCE_SIZE`.
+const MAX_RESIZE_SOURCE_PIXELS: u64 = 50_000_000;
+
+/// The width of the derivative to serve for a requested width, or `None` if the requested width
+/// is out of range.
+pub fn derivative_width(requested: u32) -> Option<u32> {
+    if requested == 0 {
+        return None;
+    }
+    DERIVATIVE_WIDTHS
+        .iter()
+        .copied()
+        .find(|&width| width >= requested)
+}
+
+/// The name of the object holding a resized derivative of media.
+fn derivative_object_name(media_id: models::MediaId, width: u32) -> String {
+    format!("{}.w{}", object_name(media_id), width)
+}
+
+/// The image format of media of the given type, if derivatives can be generated of it.
+fn image_format(media_type: &str) -> Option<image::ImageFormat> {
+    match media_type {
+        "image/jpeg" => Some(image::ImageFormat::Jpeg),
+        "image/png" => Some(image::ImageFormat::Png),
+        _ => None,
+    }
+}
+
+/// Whether resized derivatives can be generated of the media.
+pub fn is_resizable(media: &models::Media) -> bool {
+    image_format(&media.r#type).is_some() && media.size <= MAX_RESIZE_SOURCE_SIZE
+}
+
+/// An error encountered while resizing an image.
+#[derive(Debug)]
+enum ResizeError {
+    /// The image could not be decoded.
+    Decoding(image::ImageError),
+    /// The image has too many pixels to be decoded; its width and height are given.
+    TooManyPixels(u32, u32),
+    /// The resized image could not be encoded.
+    Encoding(image::ImageError),
+}
+
+/// Resize an image to the given width, preserving its aspect ratio and format. Images that are
+/// not wider than the given width are returned as-is.
+///
+/// The dimensions of the image are read before it is decoded, such that images with more than
+/// `MAX_RESIZE_SOURCE_PIXELS` pixels are rejected without being decoded.
+fn resize(data: Vec<u8>, format: image::ImageFormat, width: u32) -> Result<Vec<u8>, ResizeError> {
+    let (source_width, source_height) =
+        image::io::Reader::with_format(std::io::Cursor::new(&data), format)
+            .into_dimensions()
+            .map_err(ResizeError::Decoding)?;
+    if source_width <= width {
+        return Ok(data);
+    }
+    if u64::from(source_width) * u64::from(source_height) > MAX_RESIZE_SOURCE_PIXELS {
+        return Err(ResizeError::TooManyPixels(source_width, source_height));
+    }
+
+    let image =
+        image::load_from_memory_with_format(&data, format).map_err(ResizeError::Decoding)?;
+
+    let resized = image.resize(width, u32::MAX, image::imageops::FilterType::Triangle);
+    let mut resized_data = Vec::new();
+    let output_format = match format {
+        image::ImageFormat::Png => image::ImageOutputFormat::Png,
+        _ => image::ImageOutputFormat::Jpeg(85),
+    };
+    resized
+        .write_to(&mut resized_data, output_format)
+        .map_err(ResizeError::Encoding)?;
+    Ok(resized_data)
+}
+
+/// Get the content of image media resized to the given width, which must be one of
+/// `DERIVATIVE_WIDTHS`. The resized image is generated on first request, and cached in the object
+/// store.
+pub async fn derivative(
+    object_store: &astroplant_object::ObjectStore,
+    kit_serial: &str,
+    media: &models::Media,
+    width: u32,
+) -> AppResult<ByteStream> {
+    let format = match image_format(&media.r#type) {
+        Some(format) if is_resizable(media) => format,
+        _ => return Err(NOT_FOUND),
+    };
+    let media_id = media.get_id();
+    let derivative_name = derivative_object_name(media_id, width);
+
+    match object_store.get(kit_serial, &derivative_name).await {
+        Ok(stream) => return Ok(stream),
+        Err(err) if err.is_not_found() => {}
+        Err(err) => {
+            error!(
+                "could not get derivative {} of kit {}: {:?}",
+                derivative_name, kit_serial, err
+            );
+            return Err(INTERNAL_SERVER_ERROR);
+        }
+    }
+
+    let original: Vec<u8> = object_store
+        .get(kit_serial, &object_name(media_id))
+        .await
+        .map_err(|err| {
+            error!(
+                "could not get media {} of kit {}: {:?}",
+                media_id.0, kit_serial, err
+            );
+            INTERNAL_SERVER_ERROR
+        })?
+        .map_ok(|data| data.to_vec())
+        .try_concat()
+        .await
+        .map_err(|err| {
+            error!(
+                "could not read media {} of kit {}: {:?}",
+                media_id.0, kit_serial, err
+            );
+            INTERNAL_SERVER_ERROR
+        })?;
+
+    let resized = helpers::threadpool(move || resize(original, format, width))
+        .await
+        .map_err(|err| match err {
+            ResizeError::Encoding(err) => {
+                error!("could not encode resized media {}: {:?}", media_id.0, err);
+                INTERNAL_SERVER_ERROR
+            }
+            ResizeError::Decoding(err) => {
+                warn!("could not decode media {}: {:?}", media_id.0, err);
+                UNPROCESSABLE_ENTITY
+            }
+            ResizeError::TooManyPixels(width, height) => {
+                warn!(
+                    "media {} of {}x{} pixels is too large to be resized",
+                    media_id.0, width, height
+                );
+                UNPROCESSABLE_ENTITY
+            }
+        })?;
+
+    // Failing to cache the derivative is not fatal: it is generated again on the next request.
+    if let Err(err) = object_store
+        .put(
+            kit_serial,
+            &derivative_name,
+            resized.clone(),
+            media.r#type.clone(),
+        )
+        .await
+    {
+        warn!(
+            "could not cache derivative {} of kit {}: {:?}",
+            derivative_name, kit_serial, err
+        );
+    }
+
+    Ok(stream::once(async { Ok(resized.into()) }).boxed())
+}
+
+/// Delete media of a kit, both its database row and its content (including any resized
+/// derivatives). The row is deleted first, such that media are never listed without content. The
+/// content is deleted afterwards in the background, see `delete_objects`.
+pub async fn delete(
+    pg: PgPool,
+    object_store: &astroplant_object::ObjectStore,
+    kit_serial: &str,
+    media: models::Media,
+) -> AppResult<()> {
+    let object_names = object_names(&media);
+
+    let conn = pg.get().await?;
+    helpers::threadpool_result(move || media.delete(&conn)).await?;
+
+    let object_store = object_store.clone();
+    let kit_serial = kit_serial.to_owned();
+    tokio::spawn(async move {
+        delete_objects(&object_store, &kit_serial, object_names).await;
+    });
+
+    Ok(())
+}
+
+/// The names of the objects holding the content of media, including any resized derivatives.
+pub fn object_names(media: &models::Media) -> Vec<String> {
+    let media_id = media.get_id();
+    let mut object_names = vec![object_name(media_id)];
+    if is_resizable(media) {
+        object_names.extend(
+            DERIVATIVE_WIDTHS
+                .iter()
+                .map(|&width| derivative_object_name(media_id, width)),
+        );
+    }
+    object_names
+}
+
+/// Delete objects of a kit of which the media were deleted from the database. Failed deletions
+/// are retried with exponential backoff; objects that still cannot be deleted are orphaned.
+pub async fn delete_objects(
+    object_store: &astroplant_object::ObjectStore,
+    kit_serial: &str,
+    object_names: Vec<String>,
+) {
+    for object_name in object_names {
+        let mut attempts = 1;
+        loop {
+            match object_store.delete(kit_serial, &object_name).await {
+                Ok(()) => break,
+                Err(err) if attempts < OBJECT_DELETION_ATTEMPTS => {
+                    warn!(
+                        "could not delete object {} of kit {}, retrying: {:?}",
+                        object_name, kit_serial, err
+                    );
+                    tokio::time::delay_for(Duration::from_secs(1 << attempts)).await;
+                    attempts += 1;
+                }
+                Err(err) => {
+                    error!(
+                        "giving up on deleting object {} of kit {}: {:?}",
+                        object_name, kit_serial, err
+                    );
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Make room for new media of the given size within a kit's quota, evicting the kit's oldest media
+/// if the quota allows it. Returns whether the new media fit.
+///
+/// The quota is not enforced atomically: concurrent uploads may exceed it.
+pub async fn make_room(
+    pg: PgPool,
+    object_store: &astroplant_object::ObjectStore,
+    kit: &models::Kit,
+    quota: Quota,
+    size: i64,
+) -> AppResult<bool> {
+    if size > quota.bytes {
+        return Ok(false);
+    }
+
+    let kit_id = kit.get_id();
+    loop {
+        let conn = pg.clone().get().await?;
+        let usage =
+            helpers::threadpool_result(move || models::Media::usage_of_kit(&conn, kit_id)).await?;
+        let used: i64 = usage.iter().map(|usage| usage.bytes).sum();
+
+        let excess = used + size - quota.bytes;
+        if excess <= 0 {
+            return Ok(true);
+        }
+        if !quota.evict_oldest {
+            return Ok(false);
+        }
+
+        let conn = pg.clone().get().await?;
+        let oldest = helpers::threadpool_result(move || {
+            models::Media::oldest_of_kit(&conn, kit_id, EVICTION_BATCH_SIZE)
+        })
+        .await?;
+        if oldest.is_empty() {
+            return Ok(false);
+        }
+
+        let mut freed = 0;
+        for media in oldest {
+            if freed >= excess {
+                break;
+            }
+            freed += media.size;
+            info!(
+                "evicting media {} ({} bytes) of kit {} to stay within its quota",
+                media.id, media.size, kit.serial
+            );
+            delete(pg.clone(), object_store, &kit.serial, media).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{derivative_width, resize, ResizeError};
+    use image::GenericImageView;
+
+    #[test]
+    fn derivative_widths_round_up() {
+        assert_eq!(derivative_width(0), None);
+        assert_eq!(derivative_width(1), Some(64));
+        assert_eq!(derivative_width(200), Some(256));
+        assert_eq!(derivative_width(256), Some(256));
+        assert_eq!(derivative_width(2048), Some(2048));
+        assert_eq!(derivative_width(2049), None);
+    }
+
+    #[test]
+    fn resize_preserves_aspect_ratio() {
+        let image = image::DynamicImage::new_rgb8(400, 300);
+        let mut data = Vec::new();
+        image
+            .write_to(&mut data, image::ImageOutputFormat::Png)
+            .unwrap();
+
+        let resized = resize(data.clone(), image::ImageFormat::Png, 128).unwrap();
+        let resized =
+            image::load_from_memory_with_format(&resized, image::ImageFormat::Png).unwrap();
+        assert_eq!((resized.width(), resized.height()), (128, 96));
+
+        // Images are never enlarged.
+        assert_eq!(
+            resize(data.clone(), image::ImageFormat::Png, 512).unwrap(),
+            data
+        );
+    }
+
+    #[test]
+    fn resize_rejects_undecodable_images() {
+        match resize(b"not an image".to_vec(), image::ImageFormat::Png, 128) {
+            Err(ResizeError::Decoding(_)) => {}
+            other => panic!("expected a decoding error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn resize_rejects_images_with_too_many_pixels_without_decoding() {
+        let image = image::DynamicImage::new_rgb8(16, 16);
+        let mut data = Vec::new();
+        image
+            .write_to(&mut data, image::ImageOutputFormat::Jpeg(85))
+            .unwrap();
+
+        // Claim a height and width of 60000 pixels in the start of frame segment, which is laid
+        // out as the marker, the segment length, the sample precision, the height and the width.
+        let start_of_frame = data
+            .windows(2)
+            .position(|marker| marker == [0xff, 0xc0])
+            .unwrap();
+        data[start_of_frame + 5..start_of_frame + 9].copy_from_slice(&[0xea, 0x60, 0xea, 0x60]);
+
+        match resize(data, image::ImageFormat::Jpeg, 128) {
+            Err(ResizeError::TooManyPixels(60_000, 60_000)) => {}
+            other => panic!("expected too many pixels, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,278 @@
+use crate::cursors;
+use crate::schema::{alert_rules, alerts, kits};
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use std::str::FromStr;
+use validator::Validate;
+
+#[rustfmt::skip]
+use super::{
+    Kit, KitId,
+    Peripheral, PeripheralId,
+    QuantityType, QuantityTypeId,
+};
+
+/// The condition under which an alert rule fires, relative to its threshold.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum AlertCondition {
+    Above,
+    Below,
+}
+
+impl AlertCondition {
+    /// Whether the value meets this condition relative to the threshold.
+    pub fn holds(self, value: f64, threshold: f64) -> bool {
+        match self {
+            AlertCondition::Above => value > threshold,
+            AlertCondition::Below => value < threshold,
+        }
+    }
+}
+
+impl FromStr for AlertCondition {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "above" => Ok(AlertCondition::Above),
+            "below" => Ok(AlertCondition::Below),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "alert_rules"]
+pub struct AlertRuleId(#[column_name = "id"] pub i32);
+
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "Kit", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
+#[belongs_to(parent = "Peripheral", foreign_key = "peripheral_id")]
+#[belongs_to(parent = "PeripheralId", foreign_key = "peripheral_id")]
+#[belongs_to(parent = "QuantityType", foreign_key = "quantity_type_id")]
+#[belongs_to(parent = "QuantityTypeId", foreign_key = "quantity_type_id")]
+#[table_name = "alert_rules"]
+pub struct AlertRule {
+    pub id: i32,
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub condition: String,
+    pub threshold: f64,
+    pub duration_seconds: i32,
+    pub description: Option<String>,
+    pub webhook_url: Option<String>,
+    pub enabled: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Identifiable, AsChangeset)]
+#[table_name = "alert_rules"]
+pub struct UpdateAlertRule {
+    pub id: i32,
+    // None means don't update, Some(None) means set to null.
+    pub condition: Option<String>,
+    pub threshold: Option<f64>,
+    pub duration_seconds: Option<i32>,
+    pub description: Option<Option<String>>,
+    pub webhook_url: Option<Option<String>>,
+    pub enabled: Option<bool>,
+}
+
+impl AlertRule {
+    pub fn by_id(conn: &PgConnection, alert_rule_id: AlertRuleId) -> QueryResult<Option<Self>> {
+        alert_rules::table
+            .find(&alert_rule_id.0)
+            .first(conn)
+            .optional()
+    }
+
+    pub fn alert_rules_of_kit_id(conn: &PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        AlertRule::belonging_to(&kit_id)
+            .order(alert_rules::dsl::id.asc())
+            .load(conn)
+    }
+
+    /// Get all enabled alert rules, with the serials of the kits they belong to.
+    pub fn enabled_with_kit_serials(conn: &PgConnection) -> QueryResult<Vec<(Self, String)>> {
+        alert_rules::table
+            .inner_join(kits::table)
+            .filter(alert_rules::dsl::enabled.eq(true))
+            .select((alert_rules::all_columns, kits::dsl::serial))
+            .load(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    /// The condition of this rule. Conditions are constrained by the database, so an unknown
+    /// condition is a bug.
+    pub fn condition(&self) -> AlertCondition {
+        self.condition
+            .parse()
+            .expect("alert rule condition is constrained by the database")
+    }
+
+    pub fn get_id(&self) -> AlertRuleId {
+        AlertRuleId(self.id)
+    }
+
+    pub fn get_kit_id(&self) -> KitId {
+        KitId(self.kit_id)
+    }
+}
+
+impl UpdateAlertRule {
+    /// The alert rule as it will be after this update.
+    pub fn apply(&self, alert_rule: &AlertRule) -> NewAlertRule {
+        NewAlertRule {
+            kit_id: alert_rule.kit_id,
+            peripheral_id: alert_rule.peripheral_id,
+            quantity_type_id: alert_rule.quantity_type_id,
+            condition: self
+                .condition
+                .clone()
+                .unwrap_or_else(|| alert_rule.condition.clone()),
+            threshold: self.threshold.unwrap_or(alert_rule.threshold),
+            duration_seconds: self.duration_seconds.unwrap_or(alert_rule.duration_seconds),
+            description: self
+                .description
+                .clone()
+                .unwrap_or_else(|| alert_rule.description.clone()),
+            webhook_url: self
+                .webhook_url
+                .clone()
+                .unwrap_or_else(|| alert_rule.webhook_url.clone()),
+            enabled: self.enabled.unwrap_or(alert_rule.enabled),
+        }
+    }
+
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<AlertRule> {
+        self.save_changes(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable, Validate)]
+#[table_name = "alert_rules"]
+pub struct NewAlertRule {
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub condition: String,
+    pub threshold: f64,
+    #[validate(range(min = 0, max = 604_800))]
+    pub duration_seconds: i32,
+    #[validate(length(min = 1, max = 255))]
+    pub description: Option<String>,
+    #[validate(url)]
+    pub webhook_url: Option<String>,
+    pub enabled: bool,
+}
+
+impl NewAlertRule {
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<AlertRule> {
+        diesel::insert_into(alert_rules::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "AlertRule", foreign_key = "alert_rule_id")]
+#[belongs_to(parent = "AlertRuleId", foreign_key = "alert_rule_id")]
+#[belongs_to(parent = "Kit", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
+#[table_name = "alerts"]
+pub struct Alert {
+    pub id: i32,
+    pub alert_rule_id: i32,
+    pub kit_id: i32,
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_fired: DateTime<Utc>,
+    pub datetime_resolved: Option<DateTime<Utc>>,
+    pub value: f64,
+}
+
+impl Alert {
+    pub fn page(
+        conn: &PgConnection,
+        kit_id: KitId,
+        alert_rule_id: Option<i32>,
+        cursor: Option<cursors::Alerts>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = alerts::table
+            .filter(alerts::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(alert_rule_id) = alert_rule_id {
+            query = query.filter(alerts::columns::alert_rule_id.eq(alert_rule_id));
+        }
+
+        if let Some(cursors::Alerts(datetime, id)) = cursor {
+            query = query.filter(
+                alerts::columns::datetime_fired
+                    .lt(datetime)
+                    .or(alerts::columns::datetime_fired
+                        .eq(datetime)
+                        .and(alerts::columns::id.lt(id))),
+            );
+        }
+
+        query
+            .order((alerts::dsl::datetime_fired.desc(), alerts::dsl::id.desc()))
+            .limit(cursors::Alerts::PER_PAGE as i64)
+            .load(conn)
+    }
+
+    /// Get all alerts that have fired but have not been resolved yet.
+    pub fn unresolved(conn: &PgConnection) -> QueryResult<Vec<Self>> {
+        alerts::table
+            .filter(alerts::dsl::datetime_resolved.is_null())
+            .load(conn)
+    }
+
+    /// Mark this alert as resolved.
+    pub fn resolve(&self, conn: &PgConnection, datetime: DateTime<Utc>) -> QueryResult<Self> {
+        diesel::update(self)
+            .set(alerts::dsl::datetime_resolved.eq(datetime))
+            .get_result(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable)]
+#[table_name = "alerts"]
+pub struct NewAlert {
+    pub alert_rule_id: i32,
+    pub kit_id: i32,
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_fired: DateTime<Utc>,
+    pub value: f64,
+}
+
+impl NewAlert {
+    pub fn new(
+        alert_rule_id: AlertRuleId,
+        kit_id: KitId,
+        datetime_start: DateTime<Utc>,
+        datetime_fired: DateTime<Utc>,
+        value: f64,
+    ) -> Self {
+        Self {
+            alert_rule_id: alert_rule_id.0,
+            kit_id: kit_id.0,
+            datetime_start,
+            datetime_fired,
+            value,
+        }
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<Alert> {
+        diesel::insert_into(alerts::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
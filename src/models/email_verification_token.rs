@@ -0,0 +1,155 @@
+use crate::schema::email_verification_tokens;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+use super::{User, UserId};
+
+/// The length of generated email verification tokens. Roughly 190 bits of entropy.
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "email_verification_tokens"]
+pub struct EmailVerificationTokenId(#[column_name = "id"] pub i32);
+
+/// A token verifying a user's email address. Only the hash of the token is stored.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[table_name = "email_verification_tokens"]
+pub struct EmailVerificationToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub email_address: String,
+    pub token_hash: String,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl EmailVerificationToken {
+    /// Get an email verification token by the token itself. The token is locked until the end of
+    /// the transaction, such that it cannot be used concurrently.
+    pub fn by_token_for_update(conn: &PgConnection, token: &str) -> QueryResult<Option<Self>> {
+        email_verification_tokens::table
+            .filter(
+                email_verification_tokens::dsl::token_hash
+                    .eq(astroplant_auth::hash::hash_token(token)),
+            )
+            .for_update()
+            .first(conn)
+            .optional()
+    }
+
+    /// Get the most recently created email verification token of a user.
+    pub fn latest_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<Option<Self>> {
+        EmailVerificationToken::belonging_to(&user_id)
+            .order(email_verification_tokens::dsl::datetime_created.desc())
+            .first(conn)
+            .optional()
+    }
+
+    /// Whether this token verifies the user's email address at the given datetime. Tokens only
+    /// verify the email address they were sent to.
+    pub fn verifies(&self, user: &User, now: DateTime<Utc>) -> bool {
+        self.user_id == user.id
+            && self.email_address.to_lowercase() == user.email_address.to_lowercase()
+            && now < self.datetime_expires
+    }
+
+    /// Delete all email verification tokens of a user.
+    pub fn delete_all_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(EmailVerificationToken::belonging_to(&user_id)).execute(conn)
+    }
+
+    /// Delete the email verification tokens of a user that have expired.
+    pub fn delete_expired_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        diesel::delete(
+            EmailVerificationToken::belonging_to(&user_id)
+                .filter(email_verification_tokens::dsl::datetime_expires.le(now)),
+        )
+        .execute(conn)
+    }
+
+    pub fn get_id(&self) -> EmailVerificationTokenId {
+        EmailVerificationTokenId(self.id)
+    }
+
+    pub fn get_user_id(&self) -> UserId {
+        UserId(self.user_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "email_verification_tokens"]
+pub struct NewEmailVerificationToken {
+    pub user_id: i32,
+    pub email_address: String,
+    pub token_hash: String,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl NewEmailVerificationToken {
+    /// Creates a new email verification token for the user's current email address, with a
+    /// generated token. Returns the token alongside the email verification token to be stored,
+    /// as only the token's hash is stored.
+    pub fn new(user: &User, now: DateTime<Utc>, validity: chrono::Duration) -> (Self, String) {
+        let token = random_string::string(TOKEN_LENGTH);
+        let new_token = Self {
+            user_id: user.id,
+            email_address: user.email_address.clone(),
+            token_hash: astroplant_auth::hash::hash_token(&token),
+            datetime_created: now,
+            datetime_expires: now + validity,
+        };
+        (new_token, token)
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<EmailVerificationToken> {
+        diesel::insert_into(email_verification_tokens::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::EmailVerificationToken;
+    use crate::models::User;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn verifies_only_the_address_it_was_sent_to() {
+        let created = Utc.ymd(2020, 6, 22).and_hms(12, 0, 0);
+        let mut user = User {
+            id: 1,
+            username: "example".to_owned(),
+            display_name: "Example".to_owned(),
+            password_hash: "".to_owned(),
+            email_address: "Example@example.com".to_owned(),
+            use_email_address_for_gravatar: true,
+            gravatar_alternative: "".to_owned(),
+            email_verified: false,
+        };
+        let token = EmailVerificationToken {
+            id: 1,
+            user_id: 1,
+            email_address: "example@example.com".to_owned(),
+            token_hash: "".to_owned(),
+            datetime_created: created,
+            datetime_expires: created + Duration::days(1),
+        };
+
+        assert!(token.verifies(&user, created + Duration::hours(1)));
+        assert!(!token.verifies(&user, created + Duration::days(1)));
+
+        user.email_address = "other@example.com".to_owned();
+        assert!(!token.verifies(&user, created + Duration::hours(1)));
+    }
+}
@@ -1,6 +1,7 @@
 use crate::schema::kits;
 
 use bigdecimal::BigDecimal;
+use chrono::{DateTime, Utc};
 use diesel::pg::PgConnection;
 use diesel::prelude::*;
 use diesel::{Identifiable, QueryResult, Queryable};
@@ -22,6 +23,17 @@ pub struct Kit {
     pub longitude: Option<BigDecimal>,
     pub privacy_public_dashboard: bool,
     pub privacy_show_on_map: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+/// The result of recording that a kit was seen.
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct KitSeen {
+    #[sql_type = "diesel::sql_types::Integer"]
+    pub id: i32,
+    /// The time the kit was last seen before.
+    #[sql_type = "diesel::sql_types::Nullable<diesel::sql_types::Timestamptz>"]
+    pub previous_last_seen: Option<DateTime<Utc>>,
 }
 
 impl Kit {
@@ -36,6 +48,16 @@ impl Kit {
             .optional()
     }
 
+    pub fn by_ids(conn: &PgConnection, ids: &[i32]) -> QueryResult<Vec<Kit>> {
+        kits::table.filter(kits::columns::id.eq_any(ids)).load(conn)
+    }
+
+    pub fn by_serials(conn: &PgConnection, serials: &[String]) -> QueryResult<Vec<Kit>> {
+        kits::table
+            .filter(kits::columns::serial.eq_any(serials))
+            .load(conn)
+    }
+
     pub fn all(conn: &PgConnection) -> QueryResult<Vec<Kit>> {
         kits::table.load(conn)
     }
@@ -59,6 +81,73 @@ impl Kit {
     pub fn get_id(&self) -> KitId {
         KitId(self.id)
     }
+
+    /// Delete the kit, including its measurements, media, peripherals, configurations and
+    /// memberships. The content of the kit's media is not deleted from the object store: delete
+    /// the media through `Media::delete_all_of_kit` in the same transaction, and delete their
+    /// objects once the transaction is committed.
+    ///
+    /// Should be run in a transaction.
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        use crate::schema::{
+            aggregate_measurements, kit_configurations, kit_memberships, media, peripherals,
+            raw_measurements,
+        };
+
+        diesel::delete(raw_measurements::table.filter(raw_measurements::dsl::kit_id.eq(self.id)))
+            .execute(conn)?;
+        diesel::delete(
+            aggregate_measurements::table.filter(aggregate_measurements::dsl::kit_id.eq(self.id)),
+        )
+        .execute(conn)?;
+        diesel::delete(media::table.filter(media::dsl::kit_id.eq(self.id))).execute(conn)?;
+        diesel::delete(peripherals::table.filter(peripherals::dsl::kit_id.eq(self.id)))
+            .execute(conn)?;
+        diesel::delete(
+            kit_configurations::table.filter(kit_configurations::dsl::kit_id.eq(self.id)),
+        )
+        .execute(conn)?;
+        diesel::delete(kit_memberships::table.filter(kit_memberships::dsl::kit_id.eq(self.id)))
+            .execute(conn)?;
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    /// Record that the kit with the given serial was seen at the given time. Returns `None` if the
+    /// kit does not exist, or if it was already seen at or after that time.
+    pub fn record_seen(
+        conn: &PgConnection,
+        serial: &str,
+        datetime: DateTime<Utc>,
+    ) -> QueryResult<Option<KitSeen>> {
+        use diesel::sql_types::{Text, Timestamptz};
+
+        diesel::sql_query(
+            r#"
+            UPDATE kits
+            SET last_seen = $2
+            FROM (SELECT id, last_seen FROM kits WHERE serial = $1 FOR UPDATE) AS previous
+            WHERE kits.id = previous.id
+              AND (previous.last_seen IS NULL OR previous.last_seen < $2)
+            RETURNING kits.id, previous.last_seen AS previous_last_seen
+            "#,
+        )
+        .bind::<Text, _>(serial)
+        .bind::<Timestamptz, _>(datetime)
+        .get_result(conn)
+        .optional()
+    }
+
+    /// The kits last seen in the given time range (excluding `after`, including `until`).
+    pub fn last_seen_between(
+        conn: &PgConnection,
+        after: DateTime<Utc>,
+        until: DateTime<Utc>,
+    ) -> QueryResult<Vec<Kit>> {
+        kits::table
+            .filter(kits::columns::last_seen.gt(after))
+            .filter(kits::columns::last_seen.le(until))
+            .load(conn)
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Queryable, Identifiable, AsChangeset)]
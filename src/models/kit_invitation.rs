@@ -0,0 +1,153 @@
+use crate::schema::kit_invitations;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use validator::Validate;
+
+use super::{Kit, KitId, UserId};
+
+/// The length of generated invitation codes. Roughly 55 bits of entropy.
+const CODE_LENGTH: usize = 12;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "kit_invitations"]
+pub struct KitInvitationId(#[column_name = "id"] pub i32);
+
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "Kit", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
+#[table_name = "kit_invitations"]
+pub struct KitInvitation {
+    pub id: i32,
+    pub kit_id: i32,
+    pub code: String,
+    pub created_by: Option<i32>,
+    pub access_super: bool,
+    pub access_configure: bool,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl KitInvitation {
+    pub fn by_id(
+        conn: &PgConnection,
+        kit_invitation_id: KitInvitationId,
+    ) -> QueryResult<Option<Self>> {
+        kit_invitations::table
+            .find(&kit_invitation_id.0)
+            .first(conn)
+            .optional()
+    }
+
+    /// Get an invitation by its code. The invitation is locked until the end of the transaction,
+    /// such that concurrent redemptions cannot exceed its maximum uses.
+    pub fn by_code_for_update(conn: &PgConnection, code: &str) -> QueryResult<Option<Self>> {
+        kit_invitations::table
+            .filter(kit_invitations::dsl::code.eq(code))
+            .for_update()
+            .first(conn)
+            .optional()
+    }
+
+    pub fn invitations_of_kit_id(conn: &PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        KitInvitation::belonging_to(&kit_id)
+            .order(kit_invitations::dsl::id.asc())
+            .load(conn)
+    }
+
+    /// Whether this invitation can be redeemed at the given datetime.
+    pub fn redeemable(&self, now: DateTime<Utc>) -> bool {
+        self.uses < self.max_uses && now < self.datetime_expires
+    }
+
+    /// Record that this invitation was redeemed.
+    pub fn redeem(&self, conn: &PgConnection) -> QueryResult<Self> {
+        use kit_invitations::dsl;
+
+        diesel::update(self)
+            .set(dsl::uses.eq(dsl::uses + 1))
+            .get_result(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    pub fn get_kit_id(&self) -> KitId {
+        KitId(self.kit_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable, Validate)]
+#[table_name = "kit_invitations"]
+pub struct NewKitInvitation {
+    pub kit_id: i32,
+    pub code: String,
+    pub created_by: Option<i32>,
+    pub access_super: bool,
+    pub access_configure: bool,
+    #[validate(range(min = 1, max = 1000))]
+    pub max_uses: i32,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl NewKitInvitation {
+    /// Creates a new invitation with a generated code.
+    pub fn new(
+        kit_id: KitId,
+        created_by: UserId,
+        access_super: bool,
+        access_configure: bool,
+        max_uses: i32,
+        datetime_expires: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kit_id: kit_id.0,
+            code: random_string::unambiguous_lowercase_string(CODE_LENGTH),
+            created_by: Some(created_by.0),
+            access_super,
+            access_configure,
+            max_uses,
+            datetime_expires,
+        }
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<KitInvitation> {
+        diesel::insert_into(kit_invitations::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::KitInvitation;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn redeemable_until_used_up_or_expired() {
+        let created = Utc.ymd(2020, 6, 16).and_hms(12, 0, 0);
+        let mut invitation = KitInvitation {
+            id: 1,
+            kit_id: 1,
+            code: "bcd3fgh4jkm6".to_owned(),
+            created_by: Some(1),
+            access_super: false,
+            access_configure: true,
+            max_uses: 2,
+            uses: 1,
+            datetime_created: created,
+            datetime_expires: created + Duration::days(7),
+        };
+
+        assert!(invitation.redeemable(created + Duration::days(1)));
+        assert!(!invitation.redeemable(created + Duration::days(7)));
+
+        invitation.uses = 2;
+        assert!(!invitation.redeemable(created + Duration::days(1)));
+    }
+}
@@ -1,4 +1,4 @@
-use crate::schema::{kit_memberships, kits};
+use crate::schema::{kit_memberships, kits, users};
 
 use chrono::{DateTime, Utc};
 use diesel::pg::PgConnection;
@@ -12,6 +12,7 @@ use super::{User, UserId};
 #[belongs_to(parent = "User")]
 #[belongs_to(parent = "UserId", foreign_key = "user_id")]
 #[belongs_to(parent = "Kit")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
 #[table_name = "kit_memberships"]
 pub struct KitMembership {
     pub id: i32,
@@ -43,6 +44,33 @@ impl KitMembership {
             .get_results(conn)
     }
 
+    pub fn memberships_with_user_of_kit_id(
+        conn: &PgConnection,
+        kit_id: KitId,
+    ) -> QueryResult<Vec<(User, Self)>> {
+        users::table
+            .inner_join(kit_memberships::table)
+            .filter(kit_memberships::dsl::kit_id.eq(kit_id.0))
+            .order(kit_memberships::dsl::id.asc())
+            .get_results(conn)
+    }
+
+    /// Get the super memberships of a kit. The memberships are locked until the end of the
+    /// transaction, such that concurrent changes cannot leave the kit without super members.
+    pub fn super_memberships_of_kit_id_for_update(
+        conn: &PgConnection,
+        kit_id: KitId,
+    ) -> QueryResult<Vec<Self>> {
+        KitMembership::belonging_to(&kit_id)
+            .filter(kit_memberships::dsl::access_super.eq(true))
+            .for_update()
+            .load(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
     pub fn memberships_of_user(conn: &PgConnection, user: &User) -> QueryResult<Vec<Self>> {
         KitMembership::belonging_to(user).load(conn)
     }
@@ -68,6 +96,21 @@ impl KitMembership {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq, Identifiable, AsChangeset)]
+#[table_name = "kit_memberships"]
+pub struct UpdateKitMembership {
+    pub id: i32,
+    // None means don't update.
+    pub access_super: Option<bool>,
+    pub access_configure: Option<bool>,
+}
+
+impl UpdateKitMembership {
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<KitMembership> {
+        self.save_changes(conn)
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, Insertable)]
 #[table_name = "kit_memberships"]
 pub struct NewKitMembership {
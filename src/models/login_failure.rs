@@ -0,0 +1,133 @@
+use crate::schema::login_failures;
+
+use chrono::{DateTime, Duration, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+/// The number of attempts that can be made before attempts are delayed.
+const FREE_ATTEMPTS: i32 = 3;
+
+/// The number of attempts after which further attempts are locked out.
+const LOCKOUT_ATTEMPTS: i32 = 10;
+
+/// The maximum delay between attempts before being locked out.
+const MAX_DELAY_SECONDS: i64 = 60;
+
+/// The time attempts are locked out for.
+const LOCKOUT_MINUTES: i64 = 15;
+
+/// The time after the last attempt after which failures are forgotten.
+const FORGET_AFTER_HOURS: i64 = 24;
+
+/// The failed login attempts of a username. Tracked per username, rather than per user, such that
+/// the throttling of logins does not disclose which usernames exist.
+///
+/// Attempts are recorded as failures before the password is checked, such that concurrent
+/// attempts are throttled as well. The failures are cleared when logging in succeeds.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable)]
+#[primary_key(username)]
+#[table_name = "login_failures"]
+pub struct LoginFailure {
+    pub username: String,
+    pub failures: i32,
+    pub datetime_last_attempt: DateTime<Utc>,
+    pub datetime_blocked_until: Option<DateTime<Utc>>,
+}
+
+impl LoginFailure {
+    /// The time further attempts are blocked for after the given number of failures.
+    fn blocked_for(failures: i32) -> Option<Duration> {
+        if failures >= LOCKOUT_ATTEMPTS {
+            Some(Duration::minutes(LOCKOUT_MINUTES))
+        } else if failures >= FREE_ATTEMPTS {
+            let delay = 1i64 << (failures - FREE_ATTEMPTS).min(32);
+            Some(Duration::seconds(delay.min(MAX_DELAY_SECONDS)))
+        } else {
+            None
+        }
+    }
+
+    /// Record an attempt to log in as a username. Returns the time to wait if attempts for the
+    /// username are currently blocked, in which case the attempt is not recorded.
+    pub fn attempt(
+        conn: &PgConnection,
+        username: &str,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Option<Duration>> {
+        use login_failures::dsl;
+
+        let username = username.to_lowercase();
+        let forget_before = now - Duration::hours(FORGET_AFTER_HOURS);
+
+        conn.transaction(|| {
+            diesel::delete(
+                login_failures::table.filter(dsl::datetime_last_attempt.lt(forget_before)),
+            )
+            .execute(conn)?;
+            diesel::insert_into(login_failures::table)
+                .values((
+                    dsl::username.eq(&username),
+                    dsl::failures.eq(0),
+                    dsl::datetime_last_attempt.eq(now),
+                ))
+                .on_conflict_do_nothing()
+                .execute(conn)?;
+
+            let login_failure: LoginFailure = login_failures::table
+                .find(&username)
+                .for_update()
+                .first(conn)?;
+
+            if let Some(blocked_until) = login_failure.datetime_blocked_until {
+                if blocked_until > now {
+                    return Ok(Some(blocked_until - now));
+                }
+            }
+
+            let failures = login_failure.failures + 1;
+            diesel::update(&login_failure)
+                .set((
+                    dsl::failures.eq(failures),
+                    dsl::datetime_last_attempt.eq(now),
+                    dsl::datetime_blocked_until.eq(Self::blocked_for(failures).map(|d| now + d)),
+                ))
+                .execute(conn)?;
+
+            Ok(None)
+        })
+    }
+
+    /// Clear the failures of a username, e.g. after logging in succeeded.
+    pub fn clear(conn: &PgConnection, username: &str) -> QueryResult<usize> {
+        diesel::delete(login_failures::table.find(username.to_lowercase())).execute(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{LoginFailure, FREE_ATTEMPTS, LOCKOUT_ATTEMPTS, LOCKOUT_MINUTES};
+    use chrono::Duration;
+
+    #[test]
+    fn delays_increase_until_lockout() {
+        for failures in 0..FREE_ATTEMPTS {
+            assert_eq!(LoginFailure::blocked_for(failures), None);
+        }
+
+        let mut previous = Duration::zero();
+        for failures in FREE_ATTEMPTS..LOCKOUT_ATTEMPTS {
+            let delay = LoginFailure::blocked_for(failures).unwrap();
+            assert!(delay >= previous);
+            assert!(delay < Duration::minutes(LOCKOUT_MINUTES));
+            previous = delay;
+        }
+
+        for failures in LOCKOUT_ATTEMPTS..LOCKOUT_ATTEMPTS + 100 {
+            assert_eq!(
+                LoginFailure::blocked_for(failures),
+                Some(Duration::minutes(LOCKOUT_MINUTES))
+            );
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use crate::cursors;
-use crate::schema::aggregate_measurements;
+use crate::schema::{aggregate_measurements, raw_measurements};
 
 use chrono::{DateTime, Utc};
 use diesel::pg::PgConnection;
@@ -57,7 +57,36 @@ impl AggregateMeasurement {
         configuration_id: Option<i32>,
         peripheral_id: Option<i32>,
         quantity_type_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
         cursor: Option<cursors::AggregateMeasurements>,
+    ) -> QueryResult<Vec<Self>> {
+        Self::page_of_size(
+            conn,
+            kit_id,
+            configuration_id,
+            peripheral_id,
+            quantity_type_id,
+            from,
+            to,
+            order,
+            cursor,
+            cursors::AggregateMeasurements::PER_PAGE,
+        )
+    }
+
+    pub fn page_of_size(
+        conn: &PgConnection,
+        kit_id: KitId,
+        configuration_id: Option<i32>,
+        peripheral_id: Option<i32>,
+        quantity_type_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
+        cursor: Option<cursors::AggregateMeasurements>,
+        per_page: usize,
     ) -> QueryResult<Vec<Self>> {
         let mut query = aggregate_measurements::table
             .filter(aggregate_measurements::columns::kit_id.eq(kit_id.0))
@@ -75,25 +104,574 @@ impl AggregateMeasurement {
                 .filter(aggregate_measurements::columns::quantity_type_id.eq(quantity_type_id));
         }
 
+        if let Some(from) = from {
+            query = query.filter(aggregate_measurements::columns::datetime_start.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(aggregate_measurements::columns::datetime_start.lt(to));
+        }
+
         if let Some(cursors::AggregateMeasurements(datetime, id)) = cursor {
-            query = query.filter(
-                aggregate_measurements::columns::datetime_start
-                    .lt(datetime)
-                    .or(aggregate_measurements::columns::datetime_start
-                        .eq(datetime)
-                        .and(aggregate_measurements::columns::id.lt(id))),
-            )
+            query = match order {
+                cursors::Order::Asc => query.filter(
+                    aggregate_measurements::columns::datetime_start
+                        .gt(datetime)
+                        .or(aggregate_measurements::columns::datetime_start
+                            .eq(datetime)
+                            .and(aggregate_measurements::columns::id.gt(id))),
+                ),
+                cursors::Order::Desc => query.filter(
+                    aggregate_measurements::columns::datetime_start
+                        .lt(datetime)
+                        .or(aggregate_measurements::columns::datetime_start
+                            .eq(datetime)
+                            .and(aggregate_measurements::columns::id.lt(id))),
+                ),
+            };
         }
-        query
-            .order((
+        query = match order {
+            cursors::Order::Asc => query.order((
+                aggregate_measurements::dsl::datetime_start.asc(),
+                aggregate_measurements::dsl::id.asc(),
+            )),
+            cursors::Order::Desc => query.order((
                 aggregate_measurements::dsl::datetime_start.desc(),
                 aggregate_measurements::dsl::id.desc(),
-            ))
-            .limit(cursors::AggregateMeasurements::PER_PAGE as i64)
-            .load(conn)
+            )),
+        };
+        query.limit(per_page as i64).load(conn)
+    }
+
+    /**
+     * Compute statistics of the aggregate measurements of a peripheral and quantity type made in
+     * the given time range, grouped into buckets of `bucket_seconds`. Buckets are aligned to the
+     * Unix epoch, and buckets without measurements are omitted.
+     *
+     * The minimum and maximum are taken from the `minimum` and `maximum` aggregates, the mean is
+     * the `average` aggregate weighted by the `count` aggregate. Missing aggregates fall back to
+     * the `average` aggregate and a count of one, respectively.
+     */
+    pub fn statistics(
+        conn: &PgConnection,
+        kit_id: KitId,
+        peripheral_id: PeripheralId,
+        quantity_type_id: QuantityTypeId,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> QueryResult<Vec<MeasurementStatistics>> {
+        use diesel::sql_types::{BigInt, Integer, Timestamptz};
+
+        diesel::sql_query(
+            r#"
+            SELECT bucket AS datetime_start,
+                   min(minimum) AS minimum,
+                   max(maximum) AS maximum,
+                   sum(average * count) / sum(count) AS mean,
+                   sum(count)::bigint AS count
+            FROM (
+                SELECT to_timestamp(
+                           floor(date_part('epoch', datetime_start) / $6) * $6
+                       ) AS bucket,
+                       coalesce(
+                           ("values"->>'minimum')::float8,
+                           ("values"->>'average')::float8
+                       ) AS minimum,
+                       coalesce(
+                           ("values"->>'maximum')::float8,
+                           ("values"->>'average')::float8
+                       ) AS maximum,
+                       ("values"->>'average')::float8 AS average,
+                       coalesce(("values"->>'count')::float8, 1) AS count
+                FROM aggregate_measurements
+                WHERE kit_id = $1
+                  AND peripheral_id = $2
+                  AND quantity_type_id = $3
+                  AND datetime_start >= $4
+                  AND datetime_start < $5
+                  AND "values"->>'average' IS NOT NULL
+            ) AS buckets
+            WHERE count > 0
+            GROUP BY bucket
+            ORDER BY bucket
+            "#,
+        )
+        .bind::<Integer, _>(kit_id.0)
+        .bind::<Integer, _>(peripheral_id.0)
+        .bind::<Integer, _>(quantity_type_id.0)
+        .bind::<Timestamptz, _>(from)
+        .bind::<Timestamptz, _>(to)
+        .bind::<BigInt, _>(bucket_seconds)
+        .load(conn)
     }
 
     pub fn get_id(&self) -> AggregateMeasurementId {
         AggregateMeasurementId(self.id)
     }
 }
+
+#[derive(Clone, Debug, PartialEq, Insertable)]
+#[table_name = "aggregate_measurements"]
+pub struct NewAggregateMeasurement {
+    pub id: Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub quantity_type_id: i32,
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_end: DateTime<Utc>,
+    pub values: serde_json::Value,
+}
+
+impl NewAggregateMeasurement {
+    pub fn new(
+        id: Uuid,
+        peripheral_id: PeripheralId,
+        kit_id: KitId,
+        kit_configuration_id: KitConfigurationId,
+        quantity_type_id: QuantityTypeId,
+        datetime_start: DateTime<Utc>,
+        datetime_end: DateTime<Utc>,
+        values: serde_json::Value,
+    ) -> Self {
+        Self {
+            id,
+            peripheral_id: peripheral_id.0,
+            kit_id: kit_id.0,
+            kit_configuration_id: kit_configuration_id.0,
+            quantity_type_id: quantity_type_id.0,
+            datetime_start,
+            datetime_end,
+            values,
+        }
+    }
+
+    /// Insert the aggregate measurement. Returns `None` if an aggregate measurement with the same
+    /// id already exists.
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<Option<AggregateMeasurement>> {
+        use crate::schema::aggregate_measurements::dsl::*;
+
+        diesel::insert_into(aggregate_measurements)
+            .values(self)
+            .on_conflict_do_nothing()
+            .get_result::<AggregateMeasurement>(conn)
+            .optional()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "raw_measurements"]
+pub struct RawMeasurementId(#[column_name = "id"] pub Uuid);
+
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "Kit", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitConfiguration", foreign_key = "kit_configuration_id")]
+#[belongs_to(parent = "KitConfigurationId", foreign_key = "kit_configuration_id")]
+#[belongs_to(parent = "Peripheral", foreign_key = "peripheral_id")]
+#[belongs_to(parent = "PeripheralId", foreign_key = "peripheral_id")]
+#[belongs_to(parent = "QuantityType", foreign_key = "quantity_type_id")]
+#[belongs_to(parent = "QuantityTypeId", foreign_key = "quantity_type_id")]
+#[table_name = "raw_measurements"]
+pub struct RawMeasurement {
+    pub id: Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub quantity_type_id: i32,
+    pub value: f64,
+    pub datetime: DateTime<Utc>,
+}
+
+impl RawMeasurement {
+    pub fn by_id(
+        conn: &PgConnection,
+        raw_measurement_id: RawMeasurementId,
+    ) -> QueryResult<Option<Self>> {
+        raw_measurements::table
+            .find(&raw_measurement_id.0)
+            .first(conn)
+            .optional()
+    }
+
+    pub fn page(
+        conn: &PgConnection,
+        kit_id: KitId,
+        configuration_id: Option<i32>,
+        peripheral_id: Option<i32>,
+        quantity_type_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
+        cursor: Option<cursors::RawMeasurements>,
+    ) -> QueryResult<Vec<Self>> {
+        Self::page_of_size(
+            conn,
+            kit_id,
+            configuration_id,
+            peripheral_id,
+            quantity_type_id,
+            from,
+            to,
+            order,
+            cursor,
+            cursors::RawMeasurements::PER_PAGE,
+        )
+    }
+
+    pub fn page_of_size(
+        conn: &PgConnection,
+        kit_id: KitId,
+        configuration_id: Option<i32>,
+        peripheral_id: Option<i32>,
+        quantity_type_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
+        cursor: Option<cursors::RawMeasurements>,
+        per_page: usize,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = raw_measurements::table
+            .filter(raw_measurements::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(configuration_id) = configuration_id {
+            query =
+                query.filter(raw_measurements::columns::kit_configuration_id.eq(configuration_id));
+        }
+        if let Some(peripheral_id) = peripheral_id {
+            query = query.filter(raw_measurements::columns::peripheral_id.eq(peripheral_id));
+        }
+        if let Some(quantity_type_id) = quantity_type_id {
+            query = query.filter(raw_measurements::columns::quantity_type_id.eq(quantity_type_id));
+        }
+        if let Some(from) = from {
+            query = query.filter(raw_measurements::columns::datetime.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(raw_measurements::columns::datetime.lt(to));
+        }
+
+        if let Some(cursors::RawMeasurements(datetime, id)) = cursor {
+            query = match order {
+                cursors::Order::Asc => query.filter(
+                    raw_measurements::columns::datetime.gt(datetime).or(
+                        raw_measurements::columns::datetime
+                            .eq(datetime)
+                            .and(raw_measurements::columns::id.gt(id)),
+                    ),
+                ),
+                cursors::Order::Desc => query.filter(
+                    raw_measurements::columns::datetime.lt(datetime).or(
+                        raw_measurements::columns::datetime
+                            .eq(datetime)
+                            .and(raw_measurements::columns::id.lt(id)),
+                    ),
+                ),
+            };
+        }
+        query = match order {
+            cursors::Order::Asc => query.order((
+                raw_measurements::dsl::datetime.asc(),
+                raw_measurements::dsl::id.asc(),
+            )),
+            cursors::Order::Desc => query.order((
+                raw_measurements::dsl::datetime.desc(),
+                raw_measurements::dsl::id.desc(),
+            )),
+        };
+        query.limit(per_page as i64).load(conn)
+    }
+
+    /**
+     * Delete at most `limit` raw measurements made before the given datetime.
+     * Returns the amount of deleted raw measurements.
+     */
+    pub fn delete_before(
+        conn: &PgConnection,
+        datetime: DateTime<Utc>,
+        limit: i64,
+    ) -> QueryResult<usize> {
+        use raw_measurements::dsl;
+
+        let ids = raw_measurements::table
+            .select(dsl::id)
+            .filter(dsl::datetime.lt(datetime))
+            .limit(limit);
+        diesel::delete(raw_measurements::table.filter(dsl::id.eq_any(ids))).execute(conn)
+    }
+
+    /**
+     * Compute statistics of the raw measurements of a peripheral and quantity type made in the
+     * given time range, grouped into buckets of `bucket_seconds`. Buckets are aligned to the Unix
+     * epoch, and buckets without measurements are omitted.
+     */
+    pub fn statistics(
+        conn: &PgConnection,
+        kit_id: KitId,
+        peripheral_id: PeripheralId,
+        quantity_type_id: QuantityTypeId,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        bucket_seconds: i64,
+    ) -> QueryResult<Vec<MeasurementStatistics>> {
+        use diesel::sql_types::{BigInt, Integer, Timestamptz};
+
+        diesel::sql_query(
+            r#"
+            SELECT to_timestamp(
+                       floor(date_part('epoch', datetime) / $6) * $6
+                   ) AS datetime_start,
+                   min(value) AS minimum,
+                   max(value) AS maximum,
+                   avg(value) AS mean,
+                   count(*) AS count
+            FROM raw_measurements
+            WHERE kit_id = $1
+              AND peripheral_id = $2
+              AND quantity_type_id = $3
+              AND datetime >= $4
+              AND datetime < $5
+            GROUP BY 1
+            ORDER BY 1
+            "#,
+        )
+        .bind::<Integer, _>(kit_id.0)
+        .bind::<Integer, _>(peripheral_id.0)
+        .bind::<Integer, _>(quantity_type_id.0)
+        .bind::<Timestamptz, _>(from)
+        .bind::<Timestamptz, _>(to)
+        .bind::<BigInt, _>(bucket_seconds)
+        .load(conn)
+    }
+
+    pub fn get_id(&self) -> RawMeasurementId {
+        RawMeasurementId(self.id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable)]
+#[table_name = "raw_measurements"]
+pub struct NewRawMeasurement {
+    pub id: Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub quantity_type_id: i32,
+    pub value: f64,
+    pub datetime: DateTime<Utc>,
+}
+
+impl NewRawMeasurement {
+    pub fn new(
+        id: Uuid,
+        peripheral_id: PeripheralId,
+        kit_id: KitId,
+        kit_configuration_id: KitConfigurationId,
+        quantity_type_id: QuantityTypeId,
+        value: f64,
+        datetime: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            peripheral_id: peripheral_id.0,
+            kit_id: kit_id.0,
+            kit_configuration_id: kit_configuration_id.0,
+            quantity_type_id: quantity_type_id.0,
+            value,
+            datetime,
+        }
+    }
+
+    /**
+     * Insert multiple raw measurements in a single query. Raw measurements with ids that already
+     * exist are skipped.
+     * Returns the amount of inserted raw measurements.
+     */
+    pub fn create_batch(conn: &PgConnection, batch: &[Self]) -> QueryResult<usize> {
+        use crate::schema::raw_measurements::dsl::*;
+
+        if batch.is_empty() {
+            return Ok(0);
+        }
+
+        diesel::insert_into(raw_measurements)
+            .values(batch)
+            .on_conflict_do_nothing()
+            .execute(conn)
+    }
+}
+
+/// Statistics of the measurements made in a time bucket starting at `datetime_start`.
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct MeasurementStatistics {
+    #[sql_type = "diesel::sql_types::Timestamptz"]
+    pub datetime_start: DateTime<Utc>,
+    #[sql_type = "diesel::sql_types::Double"]
+    pub minimum: f64,
+    #[sql_type = "diesel::sql_types::Double"]
+    pub maximum: f64,
+    #[sql_type = "diesel::sql_types::Double"]
+    pub mean: f64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+}
+
+/// Tests that need a database connect to `DATABASE_URL` and are ignored by default; run them with
+/// `cargo test -- --ignored`. Every test runs in its own transaction, which is never committed.
+#[cfg(test)]
+mod test {
+    use super::{AggregateMeasurement, NewAggregateMeasurement, NewRawMeasurement, RawMeasurement};
+    use crate::cursors;
+    use crate::test_support::{connection, create_fixture};
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use uuid::Uuid;
+
+    /// The page size used while paging, small enough for ties to straddle page boundaries.
+    const PER_PAGE: usize = 2;
+
+    /// Ids and datetimes of measurements, with several measurements sharing a datetime. The ids
+    /// are not in datetime order, such that ties must be broken by id.
+    fn ids_and_datetimes() -> Vec<(Uuid, DateTime<Utc>)> {
+        let base = Uuid::new_v4().as_u128() & !0xff;
+        let start = Utc.ymd(2020, 6, 1).and_hms(12, 0, 0);
+        vec![(5, 0), (3, 0), (6, 0), (0, 1), (4, 1), (1, 2), (2, 2)]
+            .into_iter()
+            .map(|(id, minutes)| {
+                (
+                    Uuid::from_u128(base + id),
+                    start + Duration::minutes(minutes),
+                )
+            })
+            .collect()
+    }
+
+    /// Page through a collection by repeatedly fetching the page after the last item of the
+    /// previous page, until a partial page is fetched.
+    fn page_through<T>(
+        mut page: impl FnMut(Option<(DateTime<Utc>, Uuid)>) -> Vec<T>,
+        key: impl Fn(&T) -> (DateTime<Utc>, Uuid),
+    ) -> Vec<(DateTime<Utc>, Uuid)> {
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let items: Vec<(DateTime<Utc>, Uuid)> = page(cursor).iter().map(&key).collect();
+            assert!(items.len() <= PER_PAGE);
+            seen.extend(items.iter().cloned());
+            if items.len() < PER_PAGE {
+                return seen;
+            }
+            cursor = items.last().cloned();
+        }
+    }
+
+    /// The expected listing of all items in the given order.
+    fn expected(
+        items: &[(Uuid, DateTime<Utc>)],
+        order: cursors::Order,
+    ) -> Vec<(DateTime<Utc>, Uuid)> {
+        let mut items: Vec<(DateTime<Utc>, Uuid)> =
+            items.iter().map(|&(id, datetime)| (datetime, id)).collect();
+        items.sort();
+        if order == cursors::Order::Desc {
+            items.reverse();
+        }
+        items
+    }
+
+    #[test]
+    #[ignore]
+    fn page_through_aggregate_measurements() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let ids_and_datetimes = ids_and_datetimes();
+        for &(id, datetime) in &ids_and_datetimes {
+            NewAggregateMeasurement::new(
+                id,
+                fixture.peripheral.get_id(),
+                fixture.kit.get_id(),
+                fixture.configuration.get_id(),
+                fixture.quantity_type_id,
+                datetime,
+                datetime + Duration::minutes(1),
+                serde_json::json!({ "average": 21.5 }),
+            )
+            .create(&conn)
+            .unwrap()
+            .unwrap();
+        }
+
+        for &order in &[cursors::Order::Asc, cursors::Order::Desc] {
+            let seen = page_through(
+                |cursor| {
+                    AggregateMeasurement::page_of_size(
+                        &conn,
+                        fixture.kit.get_id(),
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        order,
+                        cursor.map(|(datetime, id)| cursors::AggregateMeasurements(datetime, id)),
+                        PER_PAGE,
+                    )
+                    .unwrap()
+                },
+                |measurement| (measurement.datetime_start, measurement.id),
+            );
+            assert_eq!(seen, expected(&ids_and_datetimes, order));
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn page_through_raw_measurements() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let ids_and_datetimes = ids_and_datetimes();
+        let batch: Vec<NewRawMeasurement> = ids_and_datetimes
+            .iter()
+            .map(|&(id, datetime)| {
+                NewRawMeasurement::new(
+                    id,
+                    fixture.peripheral.get_id(),
+                    fixture.kit.get_id(),
+                    fixture.configuration.get_id(),
+                    fixture.quantity_type_id,
+                    21.5,
+                    datetime,
+                )
+            })
+            .collect();
+        assert_eq!(
+            NewRawMeasurement::create_batch(&conn, &batch).unwrap(),
+            batch.len()
+        );
+
+        for &order in &[cursors::Order::Asc, cursors::Order::Desc] {
+            let seen = page_through(
+                |cursor| {
+                    RawMeasurement::page_of_size(
+                        &conn,
+                        fixture.kit.get_id(),
+                        None,
+                        None,
+                        None,
+                        None,
+                        None,
+                        order,
+                        cursor.map(|(datetime, id)| cursors::RawMeasurements(datetime, id)),
+                        PER_PAGE,
+                    )
+                    .unwrap()
+                },
+                |measurement| (measurement.datetime, measurement.id),
+            );
+            assert_eq!(seen, expected(&ids_and_datetimes, order));
+        }
+    }
+}
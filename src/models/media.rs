@@ -49,7 +49,34 @@ impl Media {
         kit_id: KitId,
         configuration_id: Option<i32>,
         peripheral_id: Option<i32>,
-        cursor: Option<cursors::AggregateMeasurements>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
+        cursor: Option<cursors::Media>,
+    ) -> QueryResult<Vec<Self>> {
+        Self::page_of_size(
+            conn,
+            kit_id,
+            configuration_id,
+            peripheral_id,
+            from,
+            to,
+            order,
+            cursor,
+            cursors::Media::PER_PAGE,
+        )
+    }
+
+    pub fn page_of_size(
+        conn: &PgConnection,
+        kit_id: KitId,
+        configuration_id: Option<i32>,
+        peripheral_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+        order: cursors::Order,
+        cursor: Option<cursors::Media>,
+        per_page: usize,
     ) -> QueryResult<Vec<Self>> {
         let mut query = media::table
             .filter(media::columns::kit_id.eq(kit_id.0))
@@ -62,18 +89,101 @@ impl Media {
             query = query.filter(media::columns::peripheral_id.eq(peripheral_id));
         }
 
-        if let Some(cursors::AggregateMeasurements(datetime, id)) = cursor {
-            query = query.filter(
-                media::columns::datetime
-                    .lt(datetime)
-                    .or(media::columns::datetime
-                        .eq(datetime)
-                        .and(media::columns::id.lt(id))),
-            )
+        if let Some(from) = from {
+            query = query.filter(media::columns::datetime.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(media::columns::datetime.lt(to));
+        }
+
+        if let Some(cursors::Media(datetime, id)) = cursor {
+            query = match order {
+                cursors::Order::Asc => query.filter(
+                    media::columns::datetime
+                        .gt(datetime)
+                        .or(media::columns::datetime
+                            .eq(datetime)
+                            .and(media::columns::id.gt(id))),
+                ),
+                cursors::Order::Desc => query.filter(
+                    media::columns::datetime
+                        .lt(datetime)
+                        .or(media::columns::datetime
+                            .eq(datetime)
+                            .and(media::columns::id.lt(id))),
+                ),
+            };
         }
+        query = match order {
+            cursors::Order::Asc => query.order((media::dsl::datetime.asc(), media::dsl::id.asc())),
+            cursors::Order::Desc => {
+                query.order((media::dsl::datetime.desc(), media::dsl::id.desc()))
+            }
+        };
+        query.limit(per_page as i64).load(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    /// Delete all media of a kit, returning the deleted media.
+    pub fn delete_all_of_kit(conn: &PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        diesel::delete(media::table.filter(media::columns::kit_id.eq(kit_id.0))).get_results(conn)
+    }
+
+    /// Get the storage used by the media of a kit, per peripheral.
+    pub fn usage_of_kit(conn: &PgConnection, kit_id: KitId) -> QueryResult<Vec<MediaUsage>> {
+        use diesel::sql_types::Integer;
+
+        diesel::sql_query(
+            r#"
+            SELECT peripheral_id,
+                   coalesce(sum(size), 0)::bigint AS bytes,
+                   count(*) AS count
+            FROM media
+            WHERE kit_id = $1
+            GROUP BY peripheral_id
+            ORDER BY peripheral_id
+            "#,
+        )
+        .bind::<Integer, _>(kit_id.0)
+        .load(conn)
+    }
+
+    /// Get the oldest media of a kit.
+    pub fn oldest_of_kit(conn: &PgConnection, kit_id: KitId, limit: i64) -> QueryResult<Vec<Self>> {
+        media::table
+            .filter(media::columns::kit_id.eq(kit_id.0))
+            .order((media::dsl::datetime.asc(), media::dsl::id.asc()))
+            .limit(limit)
+            .load(conn)
+    }
+
+    /// Get all media of a kit made in the given time range, oldest first.
+    pub fn in_range(
+        conn: &PgConnection,
+        kit_id: KitId,
+        peripheral_id: Option<i32>,
+        from: Option<DateTime<Utc>>,
+        to: Option<DateTime<Utc>>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = media::table
+            .filter(media::columns::kit_id.eq(kit_id.0))
+            .into_boxed();
+
+        if let Some(peripheral_id) = peripheral_id {
+            query = query.filter(media::columns::peripheral_id.eq(peripheral_id));
+        }
+        if let Some(from) = from {
+            query = query.filter(media::columns::datetime.ge(from));
+        }
+        if let Some(to) = to {
+            query = query.filter(media::columns::datetime.lt(to));
+        }
+
         query
-            .order((media::dsl::datetime.desc(), media::dsl::id.desc()))
-            .limit(cursors::Media::PER_PAGE as i64)
+            .order((media::dsl::datetime.asc(), media::dsl::id.asc()))
             .load(conn)
     }
 
@@ -94,6 +204,17 @@ impl Media {
     }
 }
 
+/// The storage used by the media of a peripheral.
+#[derive(Clone, Debug, PartialEq, QueryableByName)]
+pub struct MediaUsage {
+    #[sql_type = "diesel::sql_types::Integer"]
+    pub peripheral_id: i32,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub bytes: i64,
+    #[sql_type = "diesel::sql_types::BigInt"]
+    pub count: i64,
+}
+
 #[derive(Clone, Debug, PartialEq, Insertable, Validate)]
 #[table_name = "media"]
 pub struct NewMedia {
@@ -135,12 +256,100 @@ impl NewMedia {
         }
     }
 
-    pub fn create(&self, conn: &PgConnection) -> QueryResult<Media> {
+    /// Insert the media. Returns `None` if media with the same id already exists.
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<Option<Media>> {
         use crate::schema::media::dsl::*;
 
         diesel::insert_into(media)
             .values(self)
             .on_conflict_do_nothing()
             .get_result::<Media>(conn)
+            .optional()
+    }
+}
+
+/// Tests that need a database connect to `DATABASE_URL` and are ignored by default; run them with
+/// `cargo test -- --ignored`. Every test runs in its own transaction, which is never committed.
+#[cfg(test)]
+mod test {
+    use super::{Media, NewMedia};
+    use crate::cursors;
+    use crate::test_support::{connection, create_fixture};
+
+    use chrono::{DateTime, Duration, TimeZone, Utc};
+    use uuid::Uuid;
+
+    /// The page size used while paging, small enough for ties to straddle page boundaries.
+    const PER_PAGE: usize = 2;
+
+    #[test]
+    #[ignore]
+    fn page_through_media() {
+        let conn = connection();
+        let peripheral = create_fixture(&conn).peripheral;
+
+        // Several media share a datetime, and the ids are not in datetime order, such that ties
+        // must be broken by id.
+        let base = Uuid::new_v4().as_u128() & !0xff;
+        let start = Utc.ymd(2020, 6, 1).and_hms(12, 0, 0);
+        let mut ids_and_datetimes: Vec<(DateTime<Utc>, Uuid)> =
+            vec![(5, 0), (3, 0), (6, 0), (0, 1), (4, 1), (1, 2), (2, 2)]
+                .into_iter()
+                .map(|(id, minutes)| {
+                    (
+                        start + Duration::minutes(minutes),
+                        Uuid::from_u128(base + id),
+                    )
+                })
+                .collect();
+        for &(datetime, id) in &ids_and_datetimes {
+            NewMedia::new(
+                id,
+                peripheral.get_id(),
+                peripheral.get_kit_id(),
+                peripheral.get_kit_configuration_id(),
+                datetime,
+                "image.jpg".to_owned(),
+                "image/jpeg".to_owned(),
+                serde_json::json!({}),
+                1024,
+            )
+            .create(&conn)
+            .unwrap()
+            .unwrap();
+        }
+        ids_and_datetimes.sort();
+
+        for &order in &[cursors::Order::Asc, cursors::Order::Desc] {
+            let mut seen = Vec::new();
+            let mut cursor = None;
+            loop {
+                let page = Media::page_of_size(
+                    &conn,
+                    peripheral.get_kit_id(),
+                    None,
+                    None,
+                    None,
+                    None,
+                    order,
+                    cursor,
+                    PER_PAGE,
+                )
+                .unwrap();
+                assert!(page.len() <= PER_PAGE);
+                seen.extend(page.iter().map(|media| (media.datetime, media.id)));
+                if page.len() < PER_PAGE {
+                    break;
+                }
+                let last = page.last().unwrap();
+                cursor = Some(cursors::Media(last.datetime, last.id));
+            }
+
+            let mut expected = ids_and_datetimes.clone();
+            if order == cursors::Order::Desc {
+                expected.reverse();
+            }
+            assert_eq!(seen, expected);
+        }
     }
 }
@@ -1,11 +1,39 @@
 mod kit;
-pub use kit::{Kit, KitId, NewKit, UpdateKit};
+pub use kit::{Kit, KitId, KitSeen, NewKit, UpdateKit};
 
 mod user;
 pub use user::{NewUser, UpdateUser, User, UserId};
 
+mod email_verification_token;
+pub use email_verification_token::{
+    EmailVerificationToken, EmailVerificationTokenId, NewEmailVerificationToken,
+};
+
+mod password_reset_token;
+pub use password_reset_token::{NewPasswordResetToken, PasswordResetToken, PasswordResetTokenId};
+
+mod session;
+pub use session::{NewSession, Session, SessionId};
+
+mod personal_access_token;
+pub use personal_access_token::{
+    NewPersonalAccessToken, PersonalAccessToken, PersonalAccessTokenId,
+};
+
+mod login_failure;
+pub use login_failure::LoginFailure;
+
+mod totp_secret;
+pub use totp_secret::{NewTotpSecret, TotpSecret};
+
+mod recovery_code;
+pub use recovery_code::{NewRecoveryCode, RecoveryCode};
+
 mod kit_membership;
-pub use kit_membership::{KitMembership, NewKitMembership};
+pub use kit_membership::{KitMembership, NewKitMembership, UpdateKitMembership};
+
+mod kit_invitation;
+pub use kit_invitation::{KitInvitation, KitInvitationId, NewKitInvitation};
 
 mod kit_configuration;
 pub use kit_configuration::{
@@ -25,7 +53,21 @@ mod peripheral_definition_expected_quantity_type;
 pub use peripheral_definition_expected_quantity_type::PeripheralDefinitionExpectedQuantityType;
 
 mod measurement;
-pub use measurement::{AggregateMeasurement, AggregateMeasurementId};
+pub use measurement::{
+    AggregateMeasurement, AggregateMeasurementId, MeasurementStatistics, NewAggregateMeasurement,
+    NewRawMeasurement, RawMeasurement, RawMeasurementId,
+};
 
 mod media;
-pub use media::{Media, MediaId, NewMedia};
+pub use media::{Media, MediaId, MediaUsage, NewMedia};
+
+mod alert;
+pub use alert::{
+    Alert, AlertCondition, AlertRule, AlertRuleId, NewAlert, NewAlertRule, UpdateAlertRule,
+};
+
+mod webhook;
+pub use webhook::{
+    NewWebhook, NewWebhookDelivery, UpdateWebhook, Webhook, WebhookDelivery, WebhookEvent,
+    WebhookId,
+};
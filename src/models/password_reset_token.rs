@@ -0,0 +1,131 @@
+use crate::schema::password_reset_tokens;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+use super::{User, UserId};
+
+/// The length of generated password reset tokens. Roughly 190 bits of entropy.
+const TOKEN_LENGTH: usize = 32;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "password_reset_tokens"]
+pub struct PasswordResetTokenId(#[column_name = "id"] pub i32);
+
+/// A password reset token. Only the hash of the token is stored.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[table_name = "password_reset_tokens"]
+pub struct PasswordResetToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl PasswordResetToken {
+    /// Get a password reset token by the token itself. The token is locked until the end of the
+    /// transaction, such that it cannot be used concurrently.
+    pub fn by_token_for_update(conn: &PgConnection, token: &str) -> QueryResult<Option<Self>> {
+        password_reset_tokens::table
+            .filter(
+                password_reset_tokens::dsl::token_hash.eq(astroplant_auth::hash::hash_token(token)),
+            )
+            .for_update()
+            .first(conn)
+            .optional()
+    }
+
+    /// Get the most recently created password reset token of a user.
+    pub fn latest_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<Option<Self>> {
+        PasswordResetToken::belonging_to(&user_id)
+            .order(password_reset_tokens::dsl::datetime_created.desc())
+            .first(conn)
+            .optional()
+    }
+
+    /// Whether this token can be used at the given datetime.
+    pub fn usable(&self, now: DateTime<Utc>) -> bool {
+        now < self.datetime_expires
+    }
+
+    /// Delete all password reset tokens of a user.
+    pub fn delete_all_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(PasswordResetToken::belonging_to(&user_id)).execute(conn)
+    }
+
+    /// Delete the password reset tokens of a user that have expired.
+    pub fn delete_expired_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        diesel::delete(
+            PasswordResetToken::belonging_to(&user_id)
+                .filter(password_reset_tokens::dsl::datetime_expires.le(now)),
+        )
+        .execute(conn)
+    }
+
+    pub fn get_id(&self) -> PasswordResetTokenId {
+        PasswordResetTokenId(self.id)
+    }
+
+    pub fn get_user_id(&self) -> UserId {
+        UserId(self.user_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "password_reset_tokens"]
+pub struct NewPasswordResetToken {
+    pub user_id: i32,
+    pub token_hash: String,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl NewPasswordResetToken {
+    /// Creates a new password reset token with a generated token. Returns the token alongside
+    /// the password reset token to be stored, as only the token's hash is stored.
+    pub fn new(user_id: UserId, now: DateTime<Utc>, validity: chrono::Duration) -> (Self, String) {
+        let token = random_string::string(TOKEN_LENGTH);
+        let new_token = Self {
+            user_id: user_id.0,
+            token_hash: astroplant_auth::hash::hash_token(&token),
+            datetime_created: now,
+            datetime_expires: now + validity,
+        };
+        (new_token, token)
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<PasswordResetToken> {
+        diesel::insert_into(password_reset_tokens::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::NewPasswordResetToken;
+    use crate::models::UserId;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn only_token_hash_is_stored() {
+        let now = Utc.ymd(2020, 6, 20).and_hms(12, 0, 0);
+        let (new_token, token) = NewPasswordResetToken::new(UserId(1), now, Duration::hours(1));
+
+        assert_ne!(new_token.token_hash, token);
+        assert_eq!(
+            new_token.token_hash,
+            astroplant_auth::hash::hash_token(&token)
+        );
+        assert_eq!(new_token.datetime_expires, now + Duration::hours(1));
+    }
+}
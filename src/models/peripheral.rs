@@ -58,6 +58,13 @@ impl Peripheral {
             .optional()
     }
 
+    pub fn by_ids(conn: &PgConnection, ids: Vec<i32>) -> QueryResult<Vec<Self>> {
+        use peripherals::dsl;
+        peripherals::table
+            .filter(dsl::id.eq(diesel::dsl::any(ids)))
+            .load(conn)
+    }
+
     pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
         diesel::delete(self).execute(conn).map(|r| r > 0)
     }
@@ -0,0 +1,179 @@
+use crate::authorization::KitAction;
+use crate::schema::personal_access_tokens;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+use super::{KitId, User, UserId};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "personal_access_tokens"]
+pub struct PersonalAccessTokenId(#[column_name = "id"] pub i32);
+
+/// A personal access token, allowing scripts and integrations to perform some actions on some kits
+/// on behalf of a user. The token itself is signed and not stored; it carries the id of this
+/// record, such that deleting the record revokes the token.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[table_name = "personal_access_tokens"]
+pub struct PersonalAccessToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub name: String,
+    pub kit_ids: Vec<i32>,
+    pub actions: Vec<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_last_used: Option<DateTime<Utc>>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl PersonalAccessToken {
+    /// Get a personal access token of a user that has not expired.
+    pub fn active_by_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        personal_access_token_id: PersonalAccessTokenId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Option<Self>> {
+        PersonalAccessToken::belonging_to(&user_id)
+            .filter(personal_access_tokens::dsl::id.eq(personal_access_token_id.0))
+            .filter(personal_access_tokens::dsl::datetime_expires.gt(now))
+            .first(conn)
+            .optional()
+    }
+
+    /// Get the personal access tokens of a user that have not expired, most recently created
+    /// first.
+    pub fn active_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Vec<Self>> {
+        PersonalAccessToken::belonging_to(&user_id)
+            .filter(personal_access_tokens::dsl::datetime_expires.gt(now))
+            .order(personal_access_tokens::dsl::datetime_created.desc())
+            .load(conn)
+    }
+
+    /// Whether this token permits performing the action on the kit.
+    pub fn permits(&self, kit_id: KitId, action: KitAction) -> bool {
+        self.kit_ids.contains(&kit_id.0) && self.permits_action(action)
+    }
+
+    /// Whether this token permits performing the action on the kits it is scoped to.
+    pub fn permits_action(&self, action: KitAction) -> bool {
+        self.actions
+            .iter()
+            .any(|granted| granted.parse::<KitAction>() == Ok(action))
+    }
+
+    /// Record that the token was used.
+    pub fn touch(&self, conn: &PgConnection, now: DateTime<Utc>) -> QueryResult<Self> {
+        diesel::update(self)
+            .set(personal_access_tokens::dsl::datetime_last_used.eq(now))
+            .get_result(conn)
+    }
+
+    /// Delete all personal access tokens of a user, revoking them.
+    pub fn delete_all_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(PersonalAccessToken::belonging_to(&user_id)).execute(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    pub fn get_id(&self) -> PersonalAccessTokenId {
+        PersonalAccessTokenId(self.id)
+    }
+
+    pub fn get_user_id(&self) -> UserId {
+        UserId(self.user_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "personal_access_tokens"]
+pub struct NewPersonalAccessToken {
+    pub user_id: i32,
+    pub name: String,
+    pub kit_ids: Vec<i32>,
+    pub actions: Vec<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl NewPersonalAccessToken {
+    pub fn new(
+        user_id: UserId,
+        name: String,
+        kit_ids: &[KitId],
+        actions: &[KitAction],
+        now: DateTime<Utc>,
+    ) -> Self {
+        let validity = astroplant_auth::token::PERSONAL_TOKEN_VALIDITY_TIME as i64;
+        Self {
+            user_id: user_id.0,
+            name,
+            kit_ids: kit_ids.iter().map(|kit_id| kit_id.0).collect(),
+            actions: actions
+                .iter()
+                .map(|&action| <&'static str>::from(action).to_owned())
+                .collect(),
+            datetime_created: now,
+            datetime_expires: now + chrono::Duration::seconds(validity),
+        }
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<PersonalAccessToken> {
+        diesel::insert_into(personal_access_tokens::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{NewPersonalAccessToken, PersonalAccessToken};
+    use crate::authorization::KitAction;
+    use crate::models::{KitId, UserId};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn permits_only_granted_actions_on_granted_kits() {
+        let now = Utc.ymd(2020, 6, 28).and_hms(12, 0, 0);
+        let new_token = NewPersonalAccessToken::new(
+            UserId(1),
+            "notebook".to_owned(),
+            &[KitId(1), KitId(2)],
+            &[KitAction::View, KitAction::SubscribeRealTimeMeasurements],
+            now,
+        );
+        assert_eq!(
+            new_token.actions,
+            vec![
+                "view".to_owned(),
+                "subscribeRealTimeMeasurements".to_owned()
+            ]
+        );
+
+        let token = PersonalAccessToken {
+            id: 1,
+            user_id: new_token.user_id,
+            name: new_token.name,
+            kit_ids: new_token.kit_ids,
+            actions: new_token.actions,
+            datetime_created: now,
+            datetime_last_used: None,
+            datetime_expires: new_token.datetime_expires,
+        };
+
+        assert!(token.permits(KitId(1), KitAction::View));
+        assert!(token.permits(KitId(2), KitAction::SubscribeRealTimeMeasurements));
+        assert!(!token.permits(KitId(3), KitAction::View));
+        assert!(!token.permits(KitId(1), KitAction::EditConfiguration));
+    }
+}
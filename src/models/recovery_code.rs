@@ -0,0 +1,113 @@
+use crate::schema::recovery_codes;
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+use super::{User, UserId};
+
+/// The number of recovery codes generated for a user.
+const RECOVERY_CODES: usize = 10;
+
+/// The length of generated recovery codes. Roughly 46 bits of entropy.
+const CODE_LENGTH: usize = 10;
+
+/// A one-time code to log in with instead of a two-factor authentication code, e.g. when the
+/// user has lost their authenticator. Only the hash of the code is stored.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[table_name = "recovery_codes"]
+pub struct RecoveryCode {
+    pub id: i32,
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+/// Normalize a recovery code as entered by a user, ignoring case, whitespace and separators.
+fn normalize(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_alphanumeric())
+        .collect::<String>()
+        .to_lowercase()
+}
+
+impl RecoveryCode {
+    /// Use up a recovery code of a user. Returns whether the code was valid.
+    pub fn use_code(conn: &PgConnection, user_id: UserId, code: &str) -> QueryResult<bool> {
+        let deleted = diesel::delete(RecoveryCode::belonging_to(&user_id).filter(
+            recovery_codes::dsl::code_hash.eq(astroplant_auth::hash::hash_token(&normalize(code))),
+        ))
+        .execute(conn)?;
+
+        Ok(deleted > 0)
+    }
+
+    pub fn delete_all_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(RecoveryCode::belonging_to(&user_id)).execute(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "recovery_codes"]
+pub struct NewRecoveryCode {
+    pub user_id: i32,
+    pub code_hash: String,
+}
+
+impl NewRecoveryCode {
+    /// Generates a set of recovery codes. Returns the codes, formatted for readability,
+    /// alongside the recovery codes to be stored, as only the codes' hashes are stored.
+    pub fn generate(user_id: UserId) -> (Vec<Self>, Vec<String>) {
+        (0..RECOVERY_CODES)
+            .map(|_| {
+                let code = random_string::unambiguous_lowercase_string(CODE_LENGTH);
+                let new_code = Self {
+                    user_id: user_id.0,
+                    code_hash: astroplant_auth::hash::hash_token(&code),
+                };
+                let (first, second) = code.split_at(CODE_LENGTH / 2);
+                (new_code, format!("{}-{}", first, second))
+            })
+            .unzip()
+    }
+
+    /// Replace all recovery codes of a user by the given codes.
+    pub fn replace_all_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        new_codes: &[Self],
+    ) -> QueryResult<usize> {
+        conn.transaction(|| {
+            RecoveryCode::delete_all_of_user_id(conn, user_id)?;
+            diesel::insert_into(recovery_codes::table)
+                .values(new_codes)
+                .execute(conn)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{normalize, NewRecoveryCode};
+    use crate::models::UserId;
+
+    #[test]
+    fn formatted_codes_match_their_hash() {
+        let (new_codes, codes) = NewRecoveryCode::generate(UserId(1));
+        assert_eq!(new_codes.len(), codes.len());
+
+        for (new_code, code) in new_codes.iter().zip(codes.iter()) {
+            assert_eq!(
+                new_code.code_hash,
+                astroplant_auth::hash::hash_token(&normalize(code))
+            );
+            assert_eq!(
+                new_code.code_hash,
+                astroplant_auth::hash::hash_token(&normalize(
+                    &code.to_uppercase().replace('-', " ")
+                ))
+            );
+        }
+    }
+}
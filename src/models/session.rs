@@ -0,0 +1,122 @@
+use crate::schema::sessions;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use uuid::Uuid;
+
+use super::{User, UserId};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "sessions"]
+pub struct SessionId(#[column_name = "id"] pub Uuid);
+
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[table_name = "sessions"]
+pub struct Session {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_last_used: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl Session {
+    /// Get a session of a user that has not expired.
+    pub fn active_by_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        session_id: SessionId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Option<Self>> {
+        Session::belonging_to(&user_id)
+            .filter(sessions::dsl::id.eq(session_id.0))
+            .filter(sessions::dsl::datetime_expires.gt(now))
+            .first(conn)
+            .optional()
+    }
+
+    /// Get the sessions of a user that have not expired, most recently used first.
+    pub fn active_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<Vec<Self>> {
+        Session::belonging_to(&user_id)
+            .filter(sessions::dsl::datetime_expires.gt(now))
+            .order(sessions::dsl::datetime_last_used.desc())
+            .load(conn)
+    }
+
+    /// Record that the session was used.
+    pub fn touch(&self, conn: &PgConnection, now: DateTime<Utc>) -> QueryResult<Self> {
+        diesel::update(self)
+            .set(sessions::dsl::datetime_last_used.eq(now))
+            .get_result(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    /// Delete all sessions of a user, revoking their refresh tokens.
+    pub fn delete_all_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(Session::belonging_to(&user_id)).execute(conn)
+    }
+
+    /// Delete the sessions of a user that have expired.
+    pub fn delete_expired_of_user_id(
+        conn: &PgConnection,
+        user_id: UserId,
+        now: DateTime<Utc>,
+    ) -> QueryResult<usize> {
+        diesel::delete(
+            Session::belonging_to(&user_id).filter(sessions::dsl::datetime_expires.le(now)),
+        )
+        .execute(conn)
+    }
+
+    pub fn get_id(&self) -> SessionId {
+        SessionId(self.id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "sessions"]
+pub struct NewSession {
+    pub id: Uuid,
+    pub user_id: i32,
+    pub user_agent: Option<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_last_used: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl NewSession {
+    pub fn new(
+        user_id: UserId,
+        user_agent: Option<String>,
+        now: DateTime<Utc>,
+        validity: chrono::Duration,
+    ) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            user_id: user_id.0,
+            // Limit the length of user agents, which are provided by the client.
+            user_agent: user_agent.map(|user_agent| user_agent.chars().take(255).collect()),
+            datetime_created: now,
+            datetime_last_used: now,
+            datetime_expires: now + validity,
+        }
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<Session> {
+        diesel::insert_into(sessions::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
@@ -0,0 +1,121 @@
+use crate::schema::totp_secrets;
+
+use chrono::{DateTime, Utc};
+use diesel::pg::upsert::excluded;
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+
+use super::{User, UserId};
+
+/// The TOTP secret of a user's two-factor authentication. The secret is pending until the user
+/// has proven to have configured it by providing a code, after which it is enabled.
+///
+/// Unlike passwords and tokens the secret cannot be hashed, as it is required to calculate codes.
+#[derive(Clone, Debug, PartialEq, Eq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "User", foreign_key = "user_id")]
+#[belongs_to(parent = "UserId", foreign_key = "user_id")]
+#[primary_key(user_id)]
+#[table_name = "totp_secrets"]
+pub struct TotpSecret {
+    pub user_id: i32,
+    pub secret: String,
+    pub enabled: bool,
+    pub datetime_created: DateTime<Utc>,
+    pub last_used_step: Option<i64>,
+}
+
+impl TotpSecret {
+    pub fn by_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<Option<Self>> {
+        totp_secrets::table.find(user_id.0).first(conn).optional()
+    }
+
+    /// Get the TOTP secret of a user if the user has two-factor authentication enabled.
+    pub fn enabled_by_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<Option<Self>> {
+        totp_secrets::table
+            .find(user_id.0)
+            .filter(totp_secrets::dsl::enabled.eq(true))
+            .first(conn)
+            .optional()
+    }
+
+    /// Check a code, and use it up if it is correct. Codes of a time step cannot be used more
+    /// than once, such that intercepted codes cannot be replayed.
+    pub fn use_code(
+        &self,
+        conn: &PgConnection,
+        code: &str,
+        now: DateTime<Utc>,
+    ) -> QueryResult<bool> {
+        use totp_secrets::dsl;
+
+        let step = match astroplant_auth::totp::check(&self.secret, code, now.timestamp() as u64) {
+            Some(step) => step as i64,
+            None => return Ok(false),
+        };
+
+        // Conditionally update, such that a code cannot be used concurrently either.
+        let updated = diesel::update(
+            totp_secrets::table.find(self.user_id).filter(
+                dsl::last_used_step
+                    .is_null()
+                    .or(dsl::last_used_step.lt(step)),
+            ),
+        )
+        .set(dsl::last_used_step.eq(step))
+        .execute(conn)?;
+
+        Ok(updated > 0)
+    }
+
+    pub fn enable(&self, conn: &PgConnection) -> QueryResult<Self> {
+        diesel::update(self)
+            .set(totp_secrets::dsl::enabled.eq(true))
+            .get_result(conn)
+    }
+
+    pub fn delete_of_user_id(conn: &PgConnection, user_id: UserId) -> QueryResult<usize> {
+        diesel::delete(totp_secrets::table.find(user_id.0)).execute(conn)
+    }
+
+    pub fn get_user_id(&self) -> UserId {
+        UserId(self.user_id)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Insertable)]
+#[table_name = "totp_secrets"]
+pub struct NewTotpSecret {
+    pub user_id: i32,
+    pub secret: String,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl NewTotpSecret {
+    /// Creates a new, pending TOTP secret with a generated secret.
+    pub fn new(user_id: UserId, now: DateTime<Utc>) -> Self {
+        Self {
+            user_id: user_id.0,
+            secret: astroplant_auth::totp::generate_secret(),
+            datetime_created: now,
+        }
+    }
+
+    /// Create the TOTP secret, replacing the user's existing TOTP secret if there is one. The
+    /// secret is pending until it is enabled.
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<TotpSecret> {
+        use totp_secrets::dsl;
+
+        diesel::insert_into(totp_secrets::table)
+            .values(self)
+            .on_conflict(dsl::user_id)
+            .do_update()
+            .set((
+                dsl::secret.eq(excluded(dsl::secret)),
+                dsl::enabled.eq(false),
+                dsl::datetime_created.eq(excluded(dsl::datetime_created)),
+                dsl::last_used_step.eq(None::<i64>),
+            ))
+            .get_result(conn)
+    }
+}
@@ -5,6 +5,8 @@ use diesel::prelude::*;
 use diesel::{Connection, Identifiable, QueryResult, Queryable};
 use validator::{Validate, ValidationError};
 
+sql_function!(fn lower(x: diesel::sql_types::Text) -> diesel::sql_types::Text);
+
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
 #[table_name = "users"]
 pub struct UserId(#[column_name = "id"] pub i32);
@@ -18,6 +20,7 @@ pub struct User {
     pub email_address: String,
     pub use_email_address_for_gravatar: bool,
     pub gravatar_alternative: String,
+    pub email_verified: bool,
 }
 
 impl User {
@@ -25,20 +28,35 @@ impl User {
         users::table.find(id.0).first(conn).optional()
     }
 
+    /// Get a user by username, compared case-insensitively.
     pub fn by_username(conn: &PgConnection, username: &str) -> QueryResult<Option<User>> {
         users::table
-            .filter(users::username.ilike(username))
+            .filter(lower(users::username).eq(lower(username)))
             .first(conn)
             .optional()
     }
 
+    /// Get a user by email address, compared case-insensitively.
     pub fn by_email_address(conn: &PgConnection, email_address: &str) -> QueryResult<Option<User>> {
         users::table
-            .filter(users::email_address.ilike(email_address))
+            .filter(lower(users::email_address).eq(lower(email_address)))
             .first(conn)
             .optional()
     }
 
+    /// Replace the hash of the user's password without changing the password, e.g. to upgrade
+    /// the hashing algorithm. Unlike changing the password, this does not revoke the user's
+    /// sessions.
+    pub fn update_password_hash(
+        &self,
+        conn: &PgConnection,
+        password_hash: String,
+    ) -> QueryResult<User> {
+        diesel::update(self)
+            .set(users::password_hash.eq(password_hash))
+            .get_result(conn)
+    }
+
     pub fn get_id(&self) -> UserId {
         UserId(self.id)
     }
@@ -56,6 +74,7 @@ pub struct UpdateUser {
     pub email_address: Option<String>,
     pub password_hash: Option<String>,
     pub use_email_address_for_gravatar: Option<bool>,
+    pub email_verified: Option<bool>,
 }
 
 impl UpdateUser {
@@ -66,11 +85,21 @@ impl UpdateUser {
             display_name: None,
             email_address: None,
             use_email_address_for_gravatar: None,
+            email_verified: None,
         }
     }
 
+    /// Update the user. If the password is changed, all of the user's sessions and personal access
+    /// tokens are revoked and their outstanding password reset tokens are deleted.
     pub fn update(&self, conn: &PgConnection) -> QueryResult<User> {
-        self.save_changes(conn)
+        conn.transaction(|| {
+            if self.password_hash.is_some() {
+                super::Session::delete_all_of_user_id(conn, UserId(self.id))?;
+                super::PasswordResetToken::delete_all_of_user_id(conn, UserId(self.id))?;
+                super::PersonalAccessToken::delete_all_of_user_id(conn, UserId(self.id))?;
+            }
+            self.save_changes(conn)
+        })
     }
 }
 
@@ -89,6 +118,7 @@ pub struct NewUser {
     pub email_address: String,
     use_email_address_for_gravatar: bool,
     gravatar_alternative: String,
+    email_verified: bool,
 }
 
 impl NewUser {
@@ -103,6 +133,7 @@ impl NewUser {
 
             use_email_address_for_gravatar: true,
             gravatar_alternative: random_string::unambiguous_string(32),
+            email_verified: false,
         }
     }
 
@@ -134,7 +165,9 @@ fn validate_username(username: &str) -> Result<(), ValidationError> {
 
 #[cfg(test)]
 mod test {
-    use super::NewUser;
+    use super::{NewUser, UpdateUser, User};
+    use crate::models::{NewPersonalAccessToken, PersonalAccessToken};
+    use crate::test_support::connection;
     use validator::{Validate, ValidationErrors};
 
     #[test]
@@ -196,4 +229,69 @@ mod test {
         );
         assert!(user.validate().is_ok());
     }
+
+    #[test]
+    #[ignore]
+    fn lookups_are_case_insensitive_but_exact() {
+        let conn = connection();
+        NewUser::new(
+            "lookup-example".to_owned(),
+            "".to_owned(),
+            "lookup@example.com".to_owned(),
+        )
+        .create(&conn)
+        .unwrap()
+        .unwrap();
+
+        assert!(User::by_username(&conn, "Lookup-Example")
+            .unwrap()
+            .is_some());
+        assert!(User::by_email_address(&conn, "LOOKUP@example.com")
+            .unwrap()
+            .is_some());
+
+        for pattern in &["lookup%", "lookup-exampl_", "%"] {
+            assert_eq!(User::by_username(&conn, pattern).unwrap(), None);
+        }
+        for pattern in &["lookup@%", "%@example.com", "lookup_example.com"] {
+            assert_eq!(User::by_email_address(&conn, pattern).unwrap(), None);
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn changing_password_revokes_personal_access_tokens() {
+        let conn = connection();
+        let user = NewUser::new(
+            "token-example".to_owned(),
+            "".to_owned(),
+            "token@example.com".to_owned(),
+        )
+        .create(&conn)
+        .unwrap()
+        .unwrap();
+        let now = chrono::Utc::now();
+        NewPersonalAccessToken::new(user.get_id(), "notebook".to_owned(), &[], &[], now)
+            .create(&conn)
+            .unwrap();
+
+        let mut update = UpdateUser::unchanged_for_id(user.id);
+        update.display_name = Some("Token Example".to_owned());
+        update.update(&conn).unwrap();
+        assert_eq!(
+            PersonalAccessToken::active_of_user_id(&conn, user.get_id(), now)
+                .unwrap()
+                .len(),
+            1
+        );
+
+        let mut update = UpdateUser::unchanged_for_id(user.id);
+        update.password_hash = Some("".to_owned());
+        update.update(&conn).unwrap();
+        assert!(
+            PersonalAccessToken::active_of_user_id(&conn, user.get_id(), now)
+                .unwrap()
+                .is_empty()
+        );
+    }
 }
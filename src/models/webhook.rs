@@ -0,0 +1,296 @@
+use crate::cursors;
+use crate::schema::{webhook_deliveries, webhooks};
+
+use chrono::{DateTime, Utc};
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+use diesel::{Identifiable, QueryResult, Queryable};
+use std::str::FromStr;
+use validator::Validate;
+
+use super::{Kit, KitId};
+
+/// The kit events webhooks can subscribe to.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum WebhookEvent {
+    Media,
+    ConfigurationActivated,
+    KitOffline,
+    Alert,
+}
+
+impl WebhookEvent {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            WebhookEvent::Media => "media",
+            WebhookEvent::ConfigurationActivated => "configurationActivated",
+            WebhookEvent::KitOffline => "kitOffline",
+            WebhookEvent::Alert => "alert",
+        }
+    }
+}
+
+impl FromStr for WebhookEvent {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "media" => Ok(WebhookEvent::Media),
+            "configurationActivated" => Ok(WebhookEvent::ConfigurationActivated),
+            "kitOffline" => Ok(WebhookEvent::KitOffline),
+            "alert" => Ok(WebhookEvent::Alert),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Identifiable)]
+#[table_name = "webhooks"]
+pub struct WebhookId(#[column_name = "id"] pub i32);
+
+#[derive(Clone, Debug, PartialEq, Queryable, Identifiable, Associations)]
+#[belongs_to(parent = "Kit", foreign_key = "kit_id")]
+#[belongs_to(parent = "KitId", foreign_key = "kit_id")]
+#[table_name = "webhooks"]
+pub struct Webhook {
+    pub id: i32,
+    pub kit_id: i32,
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub datetime_created: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, PartialEq, Identifiable, AsChangeset, Validate)]
+#[table_name = "webhooks"]
+pub struct UpdateWebhook {
+    pub id: i32,
+    // None means don't update.
+    #[validate(url)]
+    pub url: Option<String>,
+    pub events: Option<Vec<String>>,
+    pub enabled: Option<bool>,
+}
+
+impl Webhook {
+    pub fn by_id(conn: &PgConnection, webhook_id: WebhookId) -> QueryResult<Option<Self>> {
+        webhooks::table.find(&webhook_id.0).first(conn).optional()
+    }
+
+    pub fn webhooks_of_kit_id(conn: &PgConnection, kit_id: KitId) -> QueryResult<Vec<Self>> {
+        Webhook::belonging_to(&kit_id)
+            .order(webhooks::dsl::id.asc())
+            .load(conn)
+    }
+
+    /// Get the enabled webhooks of a kit subscribed to the given event.
+    pub fn subscribed_to(
+        conn: &PgConnection,
+        kit_id: KitId,
+        event: WebhookEvent,
+    ) -> QueryResult<Vec<Self>> {
+        Webhook::belonging_to(&kit_id)
+            .filter(webhooks::dsl::enabled.eq(true))
+            .filter(webhooks::dsl::events.contains(vec![event.as_str()]))
+            .load(conn)
+    }
+
+    pub fn delete(&self, conn: &PgConnection) -> QueryResult<bool> {
+        diesel::delete(self).execute(conn).map(|r| r > 0)
+    }
+
+    pub fn get_id(&self) -> WebhookId {
+        WebhookId(self.id)
+    }
+}
+
+impl UpdateWebhook {
+    pub fn update(&self, conn: &PgConnection) -> QueryResult<Webhook> {
+        self.save_changes(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable, Validate)]
+#[table_name = "webhooks"]
+pub struct NewWebhook {
+    pub kit_id: i32,
+    #[validate(url)]
+    pub url: String,
+    pub secret: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+}
+
+impl NewWebhook {
+    /// Creates a new webhook with a generated secret.
+    pub fn new(kit_id: KitId, url: String, events: Vec<String>, enabled: bool) -> Self {
+        Self {
+            kit_id: kit_id.0,
+            url,
+            // Roughly 190 bits of entropy.
+            secret: random_string::string(32),
+            events,
+            enabled,
+        }
+    }
+
+    pub fn create(&self, conn: &PgConnection) -> QueryResult<Webhook> {
+        diesel::insert_into(webhooks::table)
+            .values(self)
+            .get_result(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Queryable, QueryableByName, Identifiable, Associations)]
+#[belongs_to(parent = "Webhook", foreign_key = "webhook_id")]
+#[belongs_to(parent = "WebhookId", foreign_key = "webhook_id")]
+#[table_name = "webhook_deliveries"]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub datetime_created: DateTime<Utc>,
+    pub attempts: i32,
+    pub datetime_next_attempt: Option<DateTime<Utc>>,
+    pub datetime_delivered: Option<DateTime<Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+impl WebhookDelivery {
+    pub fn page(
+        conn: &PgConnection,
+        webhook_id: WebhookId,
+        cursor: Option<cursors::WebhookDeliveries>,
+    ) -> QueryResult<Vec<Self>> {
+        let mut query = WebhookDelivery::belonging_to(&webhook_id).into_boxed();
+
+        if let Some(cursors::WebhookDeliveries(datetime, id)) = cursor {
+            query = query.filter(
+                webhook_deliveries::columns::datetime_created
+                    .lt(datetime)
+                    .or(webhook_deliveries::columns::datetime_created
+                        .eq(datetime)
+                        .and(webhook_deliveries::columns::id.lt(id))),
+            );
+        }
+
+        query
+            .order((
+                webhook_deliveries::dsl::datetime_created.desc(),
+                webhook_deliveries::dsl::id.desc(),
+            ))
+            .limit(cursors::WebhookDeliveries::PER_PAGE as i64)
+            .load(conn)
+    }
+
+    /// Claim deliveries that are due for an attempt. The next attempt of the claimed deliveries
+    /// is postponed until `lease_until`, such that they are not claimed again while they are
+    /// being attempted, and are retried if the attempt is never recorded.
+    pub fn claim_due(
+        conn: &PgConnection,
+        now: DateTime<Utc>,
+        lease_until: DateTime<Utc>,
+        limit: i64,
+    ) -> QueryResult<Vec<Self>> {
+        use diesel::sql_types::{BigInt, Timestamptz};
+
+        diesel::sql_query(
+            r#"
+            UPDATE webhook_deliveries
+            SET datetime_next_attempt = $2
+            WHERE id IN (
+                SELECT id
+                FROM webhook_deliveries
+                WHERE datetime_next_attempt <= $1
+                ORDER BY datetime_next_attempt
+                LIMIT $3
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+        )
+        .bind::<Timestamptz, _>(now)
+        .bind::<Timestamptz, _>(lease_until)
+        .bind::<BigInt, _>(limit)
+        .load(conn)
+    }
+
+    /// Record an attempt to deliver. `next_attempt` is `None` if the delivery succeeded or is
+    /// given up on.
+    pub fn record_attempt(
+        &self,
+        conn: &PgConnection,
+        delivered: Option<DateTime<Utc>>,
+        next_attempt: Option<DateTime<Utc>>,
+        status_code: Option<i32>,
+        error: Option<String>,
+    ) -> QueryResult<Self> {
+        use webhook_deliveries::dsl;
+
+        diesel::update(self)
+            .set((
+                dsl::attempts.eq(self.attempts + 1),
+                dsl::datetime_delivered.eq(delivered),
+                dsl::datetime_next_attempt.eq(next_attempt),
+                dsl::last_status_code.eq(status_code),
+                dsl::last_error.eq(error),
+            ))
+            .get_result(conn)
+    }
+
+    /// Give up on the delivery without attempting it, e.g. because its webhook was disabled.
+    pub fn give_up(&self, conn: &PgConnection, error: String) -> QueryResult<Self> {
+        use webhook_deliveries::dsl;
+
+        diesel::update(self)
+            .set((
+                dsl::datetime_next_attempt.eq(None::<DateTime<Utc>>),
+                dsl::last_error.eq(error),
+            ))
+            .get_result(conn)
+    }
+
+    /// Delete deliveries created before the given datetime.
+    pub fn delete_before(conn: &PgConnection, before: DateTime<Utc>) -> QueryResult<usize> {
+        diesel::delete(
+            webhook_deliveries::table.filter(webhook_deliveries::dsl::datetime_created.lt(before)),
+        )
+        .execute(conn)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Insertable)]
+#[table_name = "webhook_deliveries"]
+pub struct NewWebhookDelivery {
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_next_attempt: Option<DateTime<Utc>>,
+}
+
+impl NewWebhookDelivery {
+    pub fn new(
+        webhook_id: WebhookId,
+        event: WebhookEvent,
+        payload: serde_json::Value,
+        datetime_created: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            webhook_id: webhook_id.0,
+            event: event.as_str().to_owned(),
+            payload,
+            datetime_created,
+            datetime_next_attempt: Some(datetime_created),
+        }
+    }
+
+    pub fn create_batch(conn: &PgConnection, deliveries: &[Self]) -> QueryResult<usize> {
+        diesel::insert_into(webhook_deliveries::table)
+            .values(deliveries)
+            .execute(conn)
+    }
+}
@@ -0,0 +1,457 @@
+//! Evaluates alert rules on the stream of raw measurements, and notifies subscribers and webhooks
+//! of alerts that fire or are resolved.
+
+use super::datetime_from_millis;
+use crate::database::PgPool;
+use crate::utils::outbound;
+use crate::websocket::Event;
+use crate::{helpers, models, views};
+
+use chrono::{DateTime, Utc};
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use futures::stream::StreamExt;
+use std::collections::HashMap;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The time between two refreshes of the alert rules.
+const REFRESH_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Transition {
+    /// The rule's condition has held for at least its duration, starting at `since`.
+    Fire { since: DateTime<Utc> },
+    /// The rule's condition no longer holds.
+    Resolve,
+}
+
+/// The evaluation state of a single alert rule.
+#[derive(Clone, Debug, Default)]
+struct RuleState {
+    /// The datetime of the last evaluated measurement.
+    last: Option<DateTime<Utc>>,
+    /// Since when the rule's condition holds.
+    since: Option<DateTime<Utc>>,
+    /// The alert that fired and has not been resolved yet.
+    alert: Option<models::Alert>,
+}
+
+impl RuleState {
+    fn firing(alert: models::Alert) -> Self {
+        Self {
+            last: None,
+            since: Some(alert.datetime_start),
+            alert: Some(alert),
+        }
+    }
+
+    /// Evaluate a measurement. Measurements older than the last evaluated measurement are
+    /// ignored.
+    fn update(
+        &mut self,
+        condition: models::AlertCondition,
+        threshold: f64,
+        duration: chrono::Duration,
+        value: f64,
+        datetime: DateTime<Utc>,
+    ) -> Option<Transition> {
+        if self.last.map(|last| datetime < last).unwrap_or(false) {
+            return None;
+        }
+        self.last = Some(datetime);
+
+        if condition.holds(value, threshold) {
+            let since = *self.since.get_or_insert(datetime);
+            if self.alert.is_none() && datetime - since >= duration {
+                return Some(Transition::Fire { since });
+            }
+            None
+        } else {
+            self.since = None;
+            if self.alert.is_some() {
+                Some(Transition::Resolve)
+            } else {
+                None
+            }
+        }
+    }
+}
+
+struct Evaluator {
+    pg: PgPool,
+    event_sender: mpsc::Sender<Event>,
+    http_client: outbound::Client,
+    /// The enabled rules, by kit serial, peripheral and quantity type.
+    rules: HashMap<(String, i32, i32), Vec<models::AlertRule>>,
+    states: HashMap<i32, RuleState>,
+}
+
+impl Evaluator {
+    /// Restore the state of alerts that fired before and have not been resolved.
+    async fn restore(&mut self) {
+        let conn = match self.pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not restore alerts: could not get a database connection");
+                return;
+            }
+        };
+
+        match helpers::threadpool(move || models::Alert::unresolved(&conn)).await {
+            Ok(alerts) => {
+                for alert in alerts {
+                    self.states
+                        .insert(alert.alert_rule_id, RuleState::firing(alert));
+                }
+            }
+            Err(err) => warn!("could not restore alerts: {:?}", err),
+        }
+    }
+
+    async fn refresh(&mut self) {
+        let conn = match self.pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not refresh alert rules: could not get a database connection");
+                return;
+            }
+        };
+
+        let rules =
+            match helpers::threadpool(move || models::AlertRule::enabled_with_kit_serials(&conn))
+                .await
+            {
+                Ok(rules) => rules,
+                Err(err) => {
+                    warn!("could not refresh alert rules: {:?}", err);
+                    return;
+                }
+            };
+
+        self.rules.clear();
+        for (rule, kit_serial) in rules {
+            self.rules
+                .entry((kit_serial, rule.peripheral_id, rule.quantity_type_id))
+                .or_default()
+                .push(rule);
+        }
+
+        // Resolve the alerts of rules that were disabled while firing. Deleted rules have their
+        // alerts deleted with them.
+        let rules = &self.rules;
+        let is_enabled = |rule_id: i32| rules.values().flatten().any(|rule| rule.id == rule_id);
+        let disabled: Vec<i32> = self
+            .states
+            .keys()
+            .copied()
+            .filter(|&rule_id| !is_enabled(rule_id))
+            .collect();
+        for rule_id in disabled {
+            if let Some(RuleState {
+                alert: Some(alert), ..
+            }) = self.states.remove(&rule_id)
+            {
+                self.resolve_quietly(alert).await;
+            }
+        }
+    }
+
+    async fn resolve_quietly(&self, alert: models::Alert) {
+        let conn = match self.pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => return,
+        };
+        let alert_id = alert.id;
+        if let Err(err) = helpers::threadpool(move || alert.resolve(&conn, Utc::now())).await {
+            warn!("could not resolve alert {}: {:?}", alert_id, err);
+        }
+    }
+
+    async fn evaluate(&mut self, raw_measurement: astroplant_mqtt::RawMeasurement) {
+        let astroplant_mqtt::RawMeasurement {
+            kit_serial,
+            datetime,
+            peripheral,
+            quantity_type,
+            value,
+            ..
+        } = raw_measurement;
+
+        let rules = match self
+            .rules
+            .get(&(kit_serial.clone(), peripheral, quantity_type))
+        {
+            Some(rules) => rules.clone(),
+            None => return,
+        };
+        let datetime = match datetime_from_millis(datetime) {
+            Some(datetime) => datetime,
+            None => return,
+        };
+
+        for rule in rules {
+            let state = self.states.entry(rule.id).or_default();
+            let transition = state.update(
+                rule.condition(),
+                rule.threshold,
+                chrono::Duration::seconds(rule.duration_seconds.into()),
+                value,
+                datetime,
+            );
+            // The alert is kept in the rule's state until it is stored as resolved, such that
+            // resolving it is retried on the next measurement if storing fails.
+            let firing_alert = match transition {
+                Some(Transition::Resolve) => state.alert.clone(),
+                _ => None,
+            };
+
+            let alert = match transition {
+                Some(Transition::Fire { since }) => {
+                    let new_alert = models::NewAlert::new(
+                        rule.get_id(),
+                        rule.get_kit_id(),
+                        since,
+                        datetime,
+                        value,
+                    );
+                    self.store(rule.id, move |conn| new_alert.create(conn))
+                        .await
+                }
+                Some(Transition::Resolve) => {
+                    let alert = firing_alert.unwrap();
+                    self.store(rule.id, move |conn| alert.resolve(conn, datetime))
+                        .await
+                }
+                None => continue,
+            };
+
+            if let Some(alert) = alert {
+                debug!(
+                    "alert rule {} of kit {}: {:?}",
+                    rule.id, kit_serial, transition
+                );
+                self.notify(
+                    views::AlertNotification::new(kit_serial.clone(), &rule, alert),
+                    &rule,
+                )
+                .await;
+            }
+        }
+    }
+
+    /// Store a fired or resolved alert. The stored alert is kept in the rule's state while it
+    /// has not been resolved, and is removed from it once it has been stored as resolved.
+    async fn store<F>(&mut self, rule_id: i32, f: F) -> Option<models::Alert>
+    where
+        F: FnOnce(&diesel::pg::PgConnection) -> diesel::QueryResult<models::Alert> + Send + 'static,
+    {
+        let conn = match self.pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!(
+                    "could not store alert of rule {}: could not get a database connection",
+                    rule_id
+                );
+                return None;
+            }
+        };
+
+        match helpers::threadpool(move || f(&conn)).await {
+            Ok(alert) => {
+                if let Some(state) = self.states.get_mut(&rule_id) {
+                    state.alert = if alert.datetime_resolved.is_none() {
+                        Some(alert.clone())
+                    } else {
+                        None
+                    };
+                }
+                Some(alert)
+            }
+            Err(err) => {
+                warn!("could not store alert of rule {}: {:?}", rule_id, err);
+                None
+            }
+        }
+    }
+
+    async fn notify(&mut self, notification: views::AlertNotification, rule: &models::AlertRule) {
+        if let Some(webhook_url) = rule.webhook_url.clone() {
+            let http_client = self.http_client.clone();
+            let body = serde_json::to_vec(&notification).expect("notifications serialize");
+            tokio::spawn(async move {
+                match outbound::post_json(&http_client, &webhook_url, &[], body).await {
+                    Ok(status) if status.is_success() => {}
+                    Ok(status) => warn!(
+                        "could not deliver alert to webhook {}: responded with {}",
+                        webhook_url, status
+                    ),
+                    Err(err) => warn!(
+                        "could not deliver alert to webhook {}: {}",
+                        webhook_url, err
+                    ),
+                }
+            });
+        }
+
+        crate::webhook::publish(
+            self.pg.clone(),
+            rule.get_kit_id(),
+            notification.kit_serial.clone(),
+            models::WebhookEvent::Alert,
+            &notification,
+        );
+        let _ = self.event_sender.send(Event::Alert(notification)).await;
+    }
+}
+
+/// Evaluate the alert rules on the received raw measurements. The rules are refreshed
+/// periodically, such that changes to rules take effect without restarting.
+pub async fn run_evaluator(
+    pg: PgPool,
+    event_sender: mpsc::Sender<Event>,
+    mut raw_measurement_receiver: mpsc::Receiver<astroplant_mqtt::RawMeasurement>,
+) {
+    let mut evaluator = Evaluator {
+        pg,
+        event_sender,
+        http_client: outbound::client(),
+        rules: HashMap::new(),
+        states: HashMap::new(),
+    };
+    evaluator.restore().await;
+    evaluator.refresh().await;
+    let mut refresh_at = Instant::now() + REFRESH_INTERVAL;
+
+    loop {
+        // Measurements may arrive faster than the deadline is checked.
+        if Instant::now() >= refresh_at {
+            evaluator.refresh().await;
+            refresh_at = Instant::now() + REFRESH_INTERVAL;
+        }
+
+        match tokio::time::timeout_at(refresh_at, raw_measurement_receiver.next()).await {
+            Ok(Some(raw_measurement)) => evaluator.evaluate(raw_measurement).await,
+            Ok(None) => break,
+            Err(_) => continue,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RuleState, Transition};
+    use crate::models::AlertCondition;
+    use chrono::{Duration, TimeZone, Utc};
+
+    #[test]
+    fn fires_after_duration() {
+        let start = Utc.ymd(2020, 6, 12).and_hms(12, 0, 0);
+        let duration = Duration::seconds(60);
+        let mut state = RuleState::default();
+        let update = |state: &mut RuleState, seconds: i64, value: f64| {
+            state.update(
+                AlertCondition::Above,
+                30.0,
+                duration,
+                value,
+                start + Duration::seconds(seconds),
+            )
+        };
+
+        assert_eq!(update(&mut state, 0, 25.0), None);
+        assert_eq!(update(&mut state, 10, 31.0), None);
+        assert_eq!(update(&mut state, 40, 32.0), None);
+        assert_eq!(
+            update(&mut state, 70, 33.0),
+            Some(Transition::Fire {
+                since: start + Duration::seconds(10)
+            })
+        );
+    }
+
+    #[test]
+    fn interrupted_condition_restarts_duration() {
+        let start = Utc.ymd(2020, 6, 12).and_hms(12, 0, 0);
+        let duration = Duration::seconds(60);
+        let mut state = RuleState::default();
+        let update = |state: &mut RuleState, seconds: i64, value: f64| {
+            state.update(
+                AlertCondition::Below,
+                10.0,
+                duration,
+                value,
+                start + Duration::seconds(seconds),
+            )
+        };
+
+        assert_eq!(update(&mut state, 0, 5.0), None);
+        assert_eq!(update(&mut state, 50, 15.0), None);
+        assert_eq!(update(&mut state, 70, 5.0), None);
+        // Older measurements are ignored.
+        assert_eq!(update(&mut state, 60, 5.0), None);
+        assert_eq!(
+            update(&mut state, 130, 5.0),
+            Some(Transition::Fire {
+                since: start + Duration::seconds(70)
+            })
+        );
+    }
+
+    #[test]
+    fn restored_alert_resolves() {
+        let start = Utc.ymd(2020, 6, 12).and_hms(12, 0, 0);
+        let mut state = RuleState::firing(crate::models::Alert {
+            id: 1,
+            alert_rule_id: 1,
+            kit_id: 1,
+            datetime_start: start,
+            datetime_fired: start + Duration::seconds(60),
+            datetime_resolved: None,
+            value: 31.0,
+        });
+
+        let at = |seconds| start + Duration::seconds(seconds);
+        let duration = Duration::seconds(60);
+        assert_eq!(
+            state.update(AlertCondition::Above, 30.0, duration, 32.0, at(120)),
+            None
+        );
+        assert_eq!(
+            state.update(AlertCondition::Above, 30.0, duration, 29.0, at(180)),
+            Some(Transition::Resolve)
+        );
+    }
+
+    #[test]
+    fn resolving_is_retried_while_alert_is_kept() {
+        let start = Utc.ymd(2020, 6, 12).and_hms(12, 0, 0);
+        let mut state = RuleState::firing(crate::models::Alert {
+            id: 1,
+            alert_rule_id: 1,
+            kit_id: 1,
+            datetime_start: start,
+            datetime_fired: start + Duration::seconds(60),
+            datetime_resolved: None,
+            value: 31.0,
+        });
+
+        // Storing the resolved alert failed, so the alert is still kept in the state.
+        let at = |seconds| start + Duration::seconds(seconds);
+        let duration = Duration::seconds(60);
+        assert_eq!(
+            state.update(AlertCondition::Above, 30.0, duration, 29.0, at(120)),
+            Some(Transition::Resolve)
+        );
+        assert_eq!(
+            state.update(AlertCondition::Above, 30.0, duration, 28.0, at(180)),
+            Some(Transition::Resolve)
+        );
+
+        state.alert = None;
+        assert_eq!(
+            state.update(AlertCondition::Above, 30.0, duration, 27.0, at(240)),
+            None
+        );
+    }
+}
@@ -1,24 +1,117 @@
+mod alerts;
+mod presence;
+mod raw_measurement_writer;
+
 use crate::database::PgPool;
+use crate::websocket::Event;
 use crate::{helpers, models, problem, views};
 
 use astroplant_mqtt::{MqttApiMessage, ServerRpcRequest};
+use diesel::pg::PgConnection;
 use futures::channel::{mpsc, oneshot};
 use futures::future::FutureExt;
 use futures::sink::SinkExt;
 use std::convert::TryFrom;
 use tokio::runtime::{Handle, Runtime};
 
+/// The maximum number of days raw measurements can be kept: a hundred years.
+const MAX_RAW_MEASUREMENT_RETENTION_DAYS: i64 = 36_500;
+
 #[derive(Debug)]
 enum Error {
     PgPool,
     Internal,
+    InvalidDatetime,
+    KitNotFound,
+    PeripheralNotFound,
+    /// The peripheral does not belong to the kit's active configuration.
+    PeripheralNotActive,
+}
+
+/// Convert a timestamp in milliseconds since the Unix epoch to a UTC datetime.
+fn datetime_from_millis(millis: u64) -> Option<chrono::DateTime<chrono::Utc>> {
+    let seconds = i64::try_from(millis / 1000).ok()?;
+    let nanoseconds = u32::try_from((millis % 1000) * 1_000_000).ok()?;
+    let naive = chrono::NaiveDateTime::from_timestamp_opt(seconds, nanoseconds)?;
+    Some(chrono::DateTime::from_utc(naive, chrono::Utc))
+}
+
+/// Find the kit with the given serial and the peripheral with the given id. Fails if the
+/// peripheral is not part of the kit's active configuration.
+fn kit_and_active_peripheral(
+    conn: &PgConnection,
+    kit_serial: String,
+    peripheral_id: models::PeripheralId,
+) -> Result<(models::Kit, models::Peripheral), Error> {
+    let kit = models::Kit::by_serial(conn, kit_serial)
+        .map_err(|_| Error::Internal)?
+        .ok_or(Error::KitNotFound)?;
+    let peripheral = models::Peripheral::by_id(conn, peripheral_id)
+        .map_err(|_| Error::Internal)?
+        .ok_or(Error::PeripheralNotFound)?;
+    let configuration = models::KitConfiguration::active_configuration_of_kit(conn, &kit)
+        .map_err(|_| Error::Internal)?
+        .ok_or(Error::PeripheralNotActive)?;
+
+    if peripheral.kit_id != kit.id || peripheral.kit_configuration_id != configuration.id {
+        return Err(Error::PeripheralNotActive);
+    }
+
+    Ok((kit, peripheral))
+}
+
+/// Store an aggregate measurement received from a kit. Returns `None` if the aggregate
+/// measurement was already stored before.
+fn store_aggregate_measurement(
+    conn: &PgConnection,
+    aggregate_measurement: astroplant_mqtt::AggregateMeasurement,
+) -> Result<Option<models::AggregateMeasurement>, Error> {
+    let astroplant_mqtt::AggregateMeasurement {
+        id,
+        kit_serial,
+        datetime_start,
+        datetime_end,
+        peripheral,
+        quantity_type,
+        values,
+    } = aggregate_measurement;
+
+    let datetime_start = datetime_from_millis(datetime_start).ok_or(Error::InvalidDatetime)?;
+    let datetime_end = datetime_from_millis(datetime_end).ok_or(Error::InvalidDatetime)?;
+
+    // Non-finite values cannot be represented in JSON.
+    let values: serde_json::Map<String, serde_json::Value> = values
+        .into_iter()
+        .filter(|(_, value)| value.is_finite())
+        .map(|(aggregate_type, value)| (aggregate_type, value.into()))
+        .collect();
+
+    let (kit, peripheral) =
+        kit_and_active_peripheral(conn, kit_serial, models::PeripheralId(peripheral))?;
+
+    models::NewAggregateMeasurement::new(
+        id,
+        peripheral.get_id(),
+        kit.get_id(),
+        peripheral.get_kit_configuration_id(),
+        models::QuantityTypeId(quantity_type),
+        datetime_start,
+        datetime_end,
+        serde_json::Value::Object(values),
+    )
+    .create(conn)
+    .map_err(|_| Error::Internal)
 }
 
 struct Handler {
     pg_pool: PgPool,
     object_store: astroplant_object::ObjectStore,
     runtime_handle: Handle,
-    raw_measurement_sender: mpsc::Sender<astroplant_mqtt::RawMeasurement>,
+    event_sender: mpsc::Sender<Event>,
+    raw_measurement_writer: Option<mpsc::Sender<astroplant_mqtt::RawMeasurement>>,
+    alert_evaluator: mpsc::Sender<astroplant_mqtt::RawMeasurement>,
+    media_quota: Option<crate::media::Quota>,
+    kit_offline_after: chrono::Duration,
 }
 
 impl Handler {
@@ -26,13 +119,21 @@ impl Handler {
         pg_pool: PgPool,
         object_store: astroplant_object::ObjectStore,
         runtime_handle: Handle,
-        raw_measurement_sender: mpsc::Sender<astroplant_mqtt::RawMeasurement>,
+        event_sender: mpsc::Sender<Event>,
+        raw_measurement_writer: Option<mpsc::Sender<astroplant_mqtt::RawMeasurement>>,
+        alert_evaluator: mpsc::Sender<astroplant_mqtt::RawMeasurement>,
+        media_quota: Option<crate::media::Quota>,
+        kit_offline_after: chrono::Duration,
     ) -> Self {
         Self {
             pg_pool,
             object_store,
             runtime_handle,
-            raw_measurement_sender,
+            event_sender,
+            raw_measurement_writer,
+            alert_evaluator,
+            media_quota,
+            kit_offline_after,
         }
     }
 
@@ -130,6 +231,44 @@ impl Handler {
         }
     }
 
+    async fn aggregate_measurement(
+        pg: PgPool,
+        event_sender: mpsc::Sender<Event>,
+        aggregate_measurement: astroplant_mqtt::AggregateMeasurement,
+    ) -> Result<(), Error> {
+        let id = aggregate_measurement.id;
+        let kit_serial = aggregate_measurement.kit_serial.clone();
+        trace!("storing aggregate measurement {} of kit {}", id, kit_serial);
+
+        let conn = pg.get().await.map_err(|_| Error::PgPool)?;
+        match helpers::threadpool(move || store_aggregate_measurement(&conn, aggregate_measurement))
+            .await
+        {
+            Ok(Some(aggregate_measurement)) => {
+                Self::send(
+                    event_sender,
+                    Event::AggregateMeasurement {
+                        kit_serial,
+                        aggregate_measurement,
+                    },
+                )
+                .await;
+                Ok(())
+            }
+            Ok(None) => {
+                debug!("ignored duplicate aggregate measurement {}", id);
+                Ok(())
+            }
+            Err(err) => {
+                warn!(
+                    "could not store aggregate measurement {} of kit {}: {:?}",
+                    id, kit_serial, err
+                );
+                Err(err)
+            }
+        }
+    }
+
     async fn send<T>(mut sender: mpsc::Sender<T>, val: T) {
         // TODO: handle errors.
         let _ = sender.send(val).await;
@@ -138,6 +277,8 @@ impl Handler {
     async fn upload_media(
         pg_pool: PgPool,
         object_store: astroplant_object::ObjectStore,
+        event_sender: mpsc::Sender<Event>,
+        media_quota: Option<crate::media::Quota>,
         media: astroplant_mqtt::Media,
     ) {
         let implementation = move || async move {
@@ -153,7 +294,7 @@ impl Handler {
             } = media;
 
             // TODO: handle errors.
-            let object_name = id.to_hyphenated().to_string();
+            let object_name = crate::media::object_name(models::MediaId(id));
             let size = i64::try_from(data.len()).map_err(|_| problem::INTERNAL_SERVER_ERROR)?;
 
             let naive = chrono::NaiveDateTime::from_timestamp(
@@ -179,11 +320,37 @@ impl Handler {
             .await?
             .ok_or_else(|| problem::NOT_FOUND)?;
 
-            let _ = object_store
+            if let Some(media_quota) = media_quota {
+                let conn = pg_pool.clone().get().await?;
+                let kit_id = peripheral.get_kit_id();
+                let kit = helpers::threadpool(move || models::Kit::by_id(&conn, kit_id))
+                    .await?
+                    .ok_or_else(|| problem::NOT_FOUND)?;
+
+                if !crate::media::make_room(pg_pool.clone(), &object_store, &kit, media_quota, size)
+                    .await?
+                {
+                    warn!(
+                        "rejecting media {} of kit {} ({} bytes): the kit is over its media quota",
+                        id, kit.serial, size
+                    );
+                    return Err(problem::FORBIDDEN);
+                }
+            }
+
+            if let Err(err) = object_store
                 .put(&kit_serial, &object_name, data, r#type.clone())
-                .await;
-            let conn = pg_pool.get().await?;
-            if let Err(_) = helpers::threadpool(move || {
+                .await
+            {
+                warn!(
+                    "could not store media {} of kit {}: {:?}",
+                    id, kit_serial, err
+                );
+                return Err(problem::INTERNAL_SERVER_ERROR);
+            }
+
+            let conn = pg_pool.clone().get().await?;
+            let created = helpers::threadpool(move || {
                 let new = models::NewMedia::new(
                     id,
                     peripheral.get_id(),
@@ -197,9 +364,43 @@ impl Handler {
                 );
                 new.create(&conn)
             })
-            .await
-            {
-                // TODO: Remove object
+            .await;
+
+            match created {
+                Ok(Some(media)) => {
+                    let content_url = crate::media::content_uri(media.get_id());
+                    if let Ok(media_view) = views::Media::try_from(media.clone()) {
+                        crate::webhook::publish(
+                            pg_pool,
+                            media.get_kit_id(),
+                            kit_serial.clone(),
+                            models::WebhookEvent::Media,
+                            &views::MediaWithContentUrl {
+                                media: media_view,
+                                content_url,
+                            },
+                        );
+                    }
+                    Self::send(event_sender, Event::Media { kit_serial, media }).await;
+                }
+                Ok(None) => {
+                    // The media was uploaded before: the object overwritten just now is still
+                    // referred to by the existing row.
+                    debug!("media {} of kit {} was already uploaded", id, kit_serial);
+                }
+                Err(err) => {
+                    warn!(
+                        "could not create media {} of kit {}, removing its object: {:?}",
+                        id, kit_serial, err
+                    );
+                    if let Err(err) = object_store.delete(&kit_serial, &object_name).await {
+                        warn!(
+                            "could not remove object of media {} of kit {}: {:?}",
+                            id, kit_serial, err
+                        );
+                    }
+                    return Err(problem::INTERNAL_SERVER_ERROR);
+                }
             }
 
             Ok::<(), problem::Problem>(())
@@ -215,20 +416,54 @@ impl Handler {
         for message in message_receiver {
             match message {
                 MqttApiMessage::ServerRpcRequest(request) => self.server_rpc_request(request),
+                MqttApiMessage::KitSeen(kit_seen) => {
+                    self.runtime_handle.spawn(
+                        presence::kit_seen(
+                            self.pg_pool.clone(),
+                            self.event_sender.clone(),
+                            self.kit_offline_after,
+                            kit_seen,
+                        )
+                        .map(|_| ()),
+                    );
+                }
                 MqttApiMessage::RawMeasurement(measurement) => {
                     println!("Received measurement: {:?}", measurement);
-                    self.runtime_handle
-                        .spawn(Self::send(self.raw_measurement_sender.clone(), measurement));
+                    if let Some(raw_measurement_writer) = &self.raw_measurement_writer {
+                        self.runtime_handle.spawn(Self::send(
+                            raw_measurement_writer.clone(),
+                            measurement.clone(),
+                        ));
+                    }
+                    self.runtime_handle.spawn(Self::send(
+                        self.alert_evaluator.clone(),
+                        measurement.clone(),
+                    ));
+                    self.runtime_handle.spawn(Self::send(
+                        self.event_sender.clone(),
+                        Event::RawMeasurement(measurement),
+                    ));
+                }
+                MqttApiMessage::AggregateMeasurement(aggregate_measurement) => {
+                    self.runtime_handle.spawn(
+                        Self::aggregate_measurement(
+                            self.pg_pool.clone(),
+                            self.event_sender.clone(),
+                            aggregate_measurement,
+                        )
+                        .map(|_| ()),
+                    );
                 }
                 MqttApiMessage::Media(media) => {
                     println!("Received media: {:?}", media.name);
                     self.runtime_handle.spawn(Self::upload_media(
                         self.pg_pool.clone(),
                         self.object_store.clone(),
+                        self.event_sender.clone(),
+                        self.media_quota,
                         media,
                     ));
                 }
-                _ => {}
             }
         }
     }
@@ -237,11 +472,30 @@ impl Handler {
 pub fn run(
     pg_pool: PgPool,
     object_store: astroplant_object::ObjectStore,
-) -> (
-    mpsc::Receiver<astroplant_mqtt::RawMeasurement>,
-    astroplant_mqtt::KitsRpc,
-) {
-    let (raw_measurement_sender, raw_measurement_receiver) = mpsc::channel(128);
+    media_quota: Option<crate::media::Quota>,
+    kit_offline_after: chrono::Duration,
+) -> (mpsc::Receiver<Event>, astroplant_mqtt::KitsRpc) {
+    let (event_sender, event_receiver) = mpsc::channel(128);
+
+    // A retention of zero days disables storing raw measurements.
+    let raw_measurement_retention_days: i64 = match std::env::var("RAW_MEASUREMENT_RETENTION_DAYS")
+    {
+        Ok(days) => days.parse().unwrap_or_else(|_| {
+            panic!(
+                "RAW_MEASUREMENT_RETENTION_DAYS must be a whole number of days, but is \"{}\".",
+                days
+            )
+        }),
+        Err(_) => crate::DEFAULT_RAW_MEASUREMENT_RETENTION_DAYS,
+    };
+    if raw_measurement_retention_days < 0
+        || raw_measurement_retention_days > MAX_RAW_MEASUREMENT_RETENTION_DAYS
+    {
+        panic!(
+            "RAW_MEASUREMENT_RETENTION_DAYS must be between 0 and {} days, but is {}.",
+            MAX_RAW_MEASUREMENT_RETENTION_DAYS, raw_measurement_retention_days
+        );
+    }
 
     let (message_receiver, kits_rpc) = astroplant_mqtt::run(
         std::env::var("MQTT_HOST").unwrap_or(crate::DEFAULT_MQTT_HOST.to_owned()),
@@ -260,16 +514,347 @@ pub fn run(
 
         std::thread::spawn(move || runtime.block_on(thread_pool_handle_receiver));
 
+        let raw_measurement_writer = if raw_measurement_retention_days > 0 {
+            let (raw_measurement_writer, raw_measurement_writer_receiver) = mpsc::channel(1024);
+            runtime_handle.spawn(raw_measurement_writer::run_writer(
+                pg_pool.clone(),
+                raw_measurement_writer_receiver,
+            ));
+            runtime_handle.spawn(raw_measurement_writer::run_pruner(
+                pg_pool.clone(),
+                chrono::Duration::days(raw_measurement_retention_days),
+            ));
+            Some(raw_measurement_writer)
+        } else {
+            info!("Raw measurement retention is zero days: not storing raw measurements.");
+            None
+        };
+
+        let (alert_evaluator, alert_evaluator_receiver) = mpsc::channel(1024);
+        runtime_handle.spawn(alerts::run_evaluator(
+            pg_pool.clone(),
+            event_sender.clone(),
+            alert_evaluator_receiver,
+        ));
+
+        runtime_handle.spawn(presence::run_offline_watcher(
+            pg_pool.clone(),
+            event_sender.clone(),
+            kit_offline_after,
+        ));
+
         let mut handler = Handler::new(
             pg_pool,
             object_store,
             runtime_handle,
-            raw_measurement_sender,
+            event_sender,
+            raw_measurement_writer,
+            alert_evaluator,
+            media_quota,
+            kit_offline_after,
         );
         handler.run(message_receiver);
 
         thread_pool_handle_sender.send(()).unwrap();
     });
 
-    (raw_measurement_receiver, kits_rpc)
+    (event_receiver, kits_rpc)
+}
+
+/// These tests need a PostgreSQL database with the AstroPlant schema, reachable through
+/// `DATABASE_URL`. Run them with `cargo test -- --ignored`. All changes are made in a test
+/// transaction, which is never committed.
+#[cfg(test)]
+mod test {
+    use super::raw_measurement_writer::{insert_raw_measurements, store_raw_measurements};
+    use super::{store_aggregate_measurement, Error};
+    use crate::models;
+    use crate::test_support::{connection, create_fixture, Fixture};
+
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    /// Generate a fresh id. Good enough for tests running in their own transaction.
+    fn unique_id() -> Uuid {
+        let nanos = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        Uuid::from_u128(nanos)
+    }
+
+    fn aggregate_measurement(fixture: &Fixture, id: Uuid) -> astroplant_mqtt::AggregateMeasurement {
+        let mut values = HashMap::new();
+        values.insert("average".to_owned(), 21.5);
+        values.insert("minimum".to_owned(), 20.0);
+        values.insert("maximum".to_owned(), 23.0);
+
+        astroplant_mqtt::AggregateMeasurement {
+            id,
+            kit_serial: fixture.kit.serial.clone(),
+            datetime_start: 1_590_000_000_000,
+            datetime_end: 1_590_000_600_000,
+            peripheral: fixture.peripheral.id,
+            quantity_type: fixture.quantity_type_id.0,
+            values,
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn store_aggregate_measurement_of_active_peripheral() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+        let id = unique_id();
+        let measurement = aggregate_measurement(&fixture, id);
+
+        let stored = store_aggregate_measurement(&conn, measurement)
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.id, id);
+        assert_eq!(stored.kit_id, fixture.kit.id);
+        assert_eq!(stored.kit_configuration_id, fixture.configuration.id);
+        assert_eq!(stored.values["average"], serde_json::json!(21.5));
+        assert_eq!(stored.datetime_start.timestamp_millis(), 1_590_000_000_000);
+
+        let found =
+            models::AggregateMeasurement::by_id(&conn, models::AggregateMeasurementId(id)).unwrap();
+        assert_eq!(found, Some(stored));
+    }
+
+    #[test]
+    #[ignore]
+    fn ignore_duplicate_aggregate_measurement() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+        let id = unique_id();
+        let measurement = aggregate_measurement(&fixture, id);
+        let duplicate = aggregate_measurement(&fixture, id);
+
+        assert!(store_aggregate_measurement(&conn, measurement)
+            .unwrap()
+            .is_some());
+        assert!(store_aggregate_measurement(&conn, duplicate)
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    #[ignore]
+    fn reject_peripheral_of_inactive_configuration() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let inactive_configuration = models::NewKitConfiguration::new(fixture.kit.get_id(), None)
+            .create(&conn)
+            .unwrap();
+        let inactive_peripheral = models::NewPeripheral::new(
+            fixture.kit.get_id(),
+            inactive_configuration.get_id(),
+            fixture.peripheral_definition_id,
+            "Inactive sensor".to_owned(),
+            serde_json::json!({}),
+        )
+        .create(&conn)
+        .unwrap();
+
+        let mut measurement = aggregate_measurement(&fixture, unique_id());
+        measurement.peripheral = inactive_peripheral.id;
+
+        assert!(match store_aggregate_measurement(&conn, measurement) {
+            Err(Error::PeripheralNotActive) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn reject_peripheral_of_other_kit() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+        let other_fixture = create_fixture(&conn);
+
+        let mut measurement = aggregate_measurement(&fixture, unique_id());
+        measurement.peripheral = other_fixture.peripheral.id;
+
+        assert!(match store_aggregate_measurement(&conn, measurement) {
+            Err(Error::PeripheralNotActive) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn reject_unknown_kit() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let mut measurement = aggregate_measurement(&fixture, unique_id());
+        measurement.kit_serial = "k-this-does-not-exist".to_owned();
+
+        assert!(match store_aggregate_measurement(&conn, measurement) {
+            Err(Error::KitNotFound) => true,
+            _ => false,
+        });
+    }
+
+    #[test]
+    #[ignore]
+    fn record_kit_seen() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+        let serial = &fixture.kit.serial;
+        let first = super::datetime_from_millis(1_590_000_000_000).unwrap();
+        let second = super::datetime_from_millis(1_590_000_060_000).unwrap();
+
+        let seen = models::Kit::record_seen(&conn, serial, first)
+            .unwrap()
+            .unwrap();
+        assert_eq!(seen.id, fixture.kit.id);
+        assert_eq!(seen.previous_last_seen, None);
+
+        let seen = models::Kit::record_seen(&conn, serial, second)
+            .unwrap()
+            .unwrap();
+        assert_eq!(seen.previous_last_seen, Some(first));
+
+        // Kits are never seen back in time.
+        assert!(models::Kit::record_seen(&conn, serial, first)
+            .unwrap()
+            .is_none());
+        assert!(
+            models::Kit::record_seen(&conn, "k-this-does-not-exist", second)
+                .unwrap()
+                .is_none()
+        );
+
+        let kit = models::Kit::by_id(&conn, fixture.kit.get_id())
+            .unwrap()
+            .unwrap();
+        assert_eq!(kit.last_seen, Some(second));
+        assert!(models::Kit::last_seen_between(&conn, first, second)
+            .unwrap()
+            .into_iter()
+            .any(|kit| kit.id == fixture.kit.id));
+    }
+
+    fn raw_measurement(
+        fixture: &Fixture,
+        id: Uuid,
+        datetime: u64,
+    ) -> astroplant_mqtt::RawMeasurement {
+        astroplant_mqtt::RawMeasurement {
+            id,
+            kit_serial: fixture.kit.serial.clone(),
+            datetime,
+            peripheral: fixture.peripheral.id,
+            quantity_type: fixture.quantity_type_id.0,
+            value: 21.5,
+        }
+    }
+
+    #[test]
+    #[ignore]
+    fn store_batch_of_raw_measurements() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+        let other_fixture = create_fixture(&conn);
+
+        let id = unique_id();
+        let duplicate_id = Uuid::from_u128(id.as_u128() + 1);
+
+        let mut unknown_kit = raw_measurement(&fixture, Uuid::from_u128(id.as_u128() + 2), 0);
+        unknown_kit.kit_serial = "k-this-does-not-exist".to_owned();
+        let mut foreign_peripheral =
+            raw_measurement(&fixture, Uuid::from_u128(id.as_u128() + 3), 0);
+        foreign_peripheral.peripheral = other_fixture.peripheral.id;
+        // Would violate a foreign key constraint; must not cause the rest of the batch to be
+        // dropped.
+        let mut unknown_quantity_type =
+            raw_measurement(&fixture, Uuid::from_u128(id.as_u128() + 4), 0);
+        unknown_quantity_type.quantity_type = -1;
+
+        let batch = vec![
+            raw_measurement(&fixture, id, 1_590_000_000_000),
+            raw_measurement(&fixture, duplicate_id, 1_590_000_010_000),
+            raw_measurement(&fixture, duplicate_id, 1_590_000_010_000),
+            unknown_kit,
+            foreign_peripheral,
+            unknown_quantity_type,
+        ];
+
+        assert_eq!(store_raw_measurements(&conn, batch).unwrap(), 2);
+
+        let stored = models::RawMeasurement::by_id(&conn, models::RawMeasurementId(id))
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored.kit_id, fixture.kit.id);
+        assert_eq!(stored.kit_configuration_id, fixture.configuration.id);
+        assert_eq!(stored.value, 21.5);
+        assert_eq!(stored.datetime.timestamp_millis(), 1_590_000_000_000);
+    }
+
+    #[test]
+    #[ignore]
+    fn insert_raw_measurements_one_by_one_after_failure() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let id = unique_id();
+        let new_raw_measurement = |id: Uuid, quantity_type_id: models::QuantityTypeId| {
+            models::NewRawMeasurement::new(
+                id,
+                fixture.peripheral.get_id(),
+                fixture.kit.get_id(),
+                fixture.configuration.get_id(),
+                quantity_type_id,
+                21.5,
+                chrono::Utc::now(),
+            )
+        };
+        let batch = vec![
+            new_raw_measurement(id, fixture.quantity_type_id),
+            new_raw_measurement(
+                Uuid::from_u128(id.as_u128() + 1),
+                models::QuantityTypeId(-1),
+            ),
+            new_raw_measurement(Uuid::from_u128(id.as_u128() + 2), fixture.quantity_type_id),
+        ];
+
+        assert_eq!(insert_raw_measurements(&conn, &batch), 2);
+        assert!(
+            models::RawMeasurement::by_id(&conn, models::RawMeasurementId(id))
+                .unwrap()
+                .is_some()
+        );
+    }
+
+    #[test]
+    #[ignore]
+    fn prune_raw_measurements() {
+        let conn = connection();
+        let fixture = create_fixture(&conn);
+
+        let old_id = unique_id();
+        let new_id = Uuid::from_u128(old_id.as_u128() + 1);
+        let batch = vec![
+            raw_measurement(&fixture, old_id, 1_590_000_000_000),
+            raw_measurement(&fixture, new_id, 1_590_000_010_000),
+        ];
+        assert_eq!(store_raw_measurements(&conn, batch).unwrap(), 2);
+
+        let before = super::datetime_from_millis(1_590_000_005_000).unwrap();
+        assert!(models::RawMeasurement::delete_before(&conn, before, 10_000).unwrap() >= 1);
+
+        assert!(
+            models::RawMeasurement::by_id(&conn, models::RawMeasurementId(old_id))
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            models::RawMeasurement::by_id(&conn, models::RawMeasurementId(new_id))
+                .unwrap()
+                .is_some()
+        );
+    }
 }
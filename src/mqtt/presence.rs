@@ -0,0 +1,114 @@
+//! Tracks when kits were last seen, and publishes kits going online and offline.
+
+use super::{datetime_from_millis, Error};
+use crate::database::PgPool;
+use crate::websocket::Event;
+use crate::{helpers, models, views};
+
+use futures::channel::mpsc;
+use futures::sink::SinkExt;
+use std::time::Duration;
+
+/// The time between two checks for kits that went offline.
+const OFFLINE_CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+async fn publish(mut event_sender: mpsc::Sender<Event>, kit_status: views::KitStatus) {
+    let _ = event_sender.send(Event::KitStatus(kit_status)).await;
+}
+
+/// Record that a kit was seen. If the kit was offline before, it is published as online.
+pub async fn kit_seen(
+    pg: PgPool,
+    event_sender: mpsc::Sender<Event>,
+    offline_after: chrono::Duration,
+    kit_seen: astroplant_mqtt::KitSeen,
+) -> Result<(), Error> {
+    let astroplant_mqtt::KitSeen {
+        kit_serial,
+        datetime,
+    } = kit_seen;
+    let datetime = datetime_from_millis(datetime).ok_or(Error::InvalidDatetime)?;
+
+    let conn = pg.get().await.map_err(|_| Error::PgPool)?;
+    let serial = kit_serial.clone();
+    let seen = helpers::threadpool(move || models::Kit::record_seen(&conn, &serial, datetime))
+        .await
+        .map_err(|err| {
+            warn!("could not record kit {} as seen: {:?}", kit_serial, err);
+            Error::Internal
+        })?;
+
+    if let Some(models::KitSeen {
+        previous_last_seen, ..
+    }) = seen
+    {
+        let was_online = views::KitStatus::new(
+            kit_serial.clone(),
+            previous_last_seen,
+            offline_after,
+            datetime,
+        )
+        .online;
+        if !was_online {
+            debug!("kit {} came online", kit_serial);
+            publish(
+                event_sender,
+                views::KitStatus::new(kit_serial, Some(datetime), offline_after, datetime),
+            )
+            .await;
+        }
+    }
+
+    Ok(())
+}
+
+/// Periodically publish the kits that went offline: kits that have not been seen for
+/// `offline_after`.
+pub async fn run_offline_watcher(
+    pg: PgPool,
+    event_sender: mpsc::Sender<Event>,
+    offline_after: chrono::Duration,
+) {
+    let mut interval = tokio::time::interval(OFFLINE_CHECK_INTERVAL);
+    let mut checked_until = chrono::Utc::now() - offline_after;
+
+    loop {
+        interval.tick().await;
+
+        let now = chrono::Utc::now();
+        let until = now - offline_after;
+        let conn = match pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not check for offline kits: could not get a database connection");
+                continue;
+            }
+        };
+
+        let after = checked_until;
+        match helpers::threadpool(move || models::Kit::last_seen_between(&conn, after, until)).await
+        {
+            Ok(kits) => {
+                for kit in kits {
+                    debug!("kit {} went offline", kit.serial);
+                    let kit_status = views::KitStatus::new(
+                        kit.serial.clone(),
+                        kit.last_seen,
+                        offline_after,
+                        now,
+                    );
+                    crate::webhook::publish(
+                        pg.clone(),
+                        kit.get_id(),
+                        kit.serial,
+                        models::WebhookEvent::KitOffline,
+                        &kit_status,
+                    );
+                    publish(event_sender.clone(), kit_status).await;
+                }
+                checked_until = until;
+            }
+            Err(err) => warn!("could not check for offline kits: {:?}", err),
+        }
+    }
+}
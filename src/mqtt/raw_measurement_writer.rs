@@ -0,0 +1,255 @@
+//! Persists raw measurements in batches, and prunes raw measurements older than the retention
+//! window.
+
+use super::{datetime_from_millis, kit_and_active_peripheral, Error};
+use crate::database::PgPool;
+use crate::{helpers, models};
+
+use diesel::pg::PgConnection;
+use diesel::Connection;
+use futures::channel::mpsc;
+use futures::stream::StreamExt;
+use std::collections::{HashMap, HashSet};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The maximum amount of raw measurements inserted in a single query.
+const BATCH_SIZE: usize = 256;
+
+/// The maximum time a raw measurement is buffered before it is written.
+const FLUSH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The time between two pruning runs.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// The maximum amount of raw measurements deleted in a single query while pruning.
+const PRUNE_BATCH_SIZE: i64 = 10_000;
+
+/// Store raw measurements received from kits. Raw measurements of unknown kits, of peripherals
+/// that are not part of their kit's active configuration, or of unknown quantity types, are
+/// dropped.
+///
+/// Returns the amount of stored raw measurements.
+pub(super) fn store_raw_measurements(
+    conn: &PgConnection,
+    raw_measurements: Vec<astroplant_mqtt::RawMeasurement>,
+) -> Result<usize, Error> {
+    // A batch usually holds many measurements of only a few peripherals.
+    let mut peripherals: HashMap<(String, i32), Option<models::Peripheral>> = HashMap::new();
+    let mut new_raw_measurements = Vec::with_capacity(raw_measurements.len());
+
+    for raw_measurement in raw_measurements {
+        let astroplant_mqtt::RawMeasurement {
+            id,
+            kit_serial,
+            datetime,
+            peripheral,
+            quantity_type,
+            value,
+        } = raw_measurement;
+
+        let datetime = match datetime_from_millis(datetime) {
+            Some(datetime) => datetime,
+            None => {
+                debug!("dropping raw measurement {}: invalid datetime", id);
+                continue;
+            }
+        };
+
+        let key = (kit_serial, peripheral);
+        if !peripherals.contains_key(&key) {
+            let resolved = match kit_and_active_peripheral(
+                conn,
+                key.0.clone(),
+                models::PeripheralId(peripheral),
+            ) {
+                Ok((_, peripheral)) => Some(peripheral),
+                Err(Error::Internal) => return Err(Error::Internal),
+                Err(err) => {
+                    debug!(
+                        "dropping raw measurements of kit {} peripheral {}: {:?}",
+                        key.0, key.1, err
+                    );
+                    None
+                }
+            };
+            peripherals.insert(key.clone(), resolved);
+        }
+
+        if let Some(peripheral) = &peripherals[&key] {
+            new_raw_measurements.push(models::NewRawMeasurement::new(
+                id,
+                peripheral.get_id(),
+                peripheral.get_kit_id(),
+                peripheral.get_kit_configuration_id(),
+                models::QuantityTypeId(quantity_type),
+                value,
+                datetime,
+            ));
+        }
+    }
+
+    let mut quantity_type_ids: Vec<i32> = new_raw_measurements
+        .iter()
+        .map(|raw_measurement| raw_measurement.quantity_type_id)
+        .collect();
+    quantity_type_ids.sort();
+    quantity_type_ids.dedup();
+    let quantity_type_ids: HashSet<i32> = models::QuantityType::by_ids(conn, quantity_type_ids)
+        .map_err(|_| Error::Internal)?
+        .into_iter()
+        .map(|quantity_type| quantity_type.id)
+        .collect();
+    new_raw_measurements.retain(|raw_measurement| {
+        let known = quantity_type_ids.contains(&raw_measurement.quantity_type_id);
+        if !known {
+            debug!(
+                "dropping raw measurement {}: unknown quantity type {}",
+                raw_measurement.id, raw_measurement.quantity_type_id
+            );
+        }
+        known
+    });
+
+    Ok(insert_raw_measurements(conn, &new_raw_measurements))
+}
+
+/// Insert raw measurements in a single query. If that fails, e.g. because one of the raw
+/// measurements violates a constraint, they are inserted one by one instead, such that only the
+/// offending raw measurements are dropped.
+///
+/// Returns the amount of inserted raw measurements.
+pub(super) fn insert_raw_measurements(
+    conn: &PgConnection,
+    new_raw_measurements: &[models::NewRawMeasurement],
+) -> usize {
+    // Insert in a (nested) transaction, such that a failed insert does not abort a surrounding
+    // transaction.
+    let insert = |batch: &[models::NewRawMeasurement]| {
+        conn.transaction(|| models::NewRawMeasurement::create_batch(conn, batch))
+    };
+
+    match insert(new_raw_measurements) {
+        Ok(stored) => return stored,
+        Err(err) => debug!(
+            "could not store batch of {} raw measurements, storing them one by one: {:?}",
+            new_raw_measurements.len(),
+            err
+        ),
+    }
+
+    let mut stored = 0;
+    for raw_measurement in new_raw_measurements {
+        match insert(std::slice::from_ref(raw_measurement)) {
+            Ok(inserted) => stored += inserted,
+            Err(err) => debug!("dropping raw measurement {}: {:?}", raw_measurement.id, err),
+        }
+    }
+    stored
+}
+
+async fn flush(pg: &PgPool, buffer: &mut Vec<astroplant_mqtt::RawMeasurement>) {
+    if buffer.is_empty() {
+        return;
+    }
+
+    let raw_measurements = std::mem::replace(buffer, Vec::with_capacity(BATCH_SIZE));
+    let amount = raw_measurements.len();
+
+    let conn = match pg.clone().get().await {
+        Ok(conn) => conn,
+        Err(_) => {
+            warn!(
+                "dropping {} raw measurements: could not get a database connection",
+                amount
+            );
+            return;
+        }
+    };
+
+    match helpers::threadpool(move || store_raw_measurements(&conn, raw_measurements)).await {
+        Ok(stored) => trace!("stored {} of {} raw measurements", stored, amount),
+        Err(err) => warn!("could not store {} raw measurements: {:?}", amount, err),
+    }
+}
+
+/// Write the received raw measurements to the database. Measurements are buffered, and are
+/// written when the buffer is full or when the oldest buffered measurement has waited for the
+/// flush interval, whichever comes first.
+pub async fn run_writer(
+    pg: PgPool,
+    mut raw_measurement_receiver: mpsc::Receiver<astroplant_mqtt::RawMeasurement>,
+) {
+    let mut buffer = Vec::with_capacity(BATCH_SIZE);
+    let mut flush_at: Option<Instant> = None;
+
+    loop {
+        let received = match flush_at {
+            Some(deadline) => {
+                match tokio::time::timeout_at(deadline, raw_measurement_receiver.next()).await {
+                    Ok(received) => received,
+                    Err(_) => {
+                        flush(&pg, &mut buffer).await;
+                        flush_at = None;
+                        continue;
+                    }
+                }
+            }
+            None => raw_measurement_receiver.next().await,
+        };
+
+        match received {
+            Some(raw_measurement) => {
+                if buffer.is_empty() {
+                    flush_at = Some(Instant::now() + FLUSH_INTERVAL);
+                }
+                buffer.push(raw_measurement);
+
+                if buffer.len() >= BATCH_SIZE {
+                    flush(&pg, &mut buffer).await;
+                    flush_at = None;
+                }
+            }
+            None => {
+                flush(&pg, &mut buffer).await;
+                break;
+            }
+        }
+    }
+}
+
+/// Periodically delete raw measurements older than the retention window.
+pub async fn run_pruner(pg: PgPool, retention: chrono::Duration) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let before = chrono::Utc::now() - retention;
+        let conn = match pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not prune raw measurements: could not get a database connection");
+                continue;
+            }
+        };
+
+        let pruned = helpers::threadpool(move || {
+            let mut pruned = 0;
+            loop {
+                let deleted =
+                    models::RawMeasurement::delete_before(&conn, before, PRUNE_BATCH_SIZE)?;
+                pruned += deleted;
+                if (deleted as i64) < PRUNE_BATCH_SIZE {
+                    return Ok::<_, diesel::result::Error>(pruned);
+                }
+            }
+        })
+        .await;
+
+        match pruned {
+            Ok(pruned) => debug!("pruned {} raw measurements made before {}", pruned, before),
+            Err(err) => warn!("could not prune raw measurements: {:?}", err),
+        }
+    }
+}
@@ -12,6 +12,7 @@ pub const NOT_FOUND: Problem = Problem::Generic(GenericProblem::NotFound);
 pub const INTERNAL_SERVER_ERROR: Problem = Problem::Generic(GenericProblem::InternalServerError);
 pub const FORBIDDEN: Problem = Problem::Generic(GenericProblem::Forbidden);
 pub const BAD_REQUEST: Problem = Problem::Generic(GenericProblem::BadRequest);
+pub const UNPROCESSABLE_ENTITY: Problem = Problem::Generic(GenericProblem::UnprocessableEntity);
 
 pub type AppResult<T> = Result<T, Problem>;
 
@@ -24,6 +25,10 @@ pub enum Problem {
     #[serde(rename = "/probs/rate-limit")]
     RateLimit(RateLimitError),
 
+    #[serde(rename = "/probs/login-throttled")]
+    #[serde(rename_all = "camelCase")]
+    LoginThrottled { retry_after_millis: u64 },
+
     #[serde(rename = "/probs/authorization-header")]
     AuthorizationHeader {
         category: AccessTokenProblemCategory,
@@ -61,7 +66,9 @@ impl Problem {
             Generic(Forbidden) => warp::http::StatusCode::FORBIDDEN,
             Generic(MethodNotAllowed) => warp::http::StatusCode::METHOD_NOT_ALLOWED,
             Generic(BadRequest) => warp::http::StatusCode::BAD_REQUEST,
+            Generic(UnprocessableEntity) => warp::http::StatusCode::UNPROCESSABLE_ENTITY,
             RateLimit(_) => warp::http::StatusCode::TOO_MANY_REQUESTS,
+            LoginThrottled { .. } => warp::http::StatusCode::TOO_MANY_REQUESTS,
             AuthorizationHeader { .. } => warp::http::StatusCode::UNAUTHORIZED,
             PayloadTooLarge { .. } => warp::http::StatusCode::PAYLOAD_TOO_LARGE,
             InvalidJson { .. } => warp::http::StatusCode::BAD_REQUEST,
@@ -122,6 +129,9 @@ pub enum GenericProblem {
 
     #[serde(rename = "Bad Request")]
     BadRequest,
+
+    #[serde(rename = "Unprocessable Entity")]
+    UnprocessableEntity,
 }
 
 #[derive(Debug, Serialize)]
@@ -157,6 +167,14 @@ impl<'a> From<&'a Problem> for DescriptiveProblem<'a> {
                 )
             }
 
+            LoginThrottled { retry_after_millis } => (
+                Some("Too many failed attempts to log in to this account.".to_owned()),
+                Some(format!(
+                    "Logging in to this account is blocked for {} more seconds.",
+                    (retry_after_millis + 999) / 1000
+                )),
+            ),
+
             AuthorizationHeader { category } => {
                 use AccessTokenProblemCategory::*;
                 match category {
@@ -172,6 +190,10 @@ impl<'a> From<&'a Problem> for DescriptiveProblem<'a> {
                         Some("Your request access token was expired.".to_owned()),
                         None,
                     ),
+                    Revoked => (
+                        Some("Your request access token was revoked.".to_owned()),
+                        None,
+                    ),
                 }
             }
 
@@ -219,6 +241,7 @@ pub enum AccessTokenProblemCategory {
     Missing,
     Malformed,
     Expired,
+    Revoked,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -292,6 +315,7 @@ pub enum InvalidParameterReason {
     },
     AlreadyExists,
     AlreadyActivated,
+    LastSuperMember,
     InvalidToken {
         category: AccessTokenProblemCategory,
     },
@@ -1,26 +1,44 @@
 use crate::problem::{Problem, RateLimitError};
 
 use ratelimit_meter::{algorithms::NonConformance, KeyedRateLimiter};
-use std::net::SocketAddr;
+use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
 use warp::{Filter, Rejection};
 
-/// Create a filter that gates a request behind a leaky bucket rate limiter.
+/// The number of requests allowed per remote IP address in a time period.
+#[derive(Copy, Clone, Debug)]
+pub struct Limit {
+    pub requests: std::num::NonZeroU32,
+    pub per: Duration,
+}
+
+impl Default for Limit {
+    /// The limit applied to all requests: 20 requests per 10 seconds.
+    fn default() -> Self {
+        Self {
+            requests: std::num::NonZeroU32::new(20u32).unwrap(),
+            per: Duration::from_secs(10),
+        }
+    }
+}
+
+/// Create a filter that gates a request behind a leaky bucket rate limiter. Requests are limited
+/// per IP address, such that clients cannot get a fresh bucket by opening a new connection.
 ///
 /// # Panics
 /// Panics if it is used with a transport not using socket addresses.
-pub fn leaky_bucket() -> impl Filter<Extract = (), Error = Rejection> + Clone {
-    let limiter = Arc::new(Mutex::new(KeyedRateLimiter::<SocketAddr>::new(
-        std::num::NonZeroU32::new(20u32).unwrap(),
-        Duration::from_secs(10),
+pub fn leaky_bucket(limit: Limit) -> impl Filter<Extract = (), Error = Rejection> + Clone {
+    let limiter = Arc::new(Mutex::new(KeyedRateLimiter::<IpAddr>::new(
+        limit.requests,
+        limit.per,
     )));
 
     warp::addr::remote()
         .and_then(move |addr: Option<SocketAddr>| {
             let addr = addr.expect("Must be used with a transport utilizing socket addresses.");
             let mut limiter = limiter.lock().unwrap();
-            let res = match limiter.check(addr) {
+            let res = match limiter.check(addr.ip()) {
                 Ok(_) => Ok(()),
                 Err(neg) => Err(warp::reject::custom(Problem::RateLimit(RateLimitError {
                     wait_time_millis: neg.wait_time_from(Instant::now()).as_millis() as u64,
@@ -30,3 +48,28 @@ pub fn leaky_bucket() -> impl Filter<Extract = (), Error = Rejection> + Clone {
         })
         .untuple_one()
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limit_per_ip_address() {
+        futures::executor::block_on(async {
+            let filter = leaky_bucket(Limit {
+                requests: std::num::NonZeroU32::new(1u32).unwrap(),
+                per: Duration::from_secs(3600),
+            });
+            let request = |addr: &str| {
+                warp::test::request()
+                    .remote_addr(addr.parse().unwrap())
+                    .matches(&filter)
+            };
+
+            assert!(request("192.0.2.1:50000").await);
+            // Another connection from the same address shares the bucket.
+            assert!(!request("192.0.2.1:50001").await);
+            assert!(request("192.0.2.2:50000").await);
+        })
+    }
+}
@@ -5,6 +5,10 @@ use std::collections::HashMap;
 use std::pin::Pin;
 use warp::http::StatusCode;
 
+/// A stream of raw response data.
+pub type ByteStream =
+    Pin<Box<dyn Stream<Item = std::result::Result<Bytes, std::io::Error>> + Send + 'static>>;
+
 pub enum ResponseValue {
     Serializable(Box<dyn ErasedSerialize + Send>),
     Data {
@@ -13,9 +17,7 @@ pub enum ResponseValue {
     },
     Stream {
         media_type: String,
-        stream: Pin<
-            Box<dyn Stream<Item = std::result::Result<Bytes, std::io::Error>> + Send + 'static>,
-        >,
+        stream: ByteStream,
     },
 }
 
@@ -153,13 +155,7 @@ impl ResponseBuilder {
 
     /// Build the response with the given raw data stream.
     #[allow(dead_code)]
-    pub fn stream(
-        mut self,
-        media_type: String,
-        stream: Pin<
-            Box<dyn Stream<Item = std::result::Result<Bytes, std::io::Error>> + Send + 'static>,
-        >,
-    ) -> Response {
+    pub fn stream(mut self, media_type: String, stream: ByteStream) -> Response {
         self.process();
         Response {
             value: Some(ResponseValue::Stream { media_type, stream }),
@@ -68,6 +68,168 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `alert_rules` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    alert_rules (id) {
+        /// The `id` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `kit_id` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `peripheral_id` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        peripheral_id -> Int4,
+        /// The `quantity_type_id` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        quantity_type_id -> Int4,
+        /// The `condition` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        condition -> Varchar,
+        /// The `threshold` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Float8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        threshold -> Float8,
+        /// The `duration_seconds` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        duration_seconds -> Int4,
+        /// The `description` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        description -> Nullable<Varchar>,
+        /// The `webhook_url` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        webhook_url -> Nullable<Varchar>,
+        /// The `enabled` column of the `alert_rules` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        enabled -> Bool,
+    }
+}
+
+table! {
+    /// Representation of the `alerts` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    alerts (id) {
+        /// The `id` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `alert_rule_id` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        alert_rule_id -> Int4,
+        /// The `kit_id` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `datetime_start` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_start -> Timestamptz,
+        /// The `datetime_fired` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_fired -> Timestamptz,
+        /// The `datetime_resolved` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_resolved -> Nullable<Timestamptz>,
+        /// The `value` column of the `alerts` table.
+        ///
+        /// Its SQL type is `Float8`.
+        ///
+        /// (Automatically generated by Diesel.)
+        value -> Float8,
+    }
+}
+
+table! {
+    /// Representation of the `email_verification_tokens` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    email_verification_tokens (id) {
+        /// The `id` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `user_id` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `email_address` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        email_address -> Varchar,
+        /// The `token_hash` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        token_hash -> Varchar,
+        /// The `datetime_created` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `datetime_expires` column of the `email_verification_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_expires -> Timestamptz,
+    }
+}
+
 table! {
     /// Representation of the `kit_configurations` table.
     ///
@@ -124,6 +286,74 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `kit_invitations` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    kit_invitations (id) {
+        /// The `id` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `kit_id` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `code` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        code -> Varchar,
+        /// The `created_by` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        created_by -> Nullable<Int4>,
+        /// The `access_super` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        access_super -> Bool,
+        /// The `access_configure` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        access_configure -> Bool,
+        /// The `max_uses` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        max_uses -> Int4,
+        /// The `uses` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        uses -> Int4,
+        /// The `datetime_created` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `datetime_expires` column of the `kit_invitations` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_expires -> Timestamptz,
+    }
+}
+
 table! {
     /// Representation of the `kit_memberships` table.
     ///
@@ -227,6 +457,44 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         privacy_show_on_map -> Bool,
+        /// The `last_seen` column of the `kits` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_seen -> Nullable<Timestamptz>,
+    }
+}
+
+table! {
+    /// Representation of the `login_failures` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    login_failures (username) {
+        /// The `username` column of the `login_failures` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        username -> Varchar,
+        /// The `failures` column of the `login_failures` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        failures -> Int4,
+        /// The `datetime_last_attempt` column of the `login_failures` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_last_attempt -> Timestamptz,
+        /// The `datetime_blocked_until` column of the `login_failures` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_blocked_until -> Nullable<Timestamptz>,
     }
 }
 
@@ -293,6 +561,100 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `password_reset_tokens` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    password_reset_tokens (id) {
+        /// The `id` column of the `password_reset_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `user_id` column of the `password_reset_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `token_hash` column of the `password_reset_tokens` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        token_hash -> Varchar,
+        /// The `datetime_created` column of the `password_reset_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `datetime_expires` column of the `password_reset_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_expires -> Timestamptz,
+    }
+}
+
+table! {
+    /// Representation of the `personal_access_tokens` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    personal_access_tokens (id) {
+        /// The `id` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `user_id` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `name` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        name -> Varchar,
+        /// The `kit_ids` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Array<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_ids -> Array<Int4>,
+        /// The `actions` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Array<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        actions -> Array<Varchar>,
+        /// The `datetime_created` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `datetime_last_used` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_last_used -> Nullable<Timestamptz>,
+        /// The `datetime_expires` column of the `personal_access_tokens` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_expires -> Timestamptz,
+    }
+}
+
 table! {
     /// Representation of the `peripheral_definition_expected_quantity_types` table.
     ///
@@ -507,6 +869,114 @@ table! {
     }
 }
 
+table! {
+    /// Representation of the `recovery_codes` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    recovery_codes (id) {
+        /// The `id` column of the `recovery_codes` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `user_id` column of the `recovery_codes` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `code_hash` column of the `recovery_codes` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        code_hash -> Varchar,
+    }
+}
+
+table! {
+    /// Representation of the `sessions` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    sessions (id) {
+        /// The `id` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Uuid`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Uuid,
+        /// The `user_id` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `user_agent` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_agent -> Nullable<Varchar>,
+        /// The `datetime_created` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `datetime_last_used` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_last_used -> Timestamptz,
+        /// The `datetime_expires` column of the `sessions` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_expires -> Timestamptz,
+    }
+}
+
+table! {
+    /// Representation of the `totp_secrets` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    totp_secrets (user_id) {
+        /// The `user_id` column of the `totp_secrets` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        user_id -> Int4,
+        /// The `secret` column of the `totp_secrets` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        secret -> Varchar,
+        /// The `enabled` column of the `totp_secrets` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        enabled -> Bool,
+        /// The `datetime_created` column of the `totp_secrets` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `last_used_step` column of the `totp_secrets` table.
+        ///
+        /// Its SQL type is `Nullable<Int8>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_used_step -> Nullable<Int8>,
+    }
+}
+
 table! {
     /// Representation of the `users` table.
     ///
@@ -554,6 +1024,130 @@ table! {
         ///
         /// (Automatically generated by Diesel.)
         gravatar_alternative -> Varchar,
+        /// The `email_verified` column of the `users` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        email_verified -> Bool,
+    }
+}
+
+table! {
+    /// Representation of the `webhook_deliveries` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    webhook_deliveries (id) {
+        /// The `id` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `webhook_id` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        webhook_id -> Int4,
+        /// The `event` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        event -> Varchar,
+        /// The `payload` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Jsonb`.
+        ///
+        /// (Automatically generated by Diesel.)
+        payload -> Jsonb,
+        /// The `datetime_created` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
+        /// The `attempts` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        attempts -> Int4,
+        /// The `datetime_next_attempt` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_next_attempt -> Nullable<Timestamptz>,
+        /// The `datetime_delivered` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Timestamptz>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_delivered -> Nullable<Timestamptz>,
+        /// The `last_status_code` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Int4>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_status_code -> Nullable<Int4>,
+        /// The `last_error` column of the `webhook_deliveries` table.
+        ///
+        /// Its SQL type is `Nullable<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        last_error -> Nullable<Varchar>,
+    }
+}
+
+table! {
+    /// Representation of the `webhooks` table.
+    ///
+    /// (Automatically generated by Diesel.)
+    webhooks (id) {
+        /// The `id` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        id -> Int4,
+        /// The `kit_id` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Int4`.
+        ///
+        /// (Automatically generated by Diesel.)
+        kit_id -> Int4,
+        /// The `url` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        url -> Varchar,
+        /// The `secret` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Varchar`.
+        ///
+        /// (Automatically generated by Diesel.)
+        secret -> Varchar,
+        /// The `events` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Array<Varchar>`.
+        ///
+        /// (Automatically generated by Diesel.)
+        events -> Array<Varchar>,
+        /// The `enabled` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Bool`.
+        ///
+        /// (Automatically generated by Diesel.)
+        enabled -> Bool,
+        /// The `datetime_created` column of the `webhooks` table.
+        ///
+        /// Its SQL type is `Timestamptz`.
+        ///
+        /// (Automatically generated by Diesel.)
+        datetime_created -> Timestamptz,
     }
 }
 
@@ -561,12 +1155,22 @@ joinable!(aggregate_measurements -> kit_configurations (kit_configuration_id));
 joinable!(aggregate_measurements -> kits (kit_id));
 joinable!(aggregate_measurements -> peripherals (peripheral_id));
 joinable!(aggregate_measurements -> quantity_types (quantity_type_id));
+joinable!(alert_rules -> kits (kit_id));
+joinable!(alert_rules -> peripherals (peripheral_id));
+joinable!(alert_rules -> quantity_types (quantity_type_id));
+joinable!(alerts -> alert_rules (alert_rule_id));
+joinable!(alerts -> kits (kit_id));
+joinable!(email_verification_tokens -> users (user_id));
 joinable!(kit_configurations -> kits (kit_id));
+joinable!(kit_invitations -> kits (kit_id));
+joinable!(kit_invitations -> users (created_by));
 joinable!(kit_memberships -> kits (kit_id));
 joinable!(kit_memberships -> users (user_id));
 joinable!(media -> kit_configurations (kit_configuration_id));
 joinable!(media -> kits (kit_id));
 joinable!(media -> peripherals (peripheral_id));
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(personal_access_tokens -> users (user_id));
 joinable!(peripheral_definition_expected_quantity_types -> peripheral_definitions (peripheral_definition_id));
 joinable!(peripheral_definition_expected_quantity_types -> quantity_types (quantity_type_id));
 joinable!(peripherals -> kit_configurations (kit_configuration_id));
@@ -576,18 +1180,35 @@ joinable!(raw_measurements -> kit_configurations (kit_configuration_id));
 joinable!(raw_measurements -> kits (kit_id));
 joinable!(raw_measurements -> peripherals (peripheral_id));
 joinable!(raw_measurements -> quantity_types (quantity_type_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(sessions -> users (user_id));
+joinable!(totp_secrets -> users (user_id));
+joinable!(webhook_deliveries -> webhooks (webhook_id));
+joinable!(webhooks -> kits (kit_id));
 
 allow_tables_to_appear_in_same_query!(
     aggregate_measurements,
     alembic_version,
+    alert_rules,
+    alerts,
+    email_verification_tokens,
     kit_configurations,
+    kit_invitations,
     kit_memberships,
     kits,
+    login_failures,
     media,
+    password_reset_tokens,
+    personal_access_tokens,
     peripheral_definition_expected_quantity_types,
     peripheral_definitions,
     peripherals,
     quantity_types,
     raw_measurements,
+    recovery_codes,
+    sessions,
+    totp_secrets,
     users,
+    webhook_deliveries,
+    webhooks,
 );
@@ -0,0 +1,86 @@
+//! Support for tests using the database.
+
+use crate::models;
+use crate::schema::{peripheral_definitions, quantity_types};
+
+use diesel::pg::PgConnection;
+use diesel::prelude::*;
+
+pub struct Fixture {
+    pub kit: models::Kit,
+    pub configuration: models::KitConfiguration,
+    pub peripheral: models::Peripheral,
+    pub peripheral_definition_id: models::PeripheralDefinitionId,
+    pub quantity_type_id: models::QuantityTypeId,
+}
+
+/// Connect to the database given by `DATABASE_URL`. Everything done through the connection is
+/// rolled back once it is dropped.
+pub fn connection() -> PgConnection {
+    let database_url =
+        std::env::var("DATABASE_URL").unwrap_or(crate::DEFAULT_DATABASE_URL.to_owned());
+    let conn = PgConnection::establish(&database_url).unwrap();
+    conn.begin_test_transaction().unwrap();
+    conn
+}
+
+/// Create a kit with an active configuration holding a single peripheral.
+pub fn create_fixture(conn: &PgConnection) -> Fixture {
+    let (new_kit, _) =
+        models::NewKit::new_with_generated_password(None, None, None, None, false, false);
+    let kit = new_kit.create(conn).unwrap();
+
+    let configuration = models::NewKitConfiguration::new(kit.get_id(), None)
+        .create(conn)
+        .unwrap();
+    let configuration = models::UpdateKitConfiguration {
+        id: configuration.id,
+        description: None,
+        controller_symbol_location: None,
+        controller_symbol: None,
+        control_rules: None,
+        active: Some(true),
+        never_used: Some(false),
+    }
+    .update(conn)
+    .unwrap();
+
+    let peripheral_definition_id: i32 = diesel::insert_into(peripheral_definitions::table)
+        .values((
+            peripheral_definitions::name.eq("Test sensor"),
+            peripheral_definitions::symbol_location.eq("astroplant_kit.peripherals.test"),
+            peripheral_definitions::symbol.eq("TestSensor"),
+            peripheral_definitions::configuration_schema.eq(serde_json::json!({})),
+        ))
+        .returning(peripheral_definitions::id)
+        .get_result(conn)
+        .unwrap();
+
+    let quantity_type_id: i32 = diesel::insert_into(quantity_types::table)
+        .values((
+            quantity_types::physical_quantity.eq("Temperature"),
+            quantity_types::physical_unit.eq("Degrees Celsius"),
+            quantity_types::physical_unit_symbol.eq("°C"),
+        ))
+        .returning(quantity_types::id)
+        .get_result(conn)
+        .unwrap();
+
+    let peripheral = models::NewPeripheral::new(
+        kit.get_id(),
+        configuration.get_id(),
+        models::PeripheralDefinitionId(peripheral_definition_id),
+        "Sensor".to_owned(),
+        serde_json::json!({}),
+    )
+    .create(conn)
+    .unwrap();
+
+    Fixture {
+        kit,
+        configuration,
+        peripheral,
+        peripheral_definition_id: models::PeripheralDefinitionId(peripheral_definition_id),
+        quantity_type_id: models::QuantityTypeId(quantity_type_id),
+    }
+}
@@ -1,3 +1,6 @@
+pub mod outbound;
+pub mod zip;
+
 use serde::{Deserialize, Deserializer};
 
 pub fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
@@ -0,0 +1,290 @@
+//! Requests to user-provided URLs, such as webhooks. Such requests must not let users reach the
+//! server's own network: the hosts of the URLs must resolve only to public addresses. This is
+//! checked when a URL is given, and again when connecting to it, such that a host cannot resolve
+//! to a public address for the check and to a non-public one for the request.
+
+use futures::future::{BoxFuture, FutureExt};
+use hyper::client::connect::dns::Name;
+use hyper::client::HttpConnector;
+use hyper::{Body, Request, StatusCode, Uri};
+use hyper_tls::HttpsConnector;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, ToSocketAddrs};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use crate::helpers;
+
+/// The maximum time an outbound request may take.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An HTTP client for outbound requests, connecting only to public addresses. It does not follow
+/// redirects, as those could lead to non-public addresses.
+pub type Client = hyper::Client<HttpsConnector<HttpConnector<PublicResolver>>>;
+
+#[derive(Debug)]
+pub enum UrlError {
+    /// The URL could not be parsed, or is not an HTTP(S) URL.
+    Invalid,
+    /// The URL's host could not be resolved.
+    Unresolvable,
+    /// The URL's host resolves to an address that is not public.
+    NotPublic(IpAddr),
+}
+
+impl std::fmt::Display for UrlError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            UrlError::Invalid => write!(f, "invalid URL"),
+            UrlError::Unresolvable => write!(f, "host could not be resolved"),
+            UrlError::NotPublic(address) => write!(f, "host resolves to non-public {}", address),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Url(UrlError),
+    Request(hyper::Error),
+    TimedOut,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::Url(err) => write!(f, "{}", err),
+            Error::Request(err) => write!(f, "{}", err),
+            Error::TimedOut => write!(f, "request timed out"),
+        }
+    }
+}
+
+/// Resolves host names, failing if any of a host's addresses is not public.
+#[derive(Copy, Clone, Debug)]
+pub struct PublicResolver;
+
+impl tower_service::Service<Name> for PublicResolver {
+    type Response = std::vec::IntoIter<IpAddr>;
+    type Error = std::io::Error;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let host = name.as_str().to_owned();
+        async move {
+            resolve_public(host, 0)
+                .await
+                .map(|addresses| addresses.into_iter())
+                .map_err(|err| {
+                    let kind = match err {
+                        UrlError::Invalid => std::io::ErrorKind::InvalidInput,
+                        UrlError::Unresolvable => std::io::ErrorKind::NotFound,
+                        UrlError::NotPublic(_) => std::io::ErrorKind::PermissionDenied,
+                    };
+                    std::io::Error::new(kind, err.to_string())
+                })
+        }
+        .boxed()
+    }
+}
+
+/// Create an HTTP client for outbound requests.
+pub fn client() -> Client {
+    let mut http = HttpConnector::new_with_resolver(PublicResolver);
+    http.enforce_http(false);
+    http.set_connect_timeout(Some(TIMEOUT));
+    let tls = native_tls::TlsConnector::new().expect("could not create TLS connector");
+    hyper::Client::builder().build(HttpsConnector::from((http, tls.into())))
+}
+
+/// Resolve a host, making sure all its addresses are public.
+async fn resolve_public(host: String, port: u16) -> Result<Vec<IpAddr>, UrlError> {
+    let addresses: Vec<IpAddr> = helpers::threadpool(move || {
+        (host.as_str(), port)
+            .to_socket_addrs()
+            .map(|addresses| addresses.map(|address| address.ip()).collect())
+    })
+    .await
+    .map_err(|_| UrlError::Unresolvable)?;
+
+    if addresses.is_empty() {
+        return Err(UrlError::Unresolvable);
+    }
+    if let Some(address) = addresses.iter().find(|address| !is_public(**address)) {
+        return Err(UrlError::NotPublic(*address));
+    }
+
+    Ok(addresses)
+}
+
+/// Check an HTTP(S) URL, resolving its host and making sure all its addresses are public.
+pub async fn check_url(url: &str) -> Result<Uri, UrlError> {
+    let uri: Uri = url.parse().map_err(|_| UrlError::Invalid)?;
+    let default_port = match uri.scheme_str() {
+        Some("https") => 443,
+        Some("http") => 80,
+        _ => return Err(UrlError::Invalid),
+    };
+    let host = uri
+        .host()
+        .ok_or(UrlError::Invalid)?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_owned();
+    let port = uri.port_u16().unwrap_or(default_port);
+
+    resolve_public(host, port).await?;
+
+    Ok(uri)
+}
+
+/// POST a JSON body to a URL, returning the response's status. The URL is checked first, as
+/// addresses given literally in the URL are not resolved when connecting.
+pub async fn post_json(
+    client: &Client,
+    url: &str,
+    headers: &[(&'static str, String)],
+    body: Vec<u8>,
+) -> Result<StatusCode, Error> {
+    let uri = check_url(url).await.map_err(Error::Url)?;
+
+    let mut request = Request::post(uri).header("Content-Type", "application/json");
+    for (name, value) in headers {
+        request = request.header(*name, value.as_str());
+    }
+    let request = request
+        .body(Body::from(body))
+        .expect("request parts were checked");
+
+    match tokio::time::timeout(TIMEOUT, client.request(request)).await {
+        Ok(Ok(response)) => Ok(response.status()),
+        Ok(Err(err)) => Err(Error::Request(err)),
+        Err(_) => Err(Error::TimedOut),
+    }
+}
+
+/// Whether the address is publicly routable.
+fn is_public(address: IpAddr) -> bool {
+    match address {
+        IpAddr::V4(address) => is_public_v4(address),
+        IpAddr::V6(address) => is_public_v6(address),
+    }
+}
+
+fn is_public_v4(address: Ipv4Addr) -> bool {
+    let octets = address.octets();
+    !(address.is_private()
+        || address.is_loopback()
+        || address.is_link_local()
+        || address.is_unspecified()
+        || address.is_broadcast()
+        || address.is_documentation()
+        || address.is_multicast()
+        // "This network", 0.0.0.0/8.
+        || octets[0] == 0
+        // Shared address space, 100.64.0.0/10.
+        || (octets[0] == 100 && octets[1] & 0xc0 == 64)
+        // IETF protocol assignments, 192.0.0.0/24.
+        || (octets[0] == 192 && octets[1] == 0 && octets[2] == 0)
+        // Benchmarking, 198.18.0.0/15.
+        || (octets[0] == 198 && octets[1] & 0xfe == 18)
+        // Reserved, 240.0.0.0/4.
+        || octets[0] & 0xf0 == 240)
+}
+
+fn is_public_v6(address: Ipv6Addr) -> bool {
+    let segments = address.segments();
+    let embedded_v4 = |high: u16, low: u16| {
+        Ipv4Addr::new((high >> 8) as u8, high as u8, (low >> 8) as u8, low as u8)
+    };
+
+    // IPv4-mapped addresses, ::ffff:0:0/96, and NAT64 addresses, 64:ff9b::/96, embed an IPv4
+    // address in their last 32 bits.
+    if segments[..5] == [0, 0, 0, 0, 0] && segments[5] == 0xffff
+        || segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0]
+    {
+        return is_public_v4(embedded_v4(segments[6], segments[7]));
+    }
+    // 6to4 addresses, 2002::/16, embed an IPv4 address in the 32 bits following the prefix.
+    if segments[0] == 0x2002 {
+        return is_public_v4(embedded_v4(segments[1], segments[2]));
+    }
+
+    !(address.is_loopback()
+        || address.is_unspecified()
+        || address.is_multicast()
+        // Unique local, fc00::/7.
+        || segments[0] & 0xfe00 == 0xfc00
+        // Link-local, fe80::/10.
+        || segments[0] & 0xffc0 == 0xfe80
+        // Documentation, 2001:db8::/32.
+        || (segments[0] == 0x2001 && segments[1] == 0x0db8))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn public(address: &str) -> bool {
+        is_public(address.parse().unwrap())
+    }
+
+    #[test]
+    fn non_public_addresses_are_rejected() {
+        for address in &[
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "0.0.0.0",
+            "100.64.0.1",
+            "255.255.255.255",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "::ffff:169.254.169.254",
+            "64:ff9b::7f00:1",
+            "64:ff9b::a9fe:a9fe",
+            "2002:7f00:1::1",
+            "2002:a9fe:a9fe::1",
+        ] {
+            assert!(!public(address), "{} must not be public", address);
+        }
+    }
+
+    #[test]
+    fn public_addresses_are_accepted() {
+        for address in &[
+            "93.184.216.34",
+            "1.1.1.1",
+            "2606:4700::1111",
+            "::ffff:1.1.1.1",
+            "64:ff9b::101:101",
+            "2002:101:101::1",
+        ] {
+            assert!(public(address), "{} must be public", address);
+        }
+    }
+
+    #[tokio::test]
+    async fn connecting_to_non_public_hosts_fails() {
+        let client = client();
+        for url in &["http://localhost/", "http://127.0.0.1/", "http://[::1]/"] {
+            match post_json(&client, url, &[], vec![]).await {
+                Err(Error::Url(UrlError::NotPublic(_))) => {}
+                result => panic!("{} must not be reachable: {:?}", url, result),
+            }
+        }
+
+        // The resolver checks the addresses itself, when connecting.
+        let request = Request::get("http://localhost/")
+            .body(Body::empty())
+            .unwrap();
+        assert!(client.request(request).await.is_err());
+    }
+}
@@ -0,0 +1,272 @@
+//! A minimal streaming zip archive writer. Files are stored without compression, and their sizes
+//! and checksums are written in data descriptors following the file contents, such that archives
+//! can be streamed without knowing the file contents in advance.
+//!
+//! Zip64 is not supported: archives (and thus each file in them) must be smaller than 4 GiB, and
+//! can hold at most 65535 files.
+
+use bytes::Bytes;
+use chrono::{DateTime, Datelike, Timelike, Utc};
+
+/// The maximum size of an archive in bytes.
+pub const MAX_ARCHIVE_SIZE: u64 = u32::MAX as u64;
+
+/// The maximum amount of files in an archive.
+pub const MAX_FILES: usize = u16::MAX as usize;
+
+const VERSION: u16 = 20;
+/// The sizes and checksum follow the file data (bit 3), and the file name is UTF-8 (bit 11).
+const FLAGS: u16 = (1 << 3) | (1 << 11);
+
+/// The CRC-32 checksum (as used by zip and gzip).
+pub struct Crc32(u32);
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self(!0)
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut crc = self.0;
+        for &byte in data {
+            crc ^= u32::from(byte);
+            for _ in 0..8 {
+                crc = if crc & 1 == 1 {
+                    (crc >> 1) ^ 0xedb8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        self.0 = crc;
+    }
+
+    pub fn finish(&self) -> u32 {
+        !self.0
+    }
+}
+
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+struct Entry {
+    name: String,
+    time: u16,
+    date: u16,
+    crc32: u32,
+    size: u32,
+    offset: u32,
+}
+
+struct CurrentFile {
+    entry: Entry,
+    crc32: Crc32,
+    size: u64,
+}
+
+fn too_large() -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::Other, "zip archive too large")
+}
+
+/// Convert a datetime to MS-DOS time and date. Datetimes before 1980 are clamped.
+fn dos_datetime(datetime: DateTime<Utc>) -> (u16, u16) {
+    if datetime.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time =
+        ((datetime.hour() << 11) | (datetime.minute() << 5) | (datetime.second() / 2)) as u16;
+    let date = ((((datetime.year() - 1980) as u32) << 9) | (datetime.month() << 5) | datetime.day())
+        as u16;
+    (time, date)
+}
+
+/// Writes the structure of a zip archive. The caller is responsible for sending the bytes
+/// returned by this writer, interleaved with the file data passed to `ZipWriter::write`, to the
+/// output.
+#[derive(Default)]
+pub struct ZipWriter {
+    entries: Vec<Entry>,
+    current: Option<CurrentFile>,
+    offset: u64,
+}
+
+impl ZipWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn advance(&mut self, len: usize) -> std::io::Result<()> {
+        self.offset += len as u64;
+        if self.offset > MAX_ARCHIVE_SIZE {
+            Err(too_large())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Start a new file. Returns the file's local header.
+    ///
+    /// # Panics
+    /// Panics if the previous file has not been finished.
+    pub fn start_file(&mut self, name: &str, modified: DateTime<Utc>) -> std::io::Result<Bytes> {
+        assert!(self.current.is_none(), "previous file was not finished");
+        if self.entries.len() >= MAX_FILES {
+            return Err(too_large());
+        }
+
+        let (time, date) = dos_datetime(modified);
+        let entry = Entry {
+            name: name.to_owned(),
+            time,
+            date,
+            crc32: 0,
+            size: 0,
+            offset: self.offset as u32,
+        };
+
+        let mut header = Vec::with_capacity(30 + name.len());
+        header.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        header.extend_from_slice(&VERSION.to_le_bytes());
+        header.extend_from_slice(&FLAGS.to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // Stored (no compression).
+        header.extend_from_slice(&time.to_le_bytes());
+        header.extend_from_slice(&date.to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes()); // CRC-32, in data descriptor.
+        header.extend_from_slice(&0u32.to_le_bytes()); // Compressed size, in data descriptor.
+        header.extend_from_slice(&0u32.to_le_bytes()); // Size, in data descriptor.
+        header.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        header.extend_from_slice(&0u16.to_le_bytes()); // Extra field length.
+        header.extend_from_slice(name.as_bytes());
+        self.advance(header.len())?;
+
+        self.current = Some(CurrentFile {
+            entry,
+            crc32: Crc32::new(),
+            size: 0,
+        });
+        Ok(header.into())
+    }
+
+    /// Record data written to the current file.
+    ///
+    /// # Panics
+    /// Panics if no file has been started.
+    pub fn write(&mut self, data: &[u8]) -> std::io::Result<()> {
+        let current = self.current.as_mut().expect("no file was started");
+        current.crc32.update(data);
+        current.size += data.len() as u64;
+        self.advance(data.len())
+    }
+
+    /// Finish the current file. Returns the file's data descriptor.
+    ///
+    /// # Panics
+    /// Panics if no file has been started.
+    pub fn finish_file(&mut self) -> std::io::Result<Bytes> {
+        let CurrentFile {
+            mut entry,
+            crc32,
+            size,
+        } = self.current.take().expect("no file was started");
+        entry.crc32 = crc32.finish();
+        entry.size = size as u32;
+
+        let mut descriptor = Vec::with_capacity(16);
+        descriptor.extend_from_slice(&0x0807_4b50u32.to_le_bytes());
+        descriptor.extend_from_slice(&entry.crc32.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+        descriptor.extend_from_slice(&entry.size.to_le_bytes());
+        self.advance(descriptor.len())?;
+
+        self.entries.push(entry);
+        Ok(descriptor.into())
+    }
+
+    /// Finish the archive. Returns the central directory.
+    ///
+    /// # Panics
+    /// Panics if the current file has not been finished.
+    pub fn finish(mut self) -> std::io::Result<Bytes> {
+        assert!(self.current.is_none(), "last file was not finished");
+
+        let directory_offset = self.offset as u32;
+        let mut directory = vec![];
+        for entry in &self.entries {
+            directory.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+            directory.extend_from_slice(&VERSION.to_le_bytes()); // Version made by.
+            directory.extend_from_slice(&VERSION.to_le_bytes()); // Version needed to extract.
+            directory.extend_from_slice(&FLAGS.to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // Stored (no compression).
+            directory.extend_from_slice(&entry.time.to_le_bytes());
+            directory.extend_from_slice(&entry.date.to_le_bytes());
+            directory.extend_from_slice(&entry.crc32.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&entry.size.to_le_bytes());
+            directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            directory.extend_from_slice(&0u16.to_le_bytes()); // Extra field length.
+            directory.extend_from_slice(&0u16.to_le_bytes()); // File comment length.
+            directory.extend_from_slice(&0u16.to_le_bytes()); // Disk number start.
+            directory.extend_from_slice(&0u16.to_le_bytes()); // Internal file attributes.
+            directory.extend_from_slice(&0u32.to_le_bytes()); // External file attributes.
+            directory.extend_from_slice(&entry.offset.to_le_bytes());
+            directory.extend_from_slice(entry.name.as_bytes());
+        }
+        let directory_size = directory.len() as u32;
+
+        directory.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes()); // Number of this disk.
+        directory.extend_from_slice(&0u16.to_le_bytes()); // Disk with the central directory.
+        directory.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        directory.extend_from_slice(&(self.entries.len() as u16).to_le_bytes());
+        directory.extend_from_slice(&directory_size.to_le_bytes());
+        directory.extend_from_slice(&directory_offset.to_le_bytes());
+        directory.extend_from_slice(&0u16.to_le_bytes()); // Comment length.
+        self.advance(directory.len())?;
+
+        Ok(directory.into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Crc32, ZipWriter};
+    use chrono::{TimeZone, Utc};
+
+    #[test]
+    fn crc32_check_value() {
+        let mut crc32 = Crc32::new();
+        crc32.update(b"1234");
+        crc32.update(b"56789");
+        assert_eq!(crc32.finish(), 0xcbf4_3926);
+    }
+
+    #[test]
+    fn archive_layout() {
+        let modified = Utc.ymd(2020, 6, 3).and_hms(20, 14, 56);
+        let mut zip = ZipWriter::new();
+        let mut archive = vec![];
+
+        archive.extend_from_slice(&zip.start_file("a.txt", modified).unwrap());
+        zip.write(b"hello").unwrap();
+        archive.extend_from_slice(b"hello");
+        archive.extend_from_slice(&zip.finish_file().unwrap());
+        archive.extend_from_slice(&zip.start_file("empty.txt", modified).unwrap());
+        archive.extend_from_slice(&zip.finish_file().unwrap());
+        archive.extend_from_slice(&zip.finish().unwrap());
+
+        // Local header (30 + 5) + data (5) + descriptor (16) + local header (30 + 9) + descriptor
+        // (16) + central directory entries (46 + 5, 46 + 9) + end of central directory (22).
+        assert_eq!(archive.len(), 35 + 5 + 16 + 39 + 16 + 51 + 55 + 22);
+        assert_eq!(&archive[..4], b"PK\x03\x04");
+
+        let end = &archive[archive.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        // Two entries.
+        assert_eq!(&end[10..12], &2u16.to_le_bytes());
+        // The central directory starts after the files.
+        assert_eq!(&end[16..20], &(35u32 + 5 + 16 + 39 + 16).to_le_bytes());
+    }
+}
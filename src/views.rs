@@ -17,6 +17,7 @@ pub struct Kit {
     pub longitude: Option<f64>,
     pub privacy_public_dashboard: bool,
     pub privacy_show_on_map: bool,
+    pub last_seen: Option<DateTime<Utc>>,
 }
 
 impl From<models::Kit> for Kit {
@@ -32,6 +33,7 @@ impl From<models::Kit> for Kit {
             longitude,
             privacy_public_dashboard,
             privacy_show_on_map,
+            last_seen,
             ..
         } = kit;
         Self {
@@ -43,6 +45,34 @@ impl From<models::Kit> for Kit {
             longitude: longitude.and_then(|l| l.to_f64()),
             privacy_public_dashboard,
             privacy_show_on_map,
+            last_seen,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitStatus {
+    pub kit_serial: String,
+    pub online: bool,
+    pub last_seen: Option<DateTime<Utc>>,
+}
+
+impl KitStatus {
+    /// The status of a kit last seen at the given time. A kit is online if it was seen within
+    /// `offline_after` of `now`.
+    pub fn new(
+        kit_serial: String,
+        last_seen: Option<DateTime<Utc>>,
+        offline_after: chrono::Duration,
+        now: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            kit_serial,
+            online: last_seen
+                .map(|last_seen| now - last_seen < offline_after)
+                .unwrap_or(false),
+            last_seen,
         }
     }
 }
@@ -56,6 +86,7 @@ pub struct FullUser {
     pub email_address: String,
     pub use_email_address_for_gravatar: bool,
     pub gravatar_alternative: String,
+    pub email_verified: bool,
 }
 
 impl From<models::User> for FullUser {
@@ -67,6 +98,7 @@ impl From<models::User> for FullUser {
             email_address,
             use_email_address_for_gravatar,
             gravatar_alternative,
+            email_verified,
             ..
         } = user;
         Self {
@@ -76,6 +108,7 @@ impl From<models::User> for FullUser {
             email_address,
             use_email_address_for_gravatar,
             gravatar_alternative,
+            email_verified,
         }
     }
 }
@@ -110,6 +143,131 @@ impl From<models::User> for User {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Session {
+    pub id: uuid::Uuid,
+    pub user_agent: Option<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_last_used: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl From<models::Session> for Session {
+    fn from(
+        models::Session {
+            id,
+            user_agent,
+            datetime_created,
+            datetime_last_used,
+            datetime_expires,
+            ..
+        }: models::Session,
+    ) -> Self {
+        Self {
+            id,
+            user_agent,
+            datetime_created,
+            datetime_last_used,
+            datetime_expires,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PersonalAccessToken<K> {
+    pub id: i32,
+    pub name: String,
+    pub kits: Vec<K>,
+    pub actions: Vec<String>,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_last_used: Option<DateTime<Utc>>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl<K> PersonalAccessToken<K> {
+    pub fn with_kits<NK>(self, kits: Vec<NK>) -> PersonalAccessToken<NK> {
+        PersonalAccessToken {
+            id: self.id,
+            name: self.name,
+            kits,
+            actions: self.actions,
+            datetime_created: self.datetime_created,
+            datetime_last_used: self.datetime_last_used,
+            datetime_expires: self.datetime_expires,
+        }
+    }
+}
+
+impl From<models::PersonalAccessToken> for PersonalAccessToken<i32> {
+    fn from(
+        models::PersonalAccessToken {
+            id,
+            name,
+            kit_ids,
+            actions,
+            datetime_created,
+            datetime_last_used,
+            datetime_expires,
+            ..
+        }: models::PersonalAccessToken,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            kits: kit_ids,
+            actions,
+            datetime_created,
+            datetime_last_used,
+            datetime_expires,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct KitInvitation {
+    pub id: i32,
+    pub kit_id: i32,
+    pub code: String,
+    pub access_super: bool,
+    pub access_configure: bool,
+    pub max_uses: i32,
+    pub uses: i32,
+    pub datetime_created: DateTime<Utc>,
+    pub datetime_expires: DateTime<Utc>,
+}
+
+impl From<models::KitInvitation> for KitInvitation {
+    fn from(
+        models::KitInvitation {
+            id,
+            kit_id,
+            code,
+            access_super,
+            access_configure,
+            max_uses,
+            uses,
+            datetime_created,
+            datetime_expires,
+            ..
+        }: models::KitInvitation,
+    ) -> Self {
+        Self {
+            id,
+            kit_id,
+            code,
+            access_super,
+            access_configure,
+            max_uses,
+            uses,
+            datetime_created,
+            datetime_expires,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct KitMembership<U, K> {
@@ -429,6 +587,75 @@ impl TryFrom<models::AggregateMeasurement> for AggregateMeasurement {
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct RawMeasurement {
+    pub id: uuid::Uuid,
+    pub peripheral_id: i32,
+    pub kit_id: i32,
+    pub kit_configuration_id: i32,
+    pub quantity_type_id: i32,
+    pub value: f64,
+    pub datetime: DateTime<Utc>,
+}
+
+impl From<models::RawMeasurement> for RawMeasurement {
+    fn from(
+        models::RawMeasurement {
+            id,
+            peripheral_id,
+            kit_id,
+            kit_configuration_id,
+            quantity_type_id,
+            value,
+            datetime,
+        }: models::RawMeasurement,
+    ) -> Self {
+        Self {
+            id,
+            peripheral_id,
+            kit_id,
+            kit_configuration_id,
+            quantity_type_id,
+            value,
+            datetime,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MeasurementStatistics {
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_end: DateTime<Utc>,
+    pub minimum: f64,
+    pub maximum: f64,
+    pub mean: f64,
+    pub count: i64,
+}
+
+impl MeasurementStatistics {
+    pub fn new(
+        models::MeasurementStatistics {
+            datetime_start,
+            minimum,
+            maximum,
+            mean,
+            count,
+        }: models::MeasurementStatistics,
+        bucket: chrono::Duration,
+    ) -> Self {
+        Self {
+            datetime_start,
+            datetime_end: datetime_start + bucket,
+            minimum,
+            maximum,
+            mean,
+            count,
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Media {
@@ -473,3 +700,252 @@ impl TryFrom<models::Media> for Media {
         })
     }
 }
+
+/// Media, with the URL its content can be downloaded from.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaWithContentUrl {
+    #[serde(flatten)]
+    pub media: Media,
+    pub content_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PeripheralMediaUsage {
+    pub peripheral_id: i32,
+    pub bytes: i64,
+    pub count: i64,
+}
+
+impl From<models::MediaUsage> for PeripheralMediaUsage {
+    fn from(
+        models::MediaUsage {
+            peripheral_id,
+            bytes,
+            count,
+        }: models::MediaUsage,
+    ) -> Self {
+        Self {
+            peripheral_id,
+            bytes,
+            count,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct MediaUsage {
+    pub bytes: i64,
+    pub count: i64,
+    pub quota_bytes: Option<i64>,
+    pub peripherals: Vec<PeripheralMediaUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertRule {
+    pub id: i32,
+    pub kit_id: i32,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub condition: String,
+    pub threshold: f64,
+    pub duration_seconds: i32,
+    pub description: Option<String>,
+    pub webhook_url: Option<String>,
+    pub enabled: bool,
+}
+
+impl From<models::AlertRule> for AlertRule {
+    fn from(
+        models::AlertRule {
+            id,
+            kit_id,
+            peripheral_id,
+            quantity_type_id,
+            condition,
+            threshold,
+            duration_seconds,
+            description,
+            webhook_url,
+            enabled,
+        }: models::AlertRule,
+    ) -> Self {
+        Self {
+            id,
+            kit_id,
+            peripheral_id,
+            quantity_type_id,
+            condition,
+            threshold,
+            duration_seconds,
+            description,
+            webhook_url,
+            enabled,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Alert {
+    pub id: i32,
+    pub alert_rule_id: i32,
+    pub kit_id: i32,
+    pub datetime_start: DateTime<Utc>,
+    pub datetime_fired: DateTime<Utc>,
+    pub datetime_resolved: Option<DateTime<Utc>>,
+    pub value: f64,
+}
+
+impl From<models::Alert> for Alert {
+    fn from(
+        models::Alert {
+            id,
+            alert_rule_id,
+            kit_id,
+            datetime_start,
+            datetime_fired,
+            datetime_resolved,
+            value,
+        }: models::Alert,
+    ) -> Self {
+        Self {
+            id,
+            alert_rule_id,
+            kit_id,
+            datetime_start,
+            datetime_fired,
+            datetime_resolved,
+            value,
+        }
+    }
+}
+
+/// An alert that fired or was resolved, with the rule that triggered it. The rule's webhook is
+/// not included, as notifications are delivered to everyone permitted to view the kit.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertNotification {
+    pub kit_serial: String,
+    pub peripheral_id: i32,
+    pub quantity_type_id: i32,
+    pub condition: String,
+    pub threshold: f64,
+    pub description: Option<String>,
+    pub alert: Alert,
+}
+
+impl AlertNotification {
+    pub fn new(kit_serial: String, alert_rule: &models::AlertRule, alert: models::Alert) -> Self {
+        Self {
+            kit_serial,
+            peripheral_id: alert_rule.peripheral_id,
+            quantity_type_id: alert_rule.quantity_type_id,
+            condition: alert_rule.condition.clone(),
+            threshold: alert_rule.threshold,
+            description: alert_rule.description.clone(),
+            alert: Alert::from(alert),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct Webhook {
+    pub id: i32,
+    pub kit_id: i32,
+    pub url: String,
+    pub events: Vec<String>,
+    pub enabled: bool,
+    pub datetime_created: DateTime<Utc>,
+}
+
+impl From<models::Webhook> for Webhook {
+    fn from(
+        models::Webhook {
+            id,
+            kit_id,
+            url,
+            events,
+            enabled,
+            datetime_created,
+            ..
+        }: models::Webhook,
+    ) -> Self {
+        Self {
+            id,
+            kit_id,
+            url,
+            events,
+            enabled,
+            datetime_created,
+        }
+    }
+}
+
+/// A webhook with its signing secret. The secret is only shown when the webhook is created.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookWithSecret {
+    #[serde(flatten)]
+    pub webhook: Webhook,
+    pub secret: String,
+}
+
+impl From<models::Webhook> for WebhookWithSecret {
+    fn from(webhook: models::Webhook) -> Self {
+        let secret = webhook.secret.clone();
+        Self {
+            webhook: Webhook::from(webhook),
+            secret,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct WebhookDelivery {
+    pub id: i32,
+    pub webhook_id: i32,
+    pub event: String,
+    pub payload: serde_json::Value,
+    pub datetime_created: DateTime<Utc>,
+    pub attempts: i32,
+    pub datetime_next_attempt: Option<DateTime<Utc>>,
+    pub datetime_delivered: Option<DateTime<Utc>>,
+    pub last_status_code: Option<i32>,
+    pub last_error: Option<String>,
+}
+
+impl From<models::WebhookDelivery> for WebhookDelivery {
+    fn from(
+        models::WebhookDelivery {
+            id,
+            webhook_id,
+            event,
+            payload,
+            datetime_created,
+            attempts,
+            datetime_next_attempt,
+            datetime_delivered,
+            last_status_code,
+            last_error,
+        }: models::WebhookDelivery,
+    ) -> Self {
+        Self {
+            id,
+            webhook_id,
+            event,
+            payload,
+            datetime_created,
+            attempts,
+            datetime_next_attempt,
+            datetime_delivered,
+            last_status_code,
+            last_error,
+        }
+    }
+}
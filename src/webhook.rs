@@ -0,0 +1,320 @@
+//! Delivers kit events to the webhooks subscribed to them.
+//!
+//! Events are queued as deliveries in the database, and are POSTed by a worker. Deliveries that
+//! fail are retried with exponential backoff. Each request is signed with the webhook's secret:
+//! the `X-AstroPlant-Signature` header holds `sha256=` followed by the hexadecimal HMAC-SHA256 of
+//! the `X-AstroPlant-Timestamp` header's value, a period, and the request body.
+
+use crate::database::PgPool;
+use crate::utils::outbound;
+use crate::{helpers, models};
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::time::Duration;
+
+/// The time between two checks for deliveries that are due.
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// The maximum amount of deliveries attempted at once.
+const BATCH_SIZE: i64 = 32;
+
+/// The time claimed deliveries are not claimed again. Should be well over `outbound::TIMEOUT`.
+const LEASE: Duration = Duration::from_secs(60);
+
+/// The maximum amount of attempts per delivery.
+const MAX_ATTEMPTS: i32 = 8;
+
+/// The time before the first retry. Every next retry waits twice as long.
+const INITIAL_BACKOFF_SECONDS: i64 = 30;
+
+/// The time deliveries are kept in the delivery log.
+const DELIVERY_RETENTION_DAYS: i64 = 30;
+
+/// The time between two prunings of the delivery log.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct Payload<'a, T: Serialize> {
+    event: &'static str,
+    kit_serial: &'a str,
+    datetime: DateTime<Utc>,
+    data: &'a T,
+}
+
+/// The time to wait before the next attempt, after the given amount of failed attempts. `None`
+/// if the delivery should be given up on.
+fn backoff(attempts: i32) -> Option<chrono::Duration> {
+    if attempts >= MAX_ATTEMPTS {
+        None
+    } else {
+        let exponent = (attempts - 1).max(0) as u32;
+        Some(chrono::Duration::seconds(
+            INITIAL_BACKOFF_SECONDS * 2i64.pow(exponent),
+        ))
+    }
+}
+
+/// Queue deliveries of an event of a kit to the webhooks subscribed to it. The event's data is
+/// serialized immediately, and the deliveries are queued on a separate task, such that
+/// publishing does not hold up the caller.
+pub fn publish<T: Serialize>(
+    pg: PgPool,
+    kit_id: models::KitId,
+    kit_serial: String,
+    event: models::WebhookEvent,
+    data: &T,
+) {
+    let now = Utc::now();
+    let payload = serde_json::to_value(Payload {
+        event: event.as_str(),
+        kit_serial: &kit_serial,
+        datetime: now,
+        data,
+    })
+    .unwrap();
+
+    tokio::spawn(queue(pg, kit_id, kit_serial, event, payload, now));
+}
+
+async fn queue(
+    pg: PgPool,
+    kit_id: models::KitId,
+    kit_serial: String,
+    event: models::WebhookEvent,
+    payload: serde_json::Value,
+    now: DateTime<Utc>,
+) {
+    let conn = match pg.get().await {
+        Ok(conn) => conn,
+        Err(_) => {
+            warn!(
+                "could not queue {} webhooks of kit {}: could not get a database connection",
+                event.as_str(),
+                kit_serial
+            );
+            return;
+        }
+    };
+
+    let queued = helpers::threadpool(move || {
+        let deliveries: Vec<_> = models::Webhook::subscribed_to(&conn, kit_id, event)?
+            .into_iter()
+            .map(|webhook| {
+                models::NewWebhookDelivery::new(webhook.get_id(), event, payload.clone(), now)
+            })
+            .collect();
+        if deliveries.is_empty() {
+            return Ok(0);
+        }
+        models::NewWebhookDelivery::create_batch(&conn, &deliveries)
+    })
+    .await;
+
+    match queued {
+        Ok(0) => {}
+        Ok(amount) => trace!(
+            "queued {} {} webhook deliveries of kit {}",
+            amount,
+            event.as_str(),
+            kit_serial
+        ),
+        Err(err) => warn!(
+            "could not queue {} webhooks of kit {}: {:?}",
+            event.as_str(),
+            kit_serial,
+            err
+        ),
+    }
+}
+
+/// The result of an attempt to deliver: the response's status code (if any), and an error
+/// message if the attempt failed.
+async fn attempt(
+    http_client: &outbound::Client,
+    webhook: &models::Webhook,
+    delivery: &models::WebhookDelivery,
+) -> (Option<i32>, Option<String>) {
+    let body = serde_json::to_vec(&delivery.payload).unwrap();
+    let timestamp = Utc::now().timestamp().to_string();
+
+    let mut message = Vec::with_capacity(timestamp.len() + 1 + body.len());
+    message.extend_from_slice(timestamp.as_bytes());
+    message.push(b'.');
+    message.extend_from_slice(&body);
+    let signature = astroplant_auth::signature::sign(webhook.secret.as_bytes(), &message);
+
+    let headers = [
+        ("X-AstroPlant-Event", delivery.event.as_str().to_owned()),
+        ("X-AstroPlant-Delivery", delivery.id.to_string()),
+        ("X-AstroPlant-Timestamp", timestamp),
+        ("X-AstroPlant-Signature", format!("sha256={}", signature)),
+    ];
+
+    match outbound::post_json(http_client, &webhook.url, &headers, body).await {
+        Ok(status) => {
+            let status_code = Some(i32::from(status.as_u16()));
+            if status.is_success() {
+                (status_code, None)
+            } else {
+                (status_code, Some(format!("unexpected status {}", status)))
+            }
+        }
+        Err(err) => (None, Some(err.to_string())),
+    }
+}
+
+async fn deliver(pg: PgPool, http_client: outbound::Client, delivery: models::WebhookDelivery) {
+    let conn = match pg.clone().get().await {
+        Ok(conn) => conn,
+        Err(_) => return,
+    };
+    let webhook_id = models::WebhookId(delivery.webhook_id);
+    let webhook = match helpers::threadpool(move || models::Webhook::by_id(&conn, webhook_id)).await
+    {
+        Ok(Some(webhook)) => webhook,
+        // The webhook was deleted, along with its deliveries.
+        Ok(None) => return,
+        Err(err) => {
+            warn!(
+                "could not deliver webhook delivery {}: {:?}",
+                delivery.id, err
+            );
+            return;
+        }
+    };
+
+    // Deliveries queued before the webhook was disabled are not sent.
+    if !webhook.enabled {
+        let conn = match pg.get().await {
+            Ok(conn) => conn,
+            Err(_) => return,
+        };
+        let delivery_id = delivery.id;
+        if let Err(err) =
+            helpers::threadpool(move || delivery.give_up(&conn, "webhook is disabled".to_owned()))
+                .await
+        {
+            warn!(
+                "could not give up on webhook delivery {}: {:?}",
+                delivery_id, err
+            );
+        }
+        return;
+    }
+
+    let (status_code, error) = attempt(&http_client, &webhook, &delivery).await;
+
+    let now = Utc::now();
+    let (delivered, next_attempt) = match &error {
+        None => (Some(now), None),
+        Some(error) => {
+            let next_attempt = backoff(delivery.attempts + 1).map(|backoff| now + backoff);
+            if next_attempt.is_none() {
+                warn!(
+                    "giving up on webhook delivery {} to {}: {}",
+                    delivery.id, webhook.url, error
+                );
+            }
+            (None, next_attempt)
+        }
+    };
+
+    let conn = match pg.get().await {
+        Ok(conn) => conn,
+        Err(_) => return,
+    };
+    let delivery_id = delivery.id;
+    if let Err(err) = helpers::threadpool(move || {
+        delivery.record_attempt(&conn, delivered, next_attempt, status_code, error)
+    })
+    .await
+    {
+        warn!(
+            "could not record attempt of webhook delivery {}: {:?}",
+            delivery_id, err
+        );
+    }
+}
+
+/// Attempt deliveries that are due.
+pub async fn run_worker(pg: PgPool) {
+    let http_client = outbound::client();
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let lease = chrono::Duration::from_std(LEASE).unwrap();
+
+    loop {
+        interval.tick().await;
+
+        let conn = match pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not deliver webhooks: could not get a database connection");
+                continue;
+            }
+        };
+
+        let now = Utc::now();
+        let due = match helpers::threadpool(move || {
+            models::WebhookDelivery::claim_due(&conn, now, now + lease, BATCH_SIZE)
+        })
+        .await
+        {
+            Ok(due) => due,
+            Err(err) => {
+                warn!("could not deliver webhooks: {:?}", err);
+                continue;
+            }
+        };
+
+        futures::future::join_all(
+            due.into_iter()
+                .map(|delivery| deliver(pg.clone(), http_client.clone(), delivery)),
+        )
+        .await;
+    }
+}
+
+/// Periodically delete deliveries older than the retention window from the delivery log.
+pub async fn run_pruner(pg: PgPool) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+
+    loop {
+        interval.tick().await;
+
+        let before = Utc::now() - chrono::Duration::days(DELIVERY_RETENTION_DAYS);
+        let conn = match pg.clone().get().await {
+            Ok(conn) => conn,
+            Err(_) => {
+                warn!("could not prune webhook deliveries: could not get a database connection");
+                continue;
+            }
+        };
+
+        match helpers::threadpool(move || models::WebhookDelivery::delete_before(&conn, before))
+            .await
+        {
+            Ok(pruned) => debug!(
+                "pruned {} webhook deliveries made before {}",
+                pruned, before
+            ),
+            Err(err) => warn!("could not prune webhook deliveries: {:?}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{backoff, MAX_ATTEMPTS};
+    use chrono::Duration;
+
+    #[test]
+    fn backoff_doubles_until_giving_up() {
+        assert_eq!(backoff(1), Some(Duration::seconds(30)));
+        assert_eq!(backoff(2), Some(Duration::seconds(60)));
+        assert_eq!(backoff(3), Some(Duration::seconds(120)));
+        assert_eq!(backoff(MAX_ATTEMPTS - 1), Some(Duration::seconds(30 * 64)));
+        assert_eq!(backoff(MAX_ATTEMPTS), None);
+    }
+}
@@ -1,31 +1,148 @@
-use log::info;
+use log::{info, warn};
+use std::convert::TryFrom;
 
 use futures::channel::mpsc;
+use futures::future::{BoxFuture, FutureExt};
 use futures::stream::StreamExt;
 
+use astroplant_auth::token;
+use astroplant_websocket::{AuthenticationError, Subscription};
+
+use crate::authentication::AuthenticatedUser;
+use crate::authorization::KitAction;
+use crate::database::PgPool;
+use crate::{helpers, models, views};
+
+/// Events published to WebSocket subscribers.
+#[derive(Debug)]
+pub enum Event {
+    RawMeasurement(astroplant_mqtt::RawMeasurement),
+    AggregateMeasurement {
+        kit_serial: String,
+        aggregate_measurement: models::AggregateMeasurement,
+    },
+    Media {
+        kit_serial: String,
+        media: models::Media,
+    },
+    KitStatus(views::KitStatus),
+    Alert(views::AlertNotification),
+}
+
+/// Authenticate a WebSocket session by an access token or a personal access token.
+pub fn authenticate(
+    access_token: &str,
+) -> Result<astroplant_websocket::AuthenticatedUser, AuthenticationError> {
+    let token_signer: &token::TokenSigner = crate::TOKEN_SIGNER.get().unwrap();
+
+    match token_signer.decode_authorization_token(access_token) {
+        Ok(authentication_state) => Ok(astroplant_websocket::AuthenticatedUser {
+            user_id: authentication_state.user_id,
+            personal_access_token_id: authentication_state.personal_access_token_id,
+        }),
+        Err(token::Error::Expired) => Err(AuthenticationError::Expired),
+        Err(_) => Err(AuthenticationError::Invalid),
+    }
+}
+
+/// Create a function authorizing WebSocket subscriptions. Authorization is run on the current
+/// runtime, as the WebSocket server runs its own executor. Users authenticated with a personal
+/// access token are only authorized for the kit actions in the token's scope.
+pub fn authorizer(
+    pg: PgPool,
+) -> impl Fn(Option<astroplant_websocket::AuthenticatedUser>, Subscription) -> BoxFuture<'static, bool>
+       + Send
+       + Sync
+       + 'static {
+    let runtime = tokio::runtime::Handle::current();
+
+    move |user, subscription| {
+        let (kit_serial, action) = match subscription {
+            Subscription::RawMeasurements { kit_serial } => {
+                (kit_serial, KitAction::SubscribeRealTimeMeasurements)
+            }
+            Subscription::AggregateMeasurements { kit_serial } => (kit_serial, KitAction::View),
+            Subscription::Media { kit_serial } => (kit_serial, KitAction::View),
+            Subscription::KitStatus { kit_serial } => (kit_serial, KitAction::View),
+            Subscription::Alerts { kit_serial } => (kit_serial, KitAction::View),
+        };
+
+        let user = user.map(|user| AuthenticatedUser {
+            user_id: models::UserId(user.user_id),
+            personal_access_token_id: user
+                .personal_access_token_id
+                .map(models::PersonalAccessTokenId),
+        });
+
+        let pg = pg.clone();
+        runtime
+            .spawn(async move {
+                helpers::fut_kit_permission_or_forbidden(pg, user, kit_serial, action)
+                    .await
+                    .is_ok()
+            })
+            .map(|authorized| authorized.unwrap_or(false))
+            .boxed()
+    }
+}
+
+fn publish_raw_measurement(
+    publisher: &mut astroplant_websocket::WebSocketPublisher,
+    raw_measurement: astroplant_mqtt::RawMeasurement,
+) {
+    let astroplant_mqtt::RawMeasurement {
+        kit_serial,
+        datetime,
+        peripheral,
+        quantity_type,
+        value,
+        ..
+    } = raw_measurement;
+    let raw_measurement = astroplant_websocket::RawMeasurement {
+        kit_serial,
+        datetime,
+        peripheral,
+        quantity_type,
+        value,
+    };
+
+    publisher.publish_raw_measurement(raw_measurement.kit_serial.clone(), raw_measurement)
+}
+
 pub async fn run(
     mut publisher: astroplant_websocket::WebSocketPublisher,
-    mut raw_measurement_receiver: mpsc::Receiver<astroplant_mqtt::RawMeasurement>,
+    mut event_receiver: mpsc::Receiver<Event>,
 ) {
     info!("Starting WebSocket server.");
 
-    while let Some(raw_measurement) = raw_measurement_receiver.next().await {
-        let astroplant_mqtt::RawMeasurement {
-            kit_serial,
-            datetime,
-            peripheral,
-            quantity_type,
-            value,
-            ..
-        } = raw_measurement;
-        let raw_measurement = astroplant_websocket::RawMeasurement {
-            kit_serial,
-            datetime,
-            peripheral,
-            quantity_type,
-            value,
-        };
-
-        publisher.publish_raw_measurement(raw_measurement.kit_serial.clone(), raw_measurement)
+    while let Some(event) = event_receiver.next().await {
+        match event {
+            Event::RawMeasurement(raw_measurement) => {
+                publish_raw_measurement(&mut publisher, raw_measurement)
+            }
+            Event::AggregateMeasurement {
+                kit_serial,
+                aggregate_measurement,
+            } => match views::AggregateMeasurement::try_from(aggregate_measurement) {
+                Ok(aggregate_measurement) => {
+                    publisher.publish_aggregate_measurement(kit_serial, &aggregate_measurement)
+                }
+                Err(err) => warn!("could not publish aggregate measurement: {:?}", err),
+            },
+            Event::Media { kit_serial, media } => {
+                let content_url = crate::media::content_uri(media.get_id());
+                match views::Media::try_from(media) {
+                    Ok(media) => publisher.publish_media(
+                        kit_serial,
+                        &views::MediaWithContentUrl { media, content_url },
+                    ),
+                    Err(err) => warn!("could not publish media: {:?}", err),
+                }
+            }
+            Event::KitStatus(kit_status) => {
+                publisher.publish_kit_status(kit_status.kit_serial.clone(), &kit_status)
+            }
+            Event::Alert(alert) => publisher.publish_alert(alert.kit_serial.clone(), &alert),
+        }
     }
 }